@@ -0,0 +1,165 @@
+use shared::{Agent, ShaderConstants};
+use shared::simulation::num_sort_bins;
+use crate::program::*;
+use crate::slot_agents::SlotAgents;
+
+const COUNT_ENTRY_POINT: &str = "sort_count_cs";
+const SCAN_ENTRY_POINT: &str = "sort_scan_cs";
+const SCATTER_ENTRY_POINT: &str = "sort_scatter_cs";
+
+/// Steps per second measured by the benchmark, see `Program::run_benchmark`
+pub struct BenchmarkResult {
+    pub num_agents: usize,
+    pub unsorted_steps_per_second: f32,
+    pub sorted_steps_per_second: f32,
+    pub sort_interval: u32,
+}
+
+/// Reorders the agents buffer by the tile each agent is in, with a counting sort over the bins of [`num_sort_bins`],
+/// so the invocations of a workgroup read and write nearby parts of the trail buffer
+pub struct AgentSorter {
+    count_pipeline: wgpu::ComputePipeline,
+    scan_pipeline: wgpu::ComputePipeline,
+    scatter_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // One count and then offset per bin
+    bins_buffer: wgpu::Buffer,
+    // The place of each agent within its bin
+    ranks_buffer: wgpu::Buffer,
+    // Copied back into the agents buffer when done
+    sorted_buffer: wgpu::Buffer,
+}
+
+impl AgentSorter {
+    pub fn create(program_init: &ProgramInit<'_>) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            count: None,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                has_dynamic_offset: false,
+                min_binding_size: None,
+                ty: wgpu::BufferBindingType::Storage { read_only },
+            },
+        };
+        let bind_group_layout = program_init.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, false),
+                storage_entry(2, false),
+                storage_entry(3, false),
+            ],
+        });
+
+        let pipeline_layout = program_init.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sort pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::COMPUTE,
+                range: 0..std::mem::size_of::<ShaderConstants>() as u32,
+            }],
+        });
+        let create_pipeline = |entry_point| program_init.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            compilation_options: Default::default(),
+            cache: None,
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &program_init.module,
+            entry_point,
+        });
+        let count_pipeline = create_pipeline(COUNT_ENTRY_POINT);
+        let scan_pipeline = create_pipeline(SCAN_ENTRY_POINT);
+        let scatter_pipeline = create_pipeline(SCATTER_ENTRY_POINT);
+
+        // Grown on the first sort
+        let bins_buffer = Self::create_buffer(program_init, 4, "Sort bins buffer");
+        let ranks_buffer = Self::create_buffer(program_init, 4, "Sort ranks buffer");
+        let sorted_buffer = Self::create_buffer(program_init, 4, "Sorted agents buffer");
+        Self {
+            count_pipeline,
+            scan_pipeline,
+            scatter_pipeline,
+            bind_group_layout,
+            bins_buffer,
+            ranks_buffer,
+            sorted_buffer,
+        }
+    }
+
+    fn create_buffer(program_init: &ProgramInit<'_>, size: u64, label: &str) -> wgpu::Buffer {
+        program_init.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// The agents' ids change with their order, so the occupancy buffer is cleared
+    /// and exclusive agents claim their pixels again on their next step, like after a respawn.
+    /// Agents in the same tile end up in whatever order the gpu counts them in,
+    /// which makes runs with sorting not reproducible.
+    pub fn sort(&mut self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, slot_agents: &SlotAgents, push_constants: &ShaderConstants) {
+        let agents_size = slot_agents.init.agents_buffer.size();
+        let num_agents = slot_agents.init.num_agents;
+        if num_agents == 0 {
+            return;
+        }
+        let bins_size = num_sort_bins(program_buffers.map_size) as u64 * std::mem::size_of::<u32>() as u64;
+        if self.bins_buffer.size() != bins_size {
+            self.bins_buffer = Self::create_buffer(program_init, bins_size, "Sort bins buffer");
+        }
+        let ranks_size = (num_agents * std::mem::size_of::<u32>()) as u64;
+        if self.ranks_buffer.size() != ranks_size {
+            self.ranks_buffer = Self::create_buffer(program_init, ranks_size, "Sort ranks buffer");
+        }
+        if self.sorted_buffer.size() != agents_size {
+            self.sorted_buffer = Self::create_buffer(program_init, agents_size, "Sorted agents buffer");
+        }
+        // The agents buffer is replaced on respawn, so the bind group is made fresh for every sort
+        let bind_group = program_init.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sort bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: slot_agents.init.agents_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.bins_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.ranks_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.sorted_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let num_groups = num_agents.div_ceil(256) as u32;
+        let mut encoder =
+            program_init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.clear_buffer(&self.bins_buffer, 0, None);
+        {
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.set_push_constants(0, bytemuck::bytes_of(push_constants));
+            cpass.set_pipeline(&self.count_pipeline);
+            cpass.dispatch_workgroups(num_groups, 1, 1);
+            cpass.set_pipeline(&self.scan_pipeline);
+            cpass.dispatch_workgroups(1, 1, 1);
+            cpass.set_pipeline(&self.scatter_pipeline);
+            cpass.dispatch_workgroups(num_groups, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.sorted_buffer, 0, &slot_agents.init.agents_buffer, 0, (num_agents * std::mem::size_of::<Agent>()) as u64);
+        encoder.clear_buffer(&program_buffers.occupancy_buffer, 0, None);
+        program_init.queue.submit([encoder.finish()]);
+    }
+}
@@ -0,0 +1,269 @@
+use std::sync::mpsc;
+use shared::{AdjustConstants, Agent, SpawnBatch, SpawnConstants};
+use shared::simulation::ADJUST_AGENT_OFFSET;
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+use crate::program::*;
+
+const SPAWN_ENTRY_POINT: &str = "spawn_cs";
+const ADJUST_ENTRY_POINT: &str = "adjust_cs";
+
+/// The agents of a running simulation, as counted by [`AgentSpawner::request_count`]
+pub struct AgentCount {
+    pub inactive_slots: usize,
+    // One entry per agent type
+    pub agent_counts: Vec<usize>,
+}
+
+struct PendingCount {
+    num_agent_types: usize,
+    // Receives whether mapping the staging buffer succeeded
+    mapped: mpsc::Receiver<bool>,
+    // Set on respawn, the count belongs to the previous agents
+    discarded: bool,
+}
+
+/// Spawns the agents with `spawn_cs` straight into a new agents buffer, so nothing is built or uploaded on the cpu,
+/// and adds or retires agents of a running simulation with `adjust_cs`
+pub struct AgentSpawner {
+    pipeline: wgpu::ComputePipeline,
+    adjust_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // The inactive slots and agents of the retired type seen by adjust_cs, followed by the agents per counted type.
+    // Grown as needed
+    counters_buffer: wgpu::Buffer,
+    counters_staging_buffer: wgpu::Buffer,
+    pending_count: Option<PendingCount>,
+}
+
+impl AgentSpawner {
+    pub fn create(program_init: &ProgramInit<'_>) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            count: None,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                has_dynamic_offset: false,
+                min_binding_size: None,
+                ty: wgpu::BufferBindingType::Storage { read_only },
+            },
+        };
+        let bind_group_layout = program_init.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                storage_entry(0, false),
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, false),
+                storage_entry(4, true),
+            ],
+        });
+
+        let pipeline_layout = program_init.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Spawn pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::COMPUTE,
+                range: 0..std::mem::size_of::<AdjustConstants>() as u32,
+            }],
+        });
+        let create_pipeline = |entry_point| program_init.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            compilation_options: Default::default(),
+            cache: None,
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &program_init.module,
+            entry_point,
+        });
+        let pipeline = create_pipeline(SPAWN_ENTRY_POINT);
+        let adjust_pipeline = create_pipeline(ADJUST_ENTRY_POINT);
+        let (counters_buffer, counters_staging_buffer) = Self::create_counters_buffers(program_init, ADJUST_AGENT_OFFSET);
+        Self {
+            pipeline,
+            adjust_pipeline,
+            bind_group_layout,
+            counters_buffer,
+            counters_staging_buffer,
+            pending_count: None,
+        }
+    }
+
+    fn create_counters_buffers(program_init: &ProgramInit<'_>, len: usize) -> (wgpu::Buffer, wgpu::Buffer) {
+        let size = (len * std::mem::size_of::<u32>()) as u64;
+        let counters_buffer = program_init.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Adjust counters buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let counters_staging_buffer = program_init.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Adjust counters staging buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (counters_buffer, counters_staging_buffer)
+    }
+
+    /// Returns a new agents buffer with `constants.num_agents` agents, spawned from the batches and the agent stats.
+    /// `density` is the running total of the density image, see [`crate::density_image::DensityImage::cumulative`].
+    pub fn spawn(&self, program_init: &ProgramInit<'_>, agent_stats_buffer: &wgpu::Buffer, spawn_batches: &[SpawnBatch], density: &[u32], constants: &SpawnConstants) -> wgpu::Buffer {
+        let agents_buffer = program_init.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Agent buffer"),
+            size: (constants.num_agents as usize * std::mem::size_of::<Agent>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.create_bind_group(program_init, &agents_buffer, agent_stats_buffer, spawn_batches, density);
+
+        let mut encoder =
+            program_init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_push_constants(0, bytemuck::bytes_of(constants));
+            cpass.dispatch_workgroups((constants.num_agents as usize).div_ceil(256) as u32, 1, 1);
+        }
+        program_init.queue.submit([encoder.finish()]);
+        agents_buffer
+    }
+
+    /// Spawns `constants.spawn.num_agents` agents from the batches into inactive slots of the agents buffer,
+    /// as far as there are enough of them, and retires `constants.num_retired` agents of `constants.retired_type`.
+    /// Retired agents don't free their pixels, so after retiring the occupancy buffer is cleared, like after sorting.
+    pub fn adjust(&self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, agents_buffer: &wgpu::Buffer, num_agents: usize, agent_stats_buffer: &wgpu::Buffer, spawn_batches: &[SpawnBatch], density: &[u32], constants: &AdjustConstants) {
+        let bind_group = self.create_bind_group(program_init, agents_buffer, agent_stats_buffer, spawn_batches, density);
+        let mut encoder =
+            program_init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.clear_buffer(&self.counters_buffer, 0, None);
+        {
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.set_pipeline(&self.adjust_pipeline);
+            cpass.set_push_constants(0, bytemuck::bytes_of(constants));
+            cpass.dispatch_workgroups(num_agents.div_ceil(256) as u32, 1, 1);
+        }
+        if constants.num_retired > 0 {
+            encoder.clear_buffer(&program_buffers.occupancy_buffer, 0, None);
+        }
+        program_init.queue.submit([encoder.finish()]);
+    }
+
+    /// Counts the inactive slots and the agents per type with an `adjust_cs` dispatch that spawns and retires nothing,
+    /// unless the previous count is still being read back. See [`Self::poll_count`] for the result.
+    pub fn request_count(&mut self, program_init: &ProgramInit<'_>, agents_buffer: &wgpu::Buffer, num_agents: usize, agent_stats_buffer: &wgpu::Buffer, num_agent_types: usize) {
+        if self.pending_count.is_some() {
+            return;
+        }
+        let len = ADJUST_AGENT_OFFSET + num_agent_types;
+        let size = (len * std::mem::size_of::<u32>()) as u64;
+        if self.counters_buffer.size() < size {
+            (self.counters_buffer, self.counters_staging_buffer) = Self::create_counters_buffers(program_init, len);
+        }
+        let bind_group = self.create_bind_group(program_init, agents_buffer, agent_stats_buffer, &[], &[]);
+        let constants = AdjustConstants {
+            num_counted_types: num_agent_types as u32,
+            ..AdjustConstants::zeroed()
+        };
+        let mut encoder =
+            program_init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.clear_buffer(&self.counters_buffer, 0, None);
+        {
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.set_pipeline(&self.adjust_pipeline);
+            cpass.set_push_constants(0, bytemuck::bytes_of(&constants));
+            cpass.dispatch_workgroups(num_agents.div_ceil(256) as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.counters_buffer, 0, &self.counters_staging_buffer, 0, size);
+        program_init.queue.submit([encoder.finish()]);
+
+        let (sender, mapped) = mpsc::channel();
+        self.counters_staging_buffer.slice(..size).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result.is_ok());
+        });
+        self.pending_count = Some(PendingCount {
+            num_agent_types,
+            mapped,
+            discarded: false,
+        });
+    }
+
+    /// Returns the requested count once the gpu is done with it, like `StatisticsReader::poll`
+    pub fn poll_count(&mut self, program_init: &ProgramInit<'_>) -> Option<AgentCount> {
+        let pending = self.pending_count.as_ref()?;
+        program_init.device.poll(wgpu::Maintain::Poll);
+        let mapped = match pending.mapped.try_recv() {
+            Ok(mapped) => mapped,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => false,
+        };
+        let pending = self.pending_count.take()?;
+        if !mapped {
+            return None;
+        }
+        let size = ((ADJUST_AGENT_OFFSET + pending.num_agent_types) * std::mem::size_of::<u32>()) as u64;
+        let counters: Vec<u32> = bytemuck::cast_slice(&self.counters_staging_buffer.slice(..size).get_mapped_range()).to_vec();
+        self.counters_staging_buffer.unmap();
+        if pending.discarded {
+            return None;
+        }
+        Some(AgentCount {
+            inactive_slots: counters[0] as usize,
+            agent_counts: counters[ADJUST_AGENT_OFFSET..].iter().map(|&count| count as usize).collect(),
+        })
+    }
+
+    /// Drops the count in flight, it belongs to the previous agents
+    pub fn discard_count(&mut self) {
+        if let Some(pending) = &mut self.pending_count {
+            pending.discarded = true;
+        }
+    }
+
+    fn create_bind_group(&self, program_init: &ProgramInit<'_>, agents_buffer: &wgpu::Buffer, agent_stats_buffer: &wgpu::Buffer, spawn_batches: &[SpawnBatch], density: &[u32]) -> wgpu::BindGroup {
+        // Bindings can't be empty, so without batches this is a single unused one
+        let spawn_batches = if spawn_batches.is_empty() { &[SpawnBatch::zeroed()][..] } else { spawn_batches };
+        let batch_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Spawn batch buffer"),
+            contents: bytemuck::cast_slice(spawn_batches),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Likewise without a density image, the spawn constants then have an empty density size
+        let density = if density.is_empty() { &[0][..] } else { density };
+        let density_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Spawn density buffer"),
+            contents: bytemuck::cast_slice(density),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        program_init.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Spawn bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: agents_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: agent_stats_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: batch_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.counters_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: density_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
@@ -1,49 +1,213 @@
 use serde::{Deserialize, Serialize};
 use shared::*;
+use crate::pixel_mask::PixelMask;
+use crate::emitters::Emitter;
+use crate::flow_field::FlowField;
+use crate::density_image::DensityImage;
 
 pub const DEFAULT_WIDTH: u32 = 1280;
 pub const DEFAULT_HEIGHT: u32 = 720;
 pub const DEFAULT_MAP_WIDTH: u32 = DEFAULT_WIDTH;
 pub const DEFAULT_MAP_HEIGHT: u32 = DEFAULT_HEIGHT;
 pub const DEFAULT_DISTANCE: u32 = 200;
+pub const DEFAULT_EMITTER_RADIUS: u32 = 20;
 pub const RESIZE_MAP_WITH_WINDOW: bool = false;
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConfigurationValues {
     pub globals: Globals,
-    pub agent_stats: [AgentStatsAll; NUM_AGENT_TYPES],
-    pub trail_stats: [TrailStats; NUM_TRAIL_STATS],
+    // The number of agent types and trails is up to the preset, the trails are limited to MAX_TRAILS
+    // and only as many of them as the trail format has channels are simulated
+    pub agent_stats: Vec<AgentStatsAll>,
+    pub trail_stats: Vec<TrailStatsAll>,
+    pub obstacles: PixelMask,
+    pub emitters: Vec<Emitter>,
+    pub reactions: Vec<Reaction>,
+    pub flow_field: FlowField,
+    // Used by SpawnMode::FromImage on the next respawn
+    pub spawn_image: DensityImage,
     pub shader_config_changed: bool,
     // CPU only fields
     pub scale_factor: f32,
     pub show_menu: bool,
     pub respawn: bool,
     pub reset_trails: bool,
+    // Write `obstacles` to the gpu
+    pub upload_obstacles: bool,
+    // Read the obstacles painted on the gpu back into `obstacles`
+    pub download_obstacles: bool,
+    // Write `emitters` to the gpu
+    pub upload_emitters: bool,
+    // Write `flow_field` to the gpu
+    pub upload_flow_field: bool,
     pub quit: bool,
     pub playing: bool,
+    // Compute steps to run next frame, also while paused
+    pub advance_steps: u32,
+    // Time compute steps with and without sorting the agents
+    pub run_benchmark: bool,
+}
+
+impl ConfigurationValues {
+    pub fn add_agent_type(&mut self) {
+        let name = format!("Type {}", self.agent_stats.len());
+        self.agent_stats.push(AgentStatsAll {
+            name,
+            spawn_mode: vec![SpawnMode::EvenlyDistributed],
+            num_agents: 0,
+            shader_stats: AgentStats {
+                velocity: 40.0,
+                turn_speed: 80.0,
+                turn_speed_avoidance: 30.0,
+                avoidance_threshold: 20.0,
+                sensor_angle_spacing: 60.0,
+                sensor_offset: 20.0,
+                ..Default::default()
+            },
+            interactions: vec![TrailInteraction::default(); self.trail_stats.len()],
+        });
+    }
+
+    // Conversions into the removed type are turned off, and the agents are respawned since the types shift
+    pub fn remove_agent_type(&mut self, agent_type: usize) {
+        self.agent_stats.remove(agent_type);
+        let agent_type = agent_type as u32;
+        for stats_all in self.agent_stats.iter_mut() {
+            let shader_stats = &mut stats_all.shader_stats;
+            if shader_stats.timeout_conversion == agent_type {
+                shader_stats.timeout = 0.0;
+                shader_stats.timeout_conversion = 0;
+            } else if shader_stats.timeout_conversion > agent_type {
+                shader_stats.timeout_conversion -= 1;
+            }
+            for interaction in stats_all.interactions.iter_mut() {
+                if interaction.conversion == agent_type {
+                    interaction.conversion_enabled = 0;
+                    interaction.conversion = 0;
+                } else if interaction.conversion > agent_type {
+                    interaction.conversion -= 1;
+                }
+            }
+        }
+        self.respawn = true;
+    }
+
+    /// Trails past the channels of the trail format are ignored
+    pub fn num_trails(&self) -> usize {
+        self.trail_stats.len().min(self.globals.trail_format.num_channels() as usize)
+    }
+
+    pub fn add_trail(&mut self) {
+        if self.trail_stats.len() >= MAX_TRAILS {
+            return;
+        }
+        let name = format!("Trail {}", self.trail_stats.len());
+        self.trail_stats.push(TrailStatsAll {
+            name,
+            shader_stats: TrailStats {
+                evaporation_speed: 50.0,
+                diffusion_speed: 480.0,
+                feed_rate: 0.0,
+                color_mode: ColorMode::Add.encode(),
+                color: Color::new(1.0, 1.0, 1.0, 1.0),
+                kernel: DiffusionKernel::BOX_3X3,
+            },
+        });
+        for stats_all in self.agent_stats.iter_mut() {
+            stats_all.interactions.resize(self.trail_stats.len(), TrailInteraction::default());
+        }
+    }
+
+    // Emitters and reactions using the removed trail go with it, and the trails are reset since the channels shift
+    pub fn remove_trail(&mut self, trail_index: usize) {
+        self.trail_stats.remove(trail_index);
+        for stats_all in self.agent_stats.iter_mut() {
+            if trail_index < stats_all.interactions.len() {
+                stats_all.interactions.remove(trail_index);
+            }
+        }
+        let trail_index = trail_index as u32;
+        let shift = |channel: u32| if channel != NO_CHANNEL && channel > trail_index { channel - 1 } else { channel };
+        self.reactions.retain(|reaction| reaction.source != trail_index);
+        for reaction in self.reactions.iter_mut() {
+            if reaction.catalyst == trail_index {
+                reaction.catalyst = NO_CHANNEL;
+            }
+            if reaction.target == trail_index {
+                reaction.target = NO_CHANNEL;
+            }
+            reaction.source = shift(reaction.source);
+            reaction.catalyst = shift(reaction.catalyst);
+            reaction.target = shift(reaction.target);
+        }
+        self.emitters.retain(|emitter| emitter.trail_index != trail_index);
+        for emitter in self.emitters.iter_mut() {
+            emitter.trail_index = shift(emitter.trail_index);
+        }
+        self.upload_emitters = true;
+        self.reset_trails = true;
+    }
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Globals {
     pub time_step: f32,
     pub max_frame_rate: f32,
-    pub smoothen_after_max_frame_rate: bool,
+    // At most this many compute steps per rendered frame, beyond that the simulation slows down
     pub compute_steps_per_render: u32,
+    // Simulated seconds per real second
+    pub simulation_speed: f32,
+    // Compute steps between statistics samples, 0 turns them off
+    pub statistics_interval: u32,
+    // Also sum every trail over the map for the statistics, which touches every pixel
+    pub statistics_trail_totals: bool,
+    // Compute steps between sorting the agents by position, 0 turns sorting off.
+    // Sorting speeds up large numbers of agents, but makes runs no longer reproducible.
+    pub sort_interval: u32,
     pub click_mode: ClickMode,
     pub brush_size: f32,
     pub background_color: Color,
+    pub obstacle_color: Color,
     pub map_width: u32,
     pub map_height: u32,
+    pub boundary_mode: BoundaryMode,
+    pub deposition_mode: DepositionMode,
+    // Precision of the trails, changing it clears them
+    pub trail_format: TrailFormat,
+    // Seeds spawning and the shaders, so a preset and a seed reproduce a run
+    pub seed: u32,
+    // Inactive agents added at spawn, which reproducing agents can be born into
+    pub spare_agent_slots: u32,
+    // Spawn the agents with a compute shader instead of on the cpu, which stalls for seconds with millions of agents.
+    // Both follow the same distributions, but don't spawn the same agents for a seed.
+    pub gpu_spawning: bool,
+    // Rate of emitters placed with ClickMode::PlaceEmitter, negative for sinks
+    pub emitter_rate: f32,
+    pub flow_mode: FlowMode,
+    // Speed of the flow in pixels per second
+    pub flow_strength: f32,
+    // Direction of FlowMode::Uniform in degrees
+    pub flow_direction: f32,
+    // Center of FlowMode::Vortex as a fraction of the map size
+    pub flow_center_x: f32,
+    pub flow_center_y: f32,
+    pub flow_noise_scale: f32,
+    // How much of the flow is added to the agents' movement
+    pub flow_agent_drift: f32,
 }
 
 pub const GLOBALS: Globals = Globals {
     time_step: 1.0 / 60.0,
     max_frame_rate: 100.0,
-    smoothen_after_max_frame_rate: false,
-    compute_steps_per_render: 1,
+    compute_steps_per_render: 16,
+    simulation_speed: 1.0,
+    statistics_interval: 30,
+    statistics_trail_totals: false,
+    sort_interval: 0,
     click_mode: ClickMode::PaintTrail(0),
     brush_size: 7.0,
     background_color: Color::new(0.0048377407, 0.014973952, 0.040314503, 1.0),
+    obstacle_color: Color::new(0.35, 0.35, 0.35, 1.0),
     map_width: if RESIZE_MAP_WITH_WINDOW {
         DEFAULT_WIDTH
     } else {
@@ -54,6 +218,20 @@ pub const GLOBALS: Globals = Globals {
     } else {
         DEFAULT_MAP_HEIGHT
     },
+    boundary_mode: BoundaryMode::Clamp,
+    deposition_mode: DepositionMode::Fast,
+    trail_format: TrailFormat::Fixed16,
+    seed: 0,
+    spare_agent_slots: 0,
+    gpu_spawning: true,
+    emitter_rate: 1.0,
+    flow_mode: FlowMode::None,
+    flow_strength: 20.0,
+    flow_direction: 0.0,
+    flow_center_x: 0.5,
+    flow_center_y: 0.5,
+    flow_noise_scale: 0.01,
+    flow_agent_drift: 0.0,
 };
 
 #[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
@@ -63,10 +241,19 @@ pub struct AgentStatsAll {
     pub spawn_mode: Vec<SpawnMode>,
     pub num_agents: usize,
     pub shader_stats: AgentStats,
+    // One per trail, how agents of this type react to and add to it
+    pub interactions: Vec<TrailInteraction>,
 }
 
-pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
-    [
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Clone, PartialEq)]
+pub struct TrailStatsAll {
+    pub name: String,
+    pub shader_stats: TrailStats,
+}
+
+pub fn create_agent_stats_all() -> Vec<AgentStatsAll> {
+    vec![
         AgentStatsAll {
             name: "Blue".to_string(),
             // spawn_mode: SpawnMode::CircumferenceFacingClockwise { distance: 170 },
@@ -87,35 +274,35 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 avoidance_threshold: 20.0,
                 sensor_angle_spacing: 60.0,
                 sensor_offset: 30.0,
-                interaction_channels: [
-                    TrailInteraction {
-                        conversion_enabled: 1,
-                        conversion_threshold: 0.8,
-                        conversion: 2,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 1.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        addition: 1.0 / 5.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 1.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                ],
                 ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    conversion_enabled: 1,
+                    conversion_threshold: 0.8,
+                    conversion: 2,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 1.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    addition: 1.0 / 5.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 1.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+            ],
             ..Default::default()
         },
         AgentStatsAll {
@@ -129,35 +316,35 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 avoidance_threshold: 20.0,
                 sensor_angle_spacing: 60.0,
                 sensor_offset: 25.0,
-                interaction_channels: [
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 1.0,
-                        addition: 1.0 / 5.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: -1.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        conversion_enabled: 1,
-                        conversion_threshold: 0.8,
-                        conversion: 3,
-                        ..Default::default()
-                    },
-                ],
                 ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 1.0,
+                    addition: 1.0 / 5.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: -1.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    conversion_enabled: 1,
+                    conversion_threshold: 0.8,
+                    conversion: 3,
+                    ..Default::default()
+                },
+            ],
             ..Default::default()
         },
         AgentStatsAll {
@@ -171,33 +358,34 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 sensor_offset: 12.0,
                 timeout: 1.5,
                 timeout_conversion: 4,
-                interaction_channels: [
-                    TrailInteraction {
-                        addition: 1.0 / 5.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 1.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        conversion_enabled: 1,
-                        conversion_threshold: 0.8,
-                        conversion: 6,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                ],
+                ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    addition: 1.0 / 5.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 1.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    conversion_enabled: 1,
+                    conversion_threshold: 0.8,
+                    conversion: 6,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+            ],
             ..Default::default()
         },
         AgentStatsAll {
@@ -211,34 +399,35 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 sensor_offset: 12.0,
                 timeout: 6.0,
                 timeout_conversion: 5,
-                interaction_channels: [
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 1.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        conversion_enabled: 1,
-                        conversion_threshold: 0.8,
-                        conversion: 7,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        addition: 1.0 / 5.0,
-                        ..Default::default()
-                    },
-                ],
+                ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 1.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    conversion_enabled: 1,
+                    conversion_threshold: 0.8,
+                    conversion: 7,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    addition: 1.0 / 5.0,
+                    ..Default::default()
+                },
+            ],
             ..Default::default()
         },
         AgentStatsAll {
@@ -250,36 +439,36 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 avoidance_threshold: 20.0,
                 sensor_angle_spacing: 60.0,
                 sensor_offset: 40.0,
-                interaction_channels: [
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        conversion_enabled: 1,
-                        conversion_threshold: 0.8,
-                        conversion: 0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        addition: 1.0 / 5.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        conversion_enabled: 1,
-                        conversion_threshold: 0.8,
-                        conversion: 0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                ],
                 ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    conversion_enabled: 1,
+                    conversion_threshold: 0.8,
+                    conversion: 0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    addition: 1.0 / 5.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    conversion_enabled: 1,
+                    conversion_threshold: 0.8,
+                    conversion: 0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+            ],
             ..Default::default()
         },
         AgentStatsAll {
@@ -291,36 +480,36 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 avoidance_threshold: 20.0,
                 sensor_angle_spacing: 60.0,
                 sensor_offset: 40.0,
-                interaction_channels: [
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        conversion_enabled: 1,
-                        conversion_threshold: 0.8,
-                        conversion: 1,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        addition: 1.0 / 5.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        conversion_enabled: 1,
-                        conversion_threshold: 0.8,
-                        conversion: 1,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                ],
                 ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    conversion_enabled: 1,
+                    conversion_threshold: 0.8,
+                    conversion: 1,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    addition: 1.0 / 5.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    conversion_enabled: 1,
+                    conversion_threshold: 0.8,
+                    conversion: 1,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+            ],
             ..Default::default()
         },
         AgentStatsAll {
@@ -350,30 +539,30 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 avoidance_threshold: 20.0,
                 sensor_angle_spacing: 60.0,
                 sensor_offset: 15.0,
-                interaction_channels: [
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 1.0,
-                        addition: 0.2,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                ],
                 ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 1.0,
+                    addition: 0.2,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+            ],
         },
         AgentStatsAll {
             name: "WhiteTemporaryBlue".to_string(),
@@ -386,30 +575,31 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 sensor_offset: 15.0,
                 timeout: 20.0,
                 timeout_conversion: 0,
-                interaction_channels: [
-                    TrailInteraction {
-                        attraction: 1.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        addition: 0.2,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                ],
+                ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    attraction: 1.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    addition: 0.2,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+            ],
             ..Default::default()
         },
         AgentStatsAll {
@@ -423,30 +613,31 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 sensor_offset: 15.0,
                 timeout: 20.0,
                 timeout_conversion: 1,
-                interaction_channels: [
-                    TrailInteraction {
-                        attraction: 1.0,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 0.2,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        addition: 0.2,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                ],
+                ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    attraction: 1.0,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 0.2,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    addition: 0.2,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+            ],
             ..Default::default()
         },
         AgentStatsAll {
@@ -464,80 +655,103 @@ pub fn create_agent_stats_all() -> [AgentStatsAll; NUM_AGENT_TYPES] {
                 avoidance_threshold: 20.0,
                 sensor_angle_spacing: 60.0,
                 sensor_offset: 15.0,
-                interaction_channels: [
-                    TrailInteraction {
-                        attraction: 1.0,
-                        addition: 0.2,
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        ..Default::default()
-                    },
-                    TrailInteraction {
-                        attraction: 1.0,
-                        addition: 0.2,
-                        ..Default::default()
-                    },
-                ],
                 ..Default::default()
             },
+            interactions: vec![
+                TrailInteraction {
+                    attraction: 1.0,
+                    addition: 0.2,
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    ..Default::default()
+                },
+                TrailInteraction {
+                    attraction: 1.0,
+                    addition: 0.2,
+                    ..Default::default()
+                },
+            ],
         },
     ]
 }
 
-pub const TRAIL_NAMES: [&'static str; NUM_TRAIL_STATS] =
-    ["BlueInfection", "Green", "Blue", "Dead", "Cure", "GreenInfection"];
-
-pub const TRAIL_STATS: [TrailStats; NUM_TRAIL_STATS] = [
-    TrailStats {
-        evaporation_speed: 50.0,
-        diffusion_speed: 480.0,
-        padding_1: 0.0,
-        color_mode: ColorMode::Add.encode(),
-        color: Color::new(200.0/255.0, 0.0, 106.0/255.0, 1.0),
-    },
-    TrailStats {
-        evaporation_speed: 50.0,
-        diffusion_speed: 480.0,
-        padding_1: 0.0,
-        color_mode: ColorMode::Add.encode(),
-        color: Color::new(0.0, 1.0, 0.0, 1.0),
-    },
-    TrailStats {
-        evaporation_speed: 50.0,
-        diffusion_speed: 480.0,
-        padding_1: 0.0,
-        color_mode: ColorMode::Add.encode(),
-        color: Color::new(0.0, 0.0, 1.0, 1.0),
-    },
-    TrailStats {
-        evaporation_speed: 50.0,
-        diffusion_speed: 480.0,
-        padding_1: 0.0,
-        color_mode: ColorMode::Add.encode(),
-        color: Color::new(0.05, 0.05, 0.05, 1.0),
-    },
-    TrailStats {
-        evaporation_speed: 50.0,
-        diffusion_speed: 480.0,
-        padding_1: 0.0,
-        color_mode: ColorMode::Add.encode(),
-        color: Color::new(1.0, 248.0 / 255.0, 0.00, 1.0),
-    },
-    TrailStats {
-        evaporation_speed: 50.0,
-        diffusion_speed: 480.0,
-        padding_1: 0.0,
-        color_mode: ColorMode::Add.encode(),
-        color: Color::new(1.00, 120.0/255.0, 72.0/255.0, 1.0),
-    },
-];
+pub fn create_trail_stats_all() -> Vec<TrailStatsAll> {
+    vec![
+        TrailStatsAll {
+            name: "BlueInfection".to_string(),
+            shader_stats: TrailStats {
+                evaporation_speed: 50.0,
+                diffusion_speed: 480.0,
+                feed_rate: 0.0,
+                color_mode: ColorMode::Add.encode(),
+                color: Color::new(200.0/255.0, 0.0, 106.0/255.0, 1.0),
+                kernel: DiffusionKernel::BOX_3X3,
+            },
+        },
+        TrailStatsAll {
+            name: "Green".to_string(),
+            shader_stats: TrailStats {
+                evaporation_speed: 50.0,
+                diffusion_speed: 480.0,
+                feed_rate: 0.0,
+                color_mode: ColorMode::Add.encode(),
+                color: Color::new(0.0, 1.0, 0.0, 1.0),
+                kernel: DiffusionKernel::BOX_3X3,
+            },
+        },
+        TrailStatsAll {
+            name: "Blue".to_string(),
+            shader_stats: TrailStats {
+                evaporation_speed: 50.0,
+                diffusion_speed: 480.0,
+                feed_rate: 0.0,
+                color_mode: ColorMode::Add.encode(),
+                color: Color::new(0.0, 0.0, 1.0, 1.0),
+                kernel: DiffusionKernel::BOX_3X3,
+            },
+        },
+        TrailStatsAll {
+            name: "Dead".to_string(),
+            shader_stats: TrailStats {
+                evaporation_speed: 50.0,
+                diffusion_speed: 480.0,
+                feed_rate: 0.0,
+                color_mode: ColorMode::Add.encode(),
+                color: Color::new(0.05, 0.05, 0.05, 1.0),
+                kernel: DiffusionKernel::BOX_3X3,
+            },
+        },
+        TrailStatsAll {
+            name: "Cure".to_string(),
+            shader_stats: TrailStats {
+                evaporation_speed: 50.0,
+                diffusion_speed: 480.0,
+                feed_rate: 0.0,
+                color_mode: ColorMode::Add.encode(),
+                color: Color::new(1.0, 248.0 / 255.0, 0.00, 1.0),
+                kernel: DiffusionKernel::BOX_3X3,
+            },
+        },
+        TrailStatsAll {
+            name: "GreenInfection".to_string(),
+            shader_stats: TrailStats {
+                evaporation_speed: 50.0,
+                diffusion_speed: 480.0,
+                feed_rate: 0.0,
+                color_mode: ColorMode::Add.encode(),
+                color: Color::new(1.00, 120.0/255.0, 72.0/255.0, 1.0),
+                kernel: DiffusionKernel::BOX_3X3,
+            },
+        },
+    ]
+}
@@ -1,20 +1,26 @@
-#[cfg(feature = "save-preset")]
+#[cfg(any(feature = "save-preset", feature = "load-image"))]
 use std::path::PathBuf;
 use crate::configuration::ConfigurationValues;
+use crate::pixel_mask::PixelMask;
+use crate::emitters::{Emitter, EmitterShape};
+#[cfg(feature = "load-image")]
+use crate::slot_egui::ImageTarget;
 use winit::dpi::PhysicalSize;
-use crate::configuration::TRAIL_NAMES;
 use egui::{Slider, Ui};
 use egui::ComboBox;
 use egui_winit::State;
 use crate::configuration::DEFAULT_DISTANCE;
-use shared::{ClickMode, ColorMode, NUM_AGENT_TYPES, NUM_TRAIL_STATS, SpawnBox, SpawnMode};
+use crate::configuration::DEFAULT_EMITTER_RADIUS;
+use shared::{BoundaryMode, ClickMode, ColorMode, DepositionMode, EdgeBehavior, FlowMode, KernelShape, MAX_KERNEL_RADIUS, MAX_REACTIONS, MAX_SENSORS, MAX_TRAILS, NO_CHANNEL, Reaction, SpawnBox, SpawnHeading, SpawnMode, SteeringModel, TrailFormat, TrailInteraction};
+use shared::simulation::sensor_placement;
+use shared::pixel_view::MAX_INTS_PER_PIXEL;
 use crate::slot_egui::LocalState;
 
 pub fn render_configuration_menu(
     state: &State,
     screen_size: PhysicalSize<u32>,
     configuration: &mut ConfigurationValues,
-    #[allow(unused_variables)] local_state: &mut LocalState,
+    local_state: &mut LocalState,
 ) {
     configuration.shader_config_changed = false;
     if configuration.show_menu {
@@ -30,7 +36,22 @@ pub fn render_configuration_menu(
                     if ui.button(if configuration.playing { "Pause" } else { "Resume" }).clicked() {
                         configuration.playing = !configuration.playing;
                     }
+                    if ui.button("Step").clicked() {
+                        configuration.advance_steps += 1;
+                    }
                 });
+                ui.horizontal(|ui| {
+                    if ui.button("Advance").clicked() {
+                        configuration.advance_steps += local_state.advance_step_count;
+                    }
+                    ui.add(egui::DragValue::new(&mut local_state.advance_step_count).range(1..=10000));
+                    ui.label("steps");
+                });
+                ui.add(Slider::new(&mut configuration.globals.simulation_speed, 0.1..=10.0)
+                    .logarithmic(true)
+                    .text("Simulation speed"));
+                ui.add(Slider::new(&mut configuration.globals.compute_steps_per_render, 1..=64)
+                    .text("Max steps per frame"));
                 ui.horizontal(|ui| {
                     if ui.button("Respawn").clicked() {
                         configuration.respawn = true;
@@ -48,8 +69,18 @@ pub fn render_configuration_menu(
                         configuration.quit = true;
                     }
                 });
-                for agent_stats in configuration.agent_stats.iter_mut() {
+                ui.checkbox(&mut local_state.show_statistics, "Show statistics");
+                let trail_names = configuration.trail_stats.iter().map(|trail| trail.name.clone()).collect::<Vec<_>>();
+                let num_agent_types = configuration.agent_stats.len() as u32;
+                let num_trails = trail_names.len() as u32;
+                let spawn_image_size = configuration.spawn_image.size();
+                let mut removed_agent_type = None;
+                for (agent_type, agent_stats) in configuration.agent_stats.iter_mut().enumerate() {
                     ui.collapsing(format!("Agent {}", agent_stats.name), |ui| {
+                        if num_agent_types > 1 && ui.button("Remove agent type (respawns)").clicked() {
+                            removed_agent_type = Some(agent_type);
+                        }
+                        agent_stats.interactions.resize(trail_names.len(), TrailInteraction::default());
                         ui.add(Slider::new(&mut agent_stats.shader_stats.velocity, 5.0..=100.0)
                             .text("Velocity"));
                         ui.add(Slider::new(&mut agent_stats.shader_stats.turn_speed, 5.0..=100.0)
@@ -62,36 +93,120 @@ pub fn render_configuration_menu(
                             .text("Sensor angle spacing (degrees)"));
                         ui.add(Slider::new(&mut agent_stats.shader_stats.sensor_offset, 3.0..=30.0)
                             .text("Sensor offset"));
+                        ui.add(Slider::new(&mut agent_stats.shader_stats.sensor_count, 1..=MAX_SENSORS as u32)
+                            .text("Sensor count"));
+                        ui.add(Slider::new(&mut agent_stats.shader_stats.sensor_kernel_radius, 0..=5)
+                            .text("Sensor kernel radius"));
+                        let mut bilinear_sensing = agent_stats.shader_stats.bilinear_sensing != 0;
+                        ui.checkbox(&mut bilinear_sensing, "Bilinear sensing");
+                        agent_stats.shader_stats.bilinear_sensing = if bilinear_sensing { 1 } else { 0 };
+                        let mut custom_sensors = agent_stats.shader_stats.custom_sensors != 0;
+                        if ui.checkbox(&mut custom_sensors, "Custom sensor placement").changed() && custom_sensors {
+                            // Start from the evenly spread placement
+                            let stats = &mut agent_stats.shader_stats;
+                            for sensor_index in 0..MAX_SENSORS {
+                                let (angle, offset) = sensor_placement(stats, sensor_index as u32, stats.sensor_count);
+                                stats.sensor_angles[sensor_index] = angle.to_degrees();
+                                stats.sensor_offsets[sensor_index] = offset;
+                            }
+                        }
+                        agent_stats.shader_stats.custom_sensors = if custom_sensors { 1 } else { 0 };
+                        if agent_stats.shader_stats.custom_sensors != 0 {
+                            for sensor_index in 0..agent_stats.shader_stats.sensor_count as usize {
+                                ui.add(Slider::new(&mut agent_stats.shader_stats.sensor_angles[sensor_index], -180.0..=180.0)
+                                    .text(format!("Sensor {} angle (degrees)", sensor_index)));
+                                ui.add(Slider::new(&mut agent_stats.shader_stats.sensor_offsets[sensor_index], 0.0..=30.0)
+                                    .text(format!("Sensor {} offset", sensor_index)));
+                            }
+                        }
                         ui.add(Slider::new(&mut agent_stats.shader_stats.timeout, 0.5..=30.0)
                             .text("Timeout"));
-                        ui.add(Slider::new(&mut agent_stats.shader_stats.timeout_conversion, 0..=NUM_AGENT_TYPES as u32 - 1)
+                        ui.add(Slider::new(&mut agent_stats.shader_stats.timeout_conversion, 0..=num_agent_types - 1)
                             .text("Timeout conversion target"));
+                        ui.add(Slider::new(&mut agent_stats.shader_stats.timeout_variation, 0.0..=10.0)
+                            .text("Timeout variation"));
+                        let mut edge_behavior = agent_stats.shader_stats.edge_behavior.decode();
+                        ComboBox::from_label("Edge behavior")
+                            .selected_text(format!("{}", edge_behavior))
+                            .show_ui(ui, |ui| {
+                                selectable_value_pred(ui, &mut edge_behavior, |mode| matches!(mode, EdgeBehavior::RandomAngle), EdgeBehavior::RandomAngle);
+                                selectable_value_pred(ui, &mut edge_behavior, |mode| matches!(mode, EdgeBehavior::Reflect), EdgeBehavior::Reflect);
+                                selectable_value_pred(ui, &mut edge_behavior, |mode| matches!(mode, EdgeBehavior::Absorb), EdgeBehavior::Absorb);
+                            });
+                        agent_stats.shader_stats.edge_behavior = edge_behavior.encode();
+                        let mut steering_model = agent_stats.shader_stats.steering_model.decode();
+                        ComboBox::from_label("Steering model")
+                            .selected_text(format!("{}", steering_model))
+                            .show_ui(ui, |ui| {
+                                selectable_value_pred(ui, &mut steering_model, |model| matches!(model, SteeringModel::Classic), SteeringModel::Classic);
+                                selectable_value_pred(ui, &mut steering_model, |model| matches!(model, SteeringModel::Jones), SteeringModel::Jones);
+                                selectable_value_pred(ui, &mut steering_model, |model| matches!(model, SteeringModel::Softmax), SteeringModel::Softmax);
+                                selectable_value_pred(ui, &mut steering_model, |model| matches!(model, SteeringModel::Multiplicative), SteeringModel::Multiplicative);
+                            });
+                        agent_stats.shader_stats.steering_model = steering_model.encode();
+                        if matches!(steering_model, SteeringModel::Softmax | SteeringModel::Multiplicative) {
+                            ui.add(Slider::new(&mut agent_stats.shader_stats.steering_gain, 0.0..=10.0)
+                                .text("Steering gain"));
+                        }
+                        ui.add(Slider::new(&mut agent_stats.shader_stats.obstacle_attraction, -10.0..=10.0)
+                            .text("Obstacle attraction"));
+                        let mut exclusive_occupancy = agent_stats.shader_stats.exclusive_occupancy != 0;
+                        ui.checkbox(&mut exclusive_occupancy, "One agent per pixel");
+                        agent_stats.shader_stats.exclusive_occupancy = if exclusive_occupancy { 1 } else { 0 };
+                        let mut splat_deposition = agent_stats.shader_stats.splat_deposition != 0;
+                        ui.checkbox(&mut splat_deposition, "Sub-pixel deposition");
+                        agent_stats.shader_stats.splat_deposition = if splat_deposition { 1 } else { 0 };
+                        ui.collapsing("Energy", |ui| {
+                            let mut starvation_enabled = agent_stats.shader_stats.starvation_enabled != 0;
+                            ui.checkbox(&mut starvation_enabled, "Starvation enabled");
+                            agent_stats.shader_stats.starvation_enabled = if starvation_enabled { 1 } else { 0 };
+                            let mut reproduction_enabled = agent_stats.shader_stats.reproduction_enabled != 0;
+                            ui.checkbox(&mut reproduction_enabled, "Reproduction enabled");
+                            agent_stats.shader_stats.reproduction_enabled = if reproduction_enabled { 1 } else { 0 };
+                            ui.add(Slider::new(&mut agent_stats.shader_stats.energy_cost, 0.0..=1.0)
+                                .text("Energy cost per pixel"));
+                            if agent_stats.shader_stats.reproduction_enabled != 0 {
+                                ui.add(Slider::new(&mut agent_stats.shader_stats.reproduction_energy, 0.0..=100.0)
+                                    .text("Reproduction energy"));
+                            }
+                            for (interaction, trail_name) in agent_stats.interactions.iter_mut().zip(trail_names.iter()) {
+                                ui.add(Slider::new(&mut interaction.energy_gain, -10.0..=10.0)
+                                    .text(format!("Energy gain from trail {}", trail_name)));
+                            }
+                        });
 
                         ui.collapsing("Trail interactions", |ui| {
-                            for channel_index in 0..NUM_TRAIL_STATS {
+                            for (interaction, trail_name) in agent_stats.interactions.iter_mut().zip(trail_names.iter()) {
                                 ui.separator();
-                                ui.label(format!("Trail {}", TRAIL_NAMES[channel_index]));
-                                let interaction = &mut agent_stats.shader_stats.interaction_channels[channel_index];
+                                ui.label(format!("Trail {}", trail_name));
                                 ui.add(Slider::new(&mut interaction.attraction, -10.0..=10.0)
                                     .text("Attraction"));
                                 ui.add(Slider::new(&mut interaction.addition, -1.0..=1.0)
                                     .text("Addition"));
+                                ui.add(Slider::new(&mut interaction.speed_coefficient, -2.0..=2.0)
+                                    .text("Speed per concentration"));
+                                ui.add(Slider::new(&mut interaction.turn_coefficient, -2.0..=2.0)
+                                    .text("Turn speed per concentration"));
                                 let mut conversion_enabled = interaction.conversion_enabled != 0;
                                 ui.checkbox(&mut conversion_enabled, "Conversion enabled");
                                 interaction.conversion_enabled = if conversion_enabled { 1 } else { 0 };
                                 if interaction.conversion_enabled != 0 {
                                     ui.add(Slider::new(&mut interaction.conversion_threshold, 0.0..=1.0)
                                         .text("Conversion threshold"));
-                                    ui.add(Slider::new(&mut interaction.conversion, 0..=NUM_AGENT_TYPES as u32 - 1)
+                                    ui.add(Slider::new(&mut interaction.conversion_rate, 0.0..=20.0)
+                                        .text("Conversion rate (0 converts at the threshold)"));
+                                    ui.add(Slider::new(&mut interaction.conversion, 0..=num_agent_types - 1)
                                         .text("Conversion new agent type"));
                                 }
                             }
                         });
                         ui.separator();
+                        ui.add(Slider::new(&mut agent_stats.num_agents, 0..=1000000)
+                            .text("Num agents per spawn mode").logarithmic(true));
                         ui.label("Applies on reset:");
                         // ui.add(ComboBox::new(&mut agent_stats.spawn_mode, "Spawn mode"));
-                        ui.add(Slider::new(&mut agent_stats.num_agents, 0..=1000000)
-                            .text("Num agents").logarithmic(true));
+                        ui.add(Slider::new(&mut agent_stats.shader_stats.initial_energy, 0.0..=100.0)
+                            .text("Initial energy"));
                         for spawn in agent_stats.spawn_mode.iter_mut() {
                             ComboBox::from_label("Spawn mode")
                                 .selected_text(format!("{}", spawn))
@@ -117,6 +232,9 @@ pub fn render_configuration_menu(
                                     selectable_value_pred(ui, spawn, |mode| matches!(mode, SpawnMode::BoxFacingRandom {..}), SpawnMode::BoxFacingRandom {
                                         spawn_box: spawn.spawn_box().unwrap_or(SpawnBox::default())
                                     });
+                                    selectable_value_pred(ui, spawn, |mode| matches!(mode, SpawnMode::FromImage {..}), SpawnMode::FromImage {
+                                        heading: SpawnHeading::Random
+                                    });
                                 });
                             let width = screen_size.width;
                             let height = screen_size.height;
@@ -170,17 +288,83 @@ pub fn render_configuration_menu(
                                     ui.add(Slider::new(box_height, 0..=height - *top)
                                         .text("Height"));
                                 }
+                                SpawnMode::FromImage { heading } => {
+                                    ComboBox::from_label("Heading")
+                                        .selected_text(format!("{}", heading))
+                                        .show_ui(ui, |ui| {
+                                            selectable_value_pred(ui, heading, |heading| matches!(heading, SpawnHeading::Random), SpawnHeading::Random);
+                                            selectable_value_pred(ui, heading, |heading| matches!(heading, SpawnHeading::Gradient), SpawnHeading::Gradient);
+                                            selectable_value_pred(ui, heading, |heading| matches!(heading, SpawnHeading::Centroid), SpawnHeading::Centroid);
+                                        });
+                                    #[cfg(feature = "load-image")]
+                                    if ui.button("Load density image").clicked() {
+                                        let picker_future = rfd::AsyncFileDialog::new()
+                                            .add_filter("image", &["png"])
+                                            .set_directory(std::env::current_dir().unwrap_or(PathBuf::from(".")))
+                                            .pick_file();
+                                        local_state.image_picker_handle = Some((ImageTarget::SpawnDensity, Box::new(picker_future)));
+                                    }
+                                    if spawn_image_size.x == 0 {
+                                        ui.label("No density image loaded, spawns evenly");
+                                    } else {
+                                        ui.label(format!("Density image {}x{}, shared by all agent types", spawn_image_size.x, spawn_image_size.y));
+                                    }
+                                }
                             }
                         }
                     });
                 }
 
-                for (trail_index, trail_stats) in configuration.trail_stats.iter_mut().enumerate() {
-                    ui.collapsing(format!("Trail {}", TRAIL_NAMES[trail_index]), |ui| {
+                if let Some(agent_type) = removed_agent_type {
+                    configuration.remove_agent_type(agent_type);
+                }
+                if ui.button("Add agent type").clicked() {
+                    configuration.add_agent_type();
+                }
+
+                let mut removed_trail = None;
+                for (trail_index, trail) in configuration.trail_stats.iter_mut().enumerate() {
+                    ui.collapsing(format!("Trail {}", trail.name), |ui| {
+                        if num_trails > 1 && ui.button("Remove trail (resets trails)").clicked() {
+                            removed_trail = Some(trail_index);
+                        }
+                        let trail_stats = &mut trail.shader_stats;
                         ui.add(Slider::new(&mut trail_stats.evaporation_speed, 0.0..=1000.0)
                             .text("Evaporation speed"));
                         ui.add(Slider::new(&mut trail_stats.diffusion_speed, 0.0..=1000.0)
                             .text("Diffusion speed"));
+                        let kernel = &mut trail_stats.kernel;
+                        let mut shape = kernel.shape.decode();
+                        ComboBox::from_label("Diffusion kernel")
+                            .selected_text(format!("{}", shape))
+                            .show_ui(ui, |ui| {
+                                selectable_value_pred(ui, &mut shape, |shape| matches!(shape, KernelShape::None), KernelShape::None);
+                                selectable_value_pred(ui, &mut shape, |shape| matches!(shape, KernelShape::Box), KernelShape::Box);
+                                selectable_value_pred(ui, &mut shape, |shape| matches!(shape, KernelShape::Gaussian), KernelShape::Gaussian);
+                                selectable_value_pred(ui, &mut shape, |shape| matches!(shape, KernelShape::Anisotropic), KernelShape::Anisotropic);
+                            });
+                        kernel.shape = shape.encode();
+                        match shape {
+                            KernelShape::None => {}
+                            KernelShape::Box => {
+                                ui.add(Slider::new(&mut kernel.radius, 1..=MAX_KERNEL_RADIUS)
+                                    .text("Kernel radius"));
+                            }
+                            KernelShape::Gaussian => {
+                                ui.add(Slider::new(&mut kernel.sigma, 0.1..=MAX_KERNEL_RADIUS as f32 / 3.0)
+                                    .text("Kernel sigma"));
+                            }
+                            KernelShape::Anisotropic => {
+                                ui.add(Slider::new(&mut kernel.sigma, 0.1..=MAX_KERNEL_RADIUS as f32 / 3.0)
+                                    .text("Kernel sigma"));
+                                ui.add(Slider::new(&mut kernel.direction, 0.0..=180.0)
+                                    .text("Kernel direction"));
+                                ui.add(Slider::new(&mut kernel.ratio, 0.05..=1.0)
+                                    .text("Kernel ratio across"));
+                            }
+                        }
+                        ui.add(Slider::new(&mut trail_stats.feed_rate, 0.0..=1.0)
+                            .text("Feed rate"));
                         ui.horizontal(|ui| {
                             let mut color = [
                                 trail_stats.color.inner.x,
@@ -208,6 +392,47 @@ pub fn render_configuration_menu(
                         trail_stats.color_mode = color_mode.encode();
                     });
                 }
+                if let Some(trail_index) = removed_trail {
+                    configuration.remove_trail(trail_index);
+                }
+                if configuration.trail_stats.len() < MAX_TRAILS && ui.button("Add trail").clicked() {
+                    configuration.add_trail();
+                }
+                ui.collapsing("Reactions", |ui| {
+                    let mut removed = None;
+                    for (reaction_index, reaction) in configuration.reactions.iter_mut().enumerate() {
+                        ui.separator();
+                        ui.push_id(reaction_index, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("Reaction {}", reaction_index));
+                                if ui.button("Remove").clicked() {
+                                    removed = Some(reaction_index);
+                                }
+                            });
+                            channel_combo_box(ui, "Source", &trail_names, &mut reaction.source, false);
+                            channel_combo_box(ui, "Catalyst", &trail_names, &mut reaction.catalyst, true);
+                            if reaction.catalyst != NO_CHANNEL {
+                                ui.add(Slider::new(&mut reaction.catalyst_exponent, 0.0..=4.0)
+                                    .text("Catalyst exponent"));
+                            }
+                            channel_combo_box(ui, "Target", &trail_names, &mut reaction.target, true);
+                            ui.add(Slider::new(&mut reaction.rate, 0.0..=10.0)
+                                .text("Rate"));
+                        });
+                    }
+                    if let Some(reaction_index) = removed {
+                        configuration.reactions.remove(reaction_index);
+                    }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if configuration.reactions.len() < MAX_REACTIONS && ui.button("Add reaction").clicked() {
+                            configuration.reactions.push(Reaction::default());
+                        }
+                        if ui.button("Clear reactions").clicked() {
+                            configuration.reactions.clear();
+                        }
+                    });
+                });
 
                 ui.separator();
                 ui.horizontal(|ui| {
@@ -236,6 +461,193 @@ pub fn render_configuration_menu(
                     configuration.globals.background_color.inner.w = color[3];
                     ui.label("Background color");
                 });
+                ui.horizontal(|ui| {
+                    let mut color = [
+                        configuration.globals.obstacle_color.inner.x,
+                        configuration.globals.obstacle_color.inner.y,
+                        configuration.globals.obstacle_color.inner.z,
+                        configuration.globals.obstacle_color.inner.w,
+                    ];
+                    ui.color_edit_button_rgba_unmultiplied(&mut color);
+                    configuration.globals.obstacle_color.inner.x = color[0];
+                    configuration.globals.obstacle_color.inner.y = color[1];
+                    configuration.globals.obstacle_color.inner.z = color[2];
+                    configuration.globals.obstacle_color.inner.w = color[3];
+                    ui.label("Obstacle color");
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Clear obstacles").clicked() {
+                        configuration.obstacles = PixelMask::default();
+                        configuration.upload_obstacles = true;
+                    }
+                    #[cfg(feature = "load-image")]
+                    if ui.button("Load obstacle mask").clicked() {
+                        let picker_future = rfd::AsyncFileDialog::new()
+                            .add_filter("image", &["png"])
+                            .set_directory(std::env::current_dir().unwrap_or(PathBuf::from(".")))
+                            .pick_file();
+                        local_state.image_picker_handle = Some((ImageTarget::Obstacles, Box::new(picker_future)));
+                    }
+                });
+                ui.collapsing("Emitters", |ui| {
+                    ui.add(Slider::new(&mut configuration.globals.emitter_rate, -5.0..=5.0)
+                        .text("Rate of new emitters (negative for sinks)"));
+                    let mut removed = None;
+                    for (emitter_index, emitter) in configuration.emitters.iter_mut().enumerate() {
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{} {}", emitter.shape, emitter_index));
+                            if ui.button("Remove").clicked() {
+                                removed = Some(emitter_index);
+                            }
+                        });
+                        ui.add(Slider::new(&mut emitter.trail_index, 0..=num_trails - 1)
+                            .text("Trail index"));
+                        ui.add(Slider::new(&mut emitter.rate, -5.0..=5.0)
+                            .text("Rate"));
+                        match &mut emitter.shape {
+                            EmitterShape::Point { x, y } => {
+                                ui.add(Slider::new(x, 0..=screen_size.width).text("X"));
+                                ui.add(Slider::new(y, 0..=screen_size.height).text("Y"));
+                            }
+                            EmitterShape::Circle { x, y, radius } => {
+                                ui.add(Slider::new(x, 0..=screen_size.width).text("X"));
+                                ui.add(Slider::new(y, 0..=screen_size.height).text("Y"));
+                                ui.add(Slider::new(radius, 0..=200).text("Radius"));
+                            }
+                            EmitterShape::Region { .. } => {}
+                        }
+                    }
+                    if let Some(emitter_index) = removed {
+                        configuration.emitters.remove(emitter_index);
+                    }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui.button("Add emitter").clicked() {
+                            configuration.emitters.push(Emitter {
+                                shape: EmitterShape::Circle {
+                                    x: screen_size.width / 2,
+                                    y: screen_size.height / 2,
+                                    radius: DEFAULT_EMITTER_RADIUS,
+                                },
+                                trail_index: 0,
+                                rate: configuration.globals.emitter_rate,
+                            });
+                        }
+                        #[cfg(feature = "load-image")]
+                        if ui.button("Load emitter region").clicked() {
+                            let picker_future = rfd::AsyncFileDialog::new()
+                                .add_filter("image", &["png"])
+                                .set_directory(std::env::current_dir().unwrap_or(PathBuf::from(".")))
+                                .pick_file();
+                            local_state.image_picker_handle = Some((ImageTarget::EmitterRegion, Box::new(picker_future)));
+                        }
+                        if ui.button("Clear emitters").clicked() {
+                            configuration.emitters.clear();
+                        }
+                    });
+                });
+                ui.collapsing("Flow", |ui| {
+                    let flow_mode = &mut configuration.globals.flow_mode;
+                    ComboBox::from_label("Flow mode")
+                        .selected_text(format!("{}", flow_mode))
+                        .show_ui(ui, |ui| {
+                            selectable_value_pred(ui, flow_mode, |mode| matches!(mode, FlowMode::None), FlowMode::None);
+                            selectable_value_pred(ui, flow_mode, |mode| matches!(mode, FlowMode::Uniform), FlowMode::Uniform);
+                            selectable_value_pred(ui, flow_mode, |mode| matches!(mode, FlowMode::Vortex), FlowMode::Vortex);
+                            selectable_value_pred(ui, flow_mode, |mode| matches!(mode, FlowMode::CurlNoise), FlowMode::CurlNoise);
+                            selectable_value_pred(ui, flow_mode, |mode| matches!(mode, FlowMode::Image), FlowMode::Image);
+                        });
+                    let globals = &mut configuration.globals;
+                    ui.add(Slider::new(&mut globals.flow_strength, -200.0..=200.0)
+                        .text("Strength (pixels per second)"));
+                    match globals.flow_mode {
+                        FlowMode::None => {}
+                        FlowMode::Uniform => {
+                            ui.add(Slider::new(&mut globals.flow_direction, 0.0..=360.0)
+                                .text("Direction"));
+                        }
+                        FlowMode::Vortex => {
+                            ui.add(Slider::new(&mut globals.flow_center_x, 0.0..=1.0)
+                                .text("Center x"));
+                            ui.add(Slider::new(&mut globals.flow_center_y, 0.0..=1.0)
+                                .text("Center y"));
+                        }
+                        FlowMode::CurlNoise => {
+                            ui.add(Slider::new(&mut globals.flow_noise_scale, 0.001..=0.1)
+                                .logarithmic(true)
+                                .text("Noise scale"));
+                        }
+                        FlowMode::Image => {
+                            #[cfg(feature = "load-image")]
+                            if ui.button("Load flow field").clicked() {
+                                let picker_future = rfd::AsyncFileDialog::new()
+                                    .add_filter("image", &["png"])
+                                    .set_directory(std::env::current_dir().unwrap_or(PathBuf::from(".")))
+                                    .pick_file();
+                                local_state.image_picker_handle = Some((ImageTarget::FlowField, Box::new(picker_future)));
+                            }
+                        }
+                    }
+                    ui.add(Slider::new(&mut globals.flow_agent_drift, 0.0..=1.0)
+                        .text("Agent drift"));
+                });
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut configuration.globals.seed));
+                    ui.label("Seed");
+                    if ui.button("Randomize").clicked() {
+                        configuration.globals.seed = rand::random();
+                    }
+                });
+                ui.add(Slider::new(&mut configuration.globals.spare_agent_slots, 0..=1000000)
+                    .logarithmic(true)
+                    .text("Spare agent slots (applies on respawn)"));
+                ui.checkbox(&mut configuration.globals.gpu_spawning, "Spawn agents on the gpu (applies on respawn)");
+                ui.collapsing("Performance", |ui| {
+                    ui.add(Slider::new(&mut configuration.globals.sort_interval, 0..=600)
+                        .text("Steps between sorting agents (0 is off)"));
+                    ui.label("Sorted runs aren't reproducible from the seed");
+                    if ui.button("Run benchmark").clicked() {
+                        configuration.run_benchmark = true;
+                    }
+                    if let Some(result) = &local_state.benchmark_result {
+                        ui.label(format!("{} agents", result.num_agents));
+                        ui.label(format!("Unsorted: {:.1} steps per second", result.unsorted_steps_per_second));
+                        ui.label(format!("Sorted every {} steps: {:.1} steps per second", result.sort_interval, result.sorted_steps_per_second));
+                    }
+                });
+                let boundary_mode = &mut configuration.globals.boundary_mode;
+                ComboBox::from_label("Boundary mode")
+                    .selected_text(format!("{}", boundary_mode))
+                    .show_ui(ui, |ui| {
+                        selectable_value_pred(ui, boundary_mode, |mode| matches!(mode, BoundaryMode::Clamp), BoundaryMode::Clamp);
+                        selectable_value_pred(ui, boundary_mode, |mode| matches!(mode, BoundaryMode::Wrap), BoundaryMode::Wrap);
+                    });
+                let deposition_mode = &mut configuration.globals.deposition_mode;
+                ComboBox::from_label("Trail deposition")
+                    .selected_text(format!("{}", deposition_mode))
+                    .show_ui(ui, |ui| {
+                        selectable_value_pred(ui, deposition_mode, |mode| matches!(mode, DepositionMode::Fast), DepositionMode::Fast);
+                        selectable_value_pred(ui, deposition_mode, |mode| matches!(mode, DepositionMode::Atomic), DepositionMode::Atomic);
+                    });
+                let trail_format = &mut configuration.globals.trail_format;
+                ComboBox::from_label("Trail precision (clears trails)")
+                    .selected_text(format!("{}", trail_format))
+                    .show_ui(ui, |ui| {
+                        selectable_value_pred(ui, trail_format, |format| matches!(format, TrailFormat::Fixed8), TrailFormat::Fixed8);
+                        selectable_value_pred(ui, trail_format, |format| matches!(format, TrailFormat::Fixed16), TrailFormat::Fixed16);
+                        selectable_value_pred(ui, trail_format, |format| matches!(format, TrailFormat::Float16), TrailFormat::Float16);
+                        selectable_value_pred(ui, trail_format, |format| matches!(format, TrailFormat::Float32 {..}), TrailFormat::Float32 {
+                            channels: MAX_INTS_PER_PIXEL
+                        });
+                    });
+                if let TrailFormat::Float32 { channels } = trail_format {
+                    ui.add(Slider::new(channels, 1..=MAX_INTS_PER_PIXEL)
+                        .text("Float channels (clears trails)"));
+                }
+                if configuration.trail_stats.len() > configuration.num_trails() {
+                    ui.label(format!("Trails past the first {} are ignored by this precision", configuration.num_trails()));
+                }
                 let click_mode = &mut configuration.globals.click_mode;
                 ComboBox::from_label("Click mode")
                     .selected_text(format!("{}", click_mode))
@@ -245,14 +657,21 @@ pub fn render_configuration_menu(
                         selectable_value_pred(ui, click_mode, |mode| matches!(mode, ClickMode::PaintTrail(_)), ClickMode::PaintTrail(0));
                         selectable_value_pred(ui, click_mode, |mode| matches!(mode, ClickMode::ResetTrail(_)), ClickMode::ResetTrail(0));
                         selectable_value_pred(ui, click_mode, |mode| matches!(mode, ClickMode::ResetAllTrails), ClickMode::ResetAllTrails);
+                        selectable_value_pred(ui, click_mode, |mode| matches!(mode, ClickMode::PaintObstacle), ClickMode::PaintObstacle);
+                        selectable_value_pred(ui, click_mode, |mode| matches!(mode, ClickMode::EraseObstacle), ClickMode::EraseObstacle);
+                        selectable_value_pred(ui, click_mode, |mode| matches!(mode, ClickMode::PlaceEmitter(_)), ClickMode::PlaceEmitter(0));
                     });
                 match click_mode {
                     ClickMode::PaintTrail(trail_index) => {
-                        ui.add(Slider::new(trail_index, 0..=NUM_TRAIL_STATS as u32 - 1)
+                        ui.add(Slider::new(trail_index, 0..=num_trails - 1)
                             .text("Trail index"));
                     }
                     ClickMode::ResetTrail(trail_index) => {
-                        ui.add(Slider::new(trail_index, 0..=NUM_TRAIL_STATS as u32 - 1)
+                        ui.add(Slider::new(trail_index, 0..=num_trails - 1)
+                            .text("Trail index"));
+                    }
+                    ClickMode::PlaceEmitter(trail_index) => {
+                        ui.add(Slider::new(trail_index, 0..=num_trails - 1)
                             .text("Trail index"));
                     }
                     _ => {}
@@ -284,6 +703,20 @@ pub fn render_configuration_menu(
     }
 }
 
+fn channel_combo_box(ui: &mut Ui, label: &str, trail_names: &[String], channel: &mut u32, allow_none: bool) {
+    let channel_name = |channel: u32| trail_names.get(channel as usize).map(String::as_str).unwrap_or("None");
+    ComboBox::from_label(label)
+        .selected_text(channel_name(*channel))
+        .show_ui(ui, |ui| {
+            if allow_none {
+                ui.selectable_value(channel, NO_CHANNEL, "None");
+            }
+            for channel_index in 0..trail_names.len() as u32 {
+                ui.selectable_value(channel, channel_index, channel_name(channel_index));
+            }
+        });
+}
+
 pub fn selectable_value_pred<Value: std::fmt::Display, F>(
     ui: &mut Ui,
     current_value: &mut Value,
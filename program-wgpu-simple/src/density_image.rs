@@ -0,0 +1,85 @@
+#[cfg(feature = "load-image")]
+use std::path::Path;
+use glam::{uvec2, vec2, UVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+// Only the rough shape matters when sampling, so loaded images are shrunk to this to keep presets small
+#[cfg(feature = "load-image")]
+const MAX_DENSITY_IMAGE_SIZE: u32 = 256;
+
+/// Spawn density for SpawnMode::FromImage, scaled to the map size.
+/// `values` is in row major order, brighter pixels spawn more agents.
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DensityImage {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<u8>,
+}
+
+impl DensityImage {
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() || self.values.len() < (self.width * self.height) as usize
+    }
+
+    /// Zero without an image, agents then spawn evenly
+    pub fn size(&self) -> UVec2 {
+        if self.is_empty() {
+            return UVec2::ZERO;
+        }
+        uvec2(self.width, self.height)
+    }
+
+    /// Running total of the values, the layout of the density buffer of the spawn shaders
+    pub fn cumulative(&self) -> Vec<u32> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        self.values[..(self.width * self.height) as usize]
+            .iter()
+            .scan(0, |total, &value| {
+                *total += value as u32;
+                Some(*total)
+            })
+            .collect()
+    }
+
+    /// Center of mass in pixels of the image, for SpawnHeading::Centroid
+    pub fn centroid(&self) -> Vec2 {
+        if self.is_empty() {
+            return Vec2::ZERO;
+        }
+        let mut weighted = Vec2::ZERO;
+        let mut total = 0.0;
+        for (index, &value) in self.values[..(self.width * self.height) as usize].iter().enumerate() {
+            let pixel = vec2((index as u32 % self.width) as f32, (index as u32 / self.width) as f32) + 0.5;
+            weighted += pixel * value as f32;
+            total += value as f32;
+        }
+        if total == 0.0 {
+            return Vec2::ZERO;
+        }
+        weighted / total
+    }
+
+    /// The alpha channel is the density if the image has any transparent pixels, the brightness otherwise
+    #[cfg(feature = "load-image")]
+    pub fn from_image(path: &Path) -> image::ImageResult<Self> {
+        let mut image = image::open(path)?;
+        if image.width() > MAX_DENSITY_IMAGE_SIZE || image.height() > MAX_DENSITY_IMAGE_SIZE {
+            image = image.resize(MAX_DENSITY_IMAGE_SIZE, MAX_DENSITY_IMAGE_SIZE, image::imageops::FilterType::Triangle);
+        }
+        let image = image.to_luma_alpha8();
+        let use_alpha = image.pixels().any(|pixel| pixel.0[1] < u8::MAX);
+        let values = image.pixels()
+            .map(|pixel| {
+                let [luma, alpha] = pixel.0;
+                if use_alpha { alpha } else { luma }
+            })
+            .collect();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            values,
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt::{Display, Formatter};
+use glam::UVec2;
+use serde::{Deserialize, Serialize};
+use shared::{EmitterStats, MAX_EMITTERS};
+use crate::pixel_mask::PixelMask;
+
+/// A static food source or sink, see [`EmitterStats`].
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Emitter {
+    pub shape: EmitterShape,
+    pub trail_index: u32,
+    pub rate: f32,
+}
+
+// Positions and sizes are in map pixels
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub enum EmitterShape {
+    Point { x: u32, y: u32 },
+    Circle { x: u32, y: u32, radius: u32 },
+    Region { mask: PixelMask },
+}
+
+impl Display for EmitterShape {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmitterShape::Point { .. } => f.write_str("Point"),
+            EmitterShape::Circle { .. } => f.write_str("Circle"),
+            EmitterShape::Region { .. } => f.write_str("Region"),
+        }
+    }
+}
+
+/// One u32 per pixel, the index + 1 of the emitter covering the pixel or zero.
+/// Where emitters overlap, the later one wins.
+pub fn emitter_map(emitters: &[Emitter], map_size: UVec2) -> Vec<u32> {
+    let mut map = vec![0; (map_size.x * map_size.y) as usize];
+    for (emitter_index, emitter) in emitters.iter().enumerate().take(MAX_EMITTERS) {
+        let value = emitter_index as u32 + 1;
+        match &emitter.shape {
+            EmitterShape::Point { x, y } => {
+                if *x < map_size.x && *y < map_size.y {
+                    map[(y * map_size.x + x) as usize] = value;
+                }
+            }
+            EmitterShape::Circle { x, y, radius } => {
+                let top = y.saturating_sub(*radius);
+                let bottom = (y + radius).min(map_size.y.saturating_sub(1));
+                let left = x.saturating_sub(*radius);
+                let right = (x + radius).min(map_size.x.saturating_sub(1));
+                for pixel_y in top..=bottom {
+                    for pixel_x in left..=right {
+                        let offset_x = pixel_x as i64 - *x as i64;
+                        let offset_y = pixel_y as i64 - *y as i64;
+                        if offset_x * offset_x + offset_y * offset_y <= (*radius as i64).pow(2) {
+                            map[(pixel_y * map_size.x + pixel_x) as usize] = value;
+                        }
+                    }
+                }
+            }
+            EmitterShape::Region { mask } => {
+                for (pixel, covered) in map.iter_mut().zip(mask.to_pixels(map_size)) {
+                    if covered != 0 {
+                        *pixel = value;
+                    }
+                }
+            }
+        }
+    }
+    map
+}
+
+/// Always MAX_EMITTERS long, so the buffer never has to be recreated
+pub fn emitter_stats(emitters: &[Emitter]) -> Vec<EmitterStats> {
+    let mut stats = emitters
+        .iter()
+        .take(MAX_EMITTERS)
+        .map(|emitter| EmitterStats {
+            trail_index: emitter.trail_index,
+            rate: emitter.rate,
+        })
+        .collect::<Vec<_>>();
+    stats.resize(MAX_EMITTERS, EmitterStats::default());
+    stats
+}
@@ -0,0 +1,71 @@
+#[cfg(feature = "load-image")]
+use std::path::Path;
+use glam::{vec2, UVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+// Flow fields are smooth, so loaded images are shrunk to this to keep presets small
+#[cfg(feature = "load-image")]
+const MAX_FLOW_FIELD_SIZE: u32 = 128;
+
+/// Flow vectors for FlowMode::Image, at a lower resolution than the map.
+/// `vectors` is in row major order, each component between -1.0 and 1.0.
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct FlowField {
+    pub width: u32,
+    pub height: u32,
+    pub vectors: Vec<[f32; 2]>,
+}
+
+impl FlowField {
+    /// One vector per pixel, scaled to the map size with bilinear sampling
+    pub fn to_pixels(&self, map_size: UVec2) -> Vec<Vec2> {
+        let num_pixels = (map_size.x * map_size.y) as usize;
+        if self.is_empty() {
+            return vec![Vec2::ZERO; num_pixels];
+        }
+        let source = |x: u32, y: u32| {
+            let [vector_x, vector_y] = self.vectors[(y.min(self.height - 1) * self.width + x.min(self.width - 1)) as usize];
+            vec2(vector_x, vector_y)
+        };
+        let mut pixels = Vec::with_capacity(num_pixels);
+        for y in 0..map_size.y {
+            for x in 0..map_size.x {
+                // Pixel centers line up between the two resolutions
+                let source_pos = ((vec2(x as f32, y as f32) + 0.5) * vec2(self.width as f32, self.height as f32)
+                    / map_size.as_vec2() - 0.5).max(Vec2::ZERO);
+                let base = source_pos.floor();
+                let fraction = source_pos - base;
+                let (base_x, base_y) = (base.x as u32, base.y as u32);
+                let top = source(base_x, base_y).lerp(source(base_x + 1, base_y), fraction.x);
+                let bottom = source(base_x, base_y + 1).lerp(source(base_x + 1, base_y + 1), fraction.x);
+                pixels.push(top.lerp(bottom, fraction.y));
+            }
+        }
+        pixels
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty() || self.vectors.len() < (self.width * self.height) as usize
+    }
+
+    /// The red and green channels are the x and y components, with 128 as zero
+    #[cfg(feature = "load-image")]
+    pub fn from_image(path: &Path) -> image::ImageResult<Self> {
+        let mut image = image::open(path)?;
+        if image.width() > MAX_FLOW_FIELD_SIZE || image.height() > MAX_FLOW_FIELD_SIZE {
+            image = image.resize(MAX_FLOW_FIELD_SIZE, MAX_FLOW_FIELD_SIZE, image::imageops::FilterType::Triangle);
+        }
+        let image = image.to_rgb8();
+        let vectors = image.pixels()
+            .map(|pixel| {
+                let [red, green, _] = pixel.0;
+                [((red as f32 - 128.0) / 127.0).max(-1.0), ((green as f32 - 128.0) / 127.0).max(-1.0)]
+            })
+            .collect();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            vectors,
+        })
+    }
+}
@@ -79,6 +79,14 @@ mod configuration;
 mod slot_egui;
 mod configuration_menu;
 mod slot_mouse;
+mod pixel_mask;
+mod emitters;
+mod flow_field;
+mod density_image;
+mod statistics;
+mod statistics_panel;
+mod agent_sort;
+mod agent_spawn;
 
 fn main() {
     window::run();
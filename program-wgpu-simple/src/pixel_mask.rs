@@ -0,0 +1,77 @@
+#[cfg(feature = "load-image")]
+use std::path::Path;
+use glam::{uvec2, UVec2};
+use serde::{Deserialize, Serialize};
+
+/// Set of pixels in the compact form that is stored in presets, used for obstacles and emitter regions.
+/// `runs` alternates between the lengths of unset and set runs of pixels in row major order,
+/// starting with an unset run.
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PixelMask {
+    pub width: u32,
+    pub height: u32,
+    pub runs: Vec<u32>,
+}
+
+impl PixelMask {
+    pub fn from_pixels(pixels: &[u32], size: UVec2) -> Self {
+        let mut runs = Vec::new();
+        let mut blocked = false;
+        let mut run_length = 0;
+        for pixel in pixels.iter().take((size.x * size.y) as usize) {
+            if (*pixel != 0) != blocked {
+                runs.push(run_length);
+                blocked = !blocked;
+                run_length = 0;
+            }
+            run_length += 1;
+        }
+        if blocked {
+            runs.push(run_length);
+        }
+        Self {
+            width: size.x,
+            height: size.y,
+            runs,
+        }
+    }
+
+    /// One u32 per pixel, scaled to the map size with nearest neighbour sampling
+    pub fn to_pixels(&self, map_size: UVec2) -> Vec<u32> {
+        let num_pixels = (map_size.x * map_size.y) as usize;
+        if self.is_empty() {
+            return vec![0; num_pixels];
+        }
+        let mut source = Vec::with_capacity((self.width * self.height) as usize);
+        for (run_index, run_length) in self.runs.iter().enumerate() {
+            let value = (run_index % 2) as u32;
+            source.extend(std::iter::repeat(value).take(*run_length as usize));
+        }
+        source.resize((self.width * self.height) as usize, 0);
+        let mut pixels = Vec::with_capacity(num_pixels);
+        for y in 0..map_size.y {
+            for x in 0..map_size.x {
+                let source_pos = uvec2(x * self.width / map_size.x, y * self.height / map_size.y);
+                pixels.push(source[(source_pos.y * self.width + source_pos.x) as usize]);
+            }
+        }
+        pixels
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.len() < 2
+    }
+
+    /// Dark, opaque pixels in the image are set
+    #[cfg(feature = "load-image")]
+    pub fn from_image(path: &Path) -> image::ImageResult<Self> {
+        let image = image::open(path)?.to_luma_alpha8();
+        let pixels = image.pixels()
+            .map(|pixel| {
+                let [luma, alpha] = pixel.0;
+                (luma < 128 && alpha >= 128) as u32
+            })
+            .collect::<Vec<_>>();
+        Ok(Self::from_pixels(&pixels, uvec2(image.width(), image.height())))
+    }
+}
@@ -1,21 +1,33 @@
 use std::ops::Deref;
 use std::thread;
 use std::time::{Duration, Instant};
-use crate::configuration::create_agent_stats_all;
-use glam::{uvec2, UVec2};
+use crate::configuration::{create_agent_stats_all, create_trail_stats_all};
+use glam::{uvec2, vec2, UVec2};
 use crate::configuration::RESIZE_MAP_WITH_WINDOW;
 use crate::configuration::ConfigurationValues;
-use rand::Rng;
-use crate::configuration::{GLOBALS, TRAIL_STATS};
+use crate::configuration::GLOBALS;
 use wgpu::SurfaceTexture;
 use winit::event::{WindowEvent};
 use wgpu::util::DeviceExt;
-use shared::{ShaderConstants, pixel_view::INTS_PER_PIXEL};
+use shared::{FlowConstants, MAX_EMITTERS, MAX_REACTIONS, MAX_TRAILS, Reaction, ShaderConstants, TrailFormat, TrailStats};
 use crate::slot_agents::SlotAgents;
 use crate::slot_diffuse::SlotDiffuse;
 use crate::slot_mouse::SlotMouse;
 use crate::slot_egui::SlotEgui;
 use crate::slot_render::SlotRender;
+use crate::pixel_mask::PixelMask;
+use crate::emitters::{emitter_map, emitter_stats};
+use crate::flow_field::FlowField;
+use crate::density_image::DensityImage;
+use crate::statistics::StatisticsReader;
+use crate::agent_sort::{AgentSorter, BenchmarkResult};
+
+// Longest frame that is simulated in full, a longer one slows the simulation down
+const MAX_DELTA_TIME: f32 = 0.25;
+// Compute steps per half of the benchmark
+const BENCHMARK_STEPS: u32 = 300;
+// Used by the sorted half of the benchmark when sorting is turned off
+const BENCHMARK_SORT_INTERVAL: u32 = 16;
 
 pub struct Program<'window> {
     program_init: ProgramInit<'window>,
@@ -27,6 +39,12 @@ pub struct Program<'window> {
     slot_egui: SlotEgui,
     configuration: ConfigurationValues,
     first_frame: bool,
+    // Compute steps since the last respawn
+    frame: u32,
+    // Simulated time that hasn't been stepped through yet
+    accumulator: f32,
+    statistics_reader: StatisticsReader,
+    agent_sorter: AgentSorter,
 }
 
 // Data that is created at program init
@@ -40,7 +58,12 @@ pub struct Handles<'window> {
 
 pub struct ProgramInit<'window> {
     pub handles: Handles<'window>,
+    // Always MAX_TRAILS long
     pub trail_stats_buffer: wgpu::Buffer,
+    // Always MAX_EMITTERS long
+    pub emitter_stats_buffer: wgpu::Buffer,
+    // Always MAX_REACTIONS long
+    pub reaction_buffer: wgpu::Buffer,
 }
 
 impl<'window> Deref for ProgramInit<'window> {
@@ -54,9 +77,19 @@ impl<'window> Deref for ProgramInit<'window> {
 // Data regenerated buffer size is changed
 pub struct ProgramBuffers {
     pub map_size: UVec2,
+    // Layout of trail_buffer, changing it recreates the buffers
+    pub trail_format: TrailFormat,
 
     pub num_bytes_screen_buffers: usize,
     pub trail_buffer: wgpu::Buffer,
+    // One u32 per pixel, non-zero pixels are obstacles
+    pub obstacle_buffer: wgpu::Buffer,
+    // One u32 per pixel, which agent holds the pixel for agents with exclusive occupancy
+    pub occupancy_buffer: wgpu::Buffer,
+    // One u32 per pixel, the index + 1 of the emitter covering the pixel or zero
+    pub emitter_map_buffer: wgpu::Buffer,
+    // One vec2 per pixel, the flow of FlowMode::Image
+    pub flow_field_buffer: wgpu::Buffer,
 }
 
 // Data regenerated each frame
@@ -86,13 +119,24 @@ impl Program<'_> {
             shader_config_changed: false,
             globals: GLOBALS,
             agent_stats: create_agent_stats_all(),
-            trail_stats: TRAIL_STATS,
+            trail_stats: create_trail_stats_all(),
+            obstacles: PixelMask::default(),
+            emitters: Vec::new(),
+            reactions: Vec::new(),
+            flow_field: FlowField::default(),
+            spawn_image: DensityImage::default(),
             scale_factor: 1.0,
             show_menu: false,
             respawn: false,
             reset_trails: false,
+            upload_obstacles: false,
+            download_obstacles: false,
+            upload_emitters: false,
+            upload_flow_field: false,
             quit: false,
             playing: true,
+            advance_steps: 0,
+            run_benchmark: false,
         };
 
         let trail_stats_bytes = Self::bytes_from_trail_stats(&configuration);
@@ -103,9 +147,23 @@ impl Program<'_> {
                 | wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC,
         });
+        let emitter_stats_buffer = handles.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Emitter stats buffer"),
+            contents: bytemuck::cast_slice(&emitter_stats(&configuration.emitters)),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
+        let reaction_buffer = handles.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Reaction buffer"),
+            contents: &Self::bytes_from_reactions(&configuration),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
         let program_init = ProgramInit {
             handles,
             trail_stats_buffer,
+            emitter_stats_buffer,
+            reaction_buffer,
         };
 
         let program_buffers = Self::create_buffers(&program_init, &configuration);
@@ -114,6 +172,8 @@ impl Program<'_> {
         let slot_mouse = SlotMouse::create(&program_init, &program_buffers, &configuration);
         let slot_render = SlotRender::create(&program_init, &program_buffers, &configuration);
         let slot_egui = SlotEgui::create(&program_init, &program_buffers, &configuration);
+        let statistics_reader = StatisticsReader::create(&program_init);
+        let agent_sorter = AgentSorter::create(&program_init);
         Program {
             program_init,
             program_buffers,
@@ -124,6 +184,10 @@ impl Program<'_> {
             slot_egui,
             configuration,
             first_frame: true,
+            frame: 0,
+            accumulator: 0.0,
+            statistics_reader,
+            agent_sorter,
         }
     }
 
@@ -136,7 +200,8 @@ impl Program<'_> {
             uvec2(configuration.globals.map_width, configuration.globals.map_height)
         };
         println!("Map width and height {}, {}", size.x, size.y);
-        let empty_bytes = Self::bytes_from_trail_map_size(size);
+        let trail_format = configuration.globals.trail_format;
+        let empty_bytes = Self::bytes_from_trail_map_size(size, trail_format);
         let num_bytes = empty_bytes.len();
 
         let trail_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -147,10 +212,48 @@ impl Program<'_> {
                 | wgpu::BufferUsages::COPY_SRC,
         });
 
+        let obstacle_bytes = Self::bytes_from_pixel_mask(&configuration.obstacles, size);
+        let obstacle_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Obstacle buffer"),
+            contents: &obstacle_bytes,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let occupancy_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Occupancy buffer"),
+            contents: &Self::bytes_from_occupancy_map_size(size),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let emitter_map_bytes = Self::bytes_from_pixels(emitter_map(&configuration.emitters, size), size);
+        let emitter_map_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Emitter map buffer"),
+            contents: &emitter_map_bytes,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let flow_field_bytes = Self::bytes_from_flow_field(&configuration.flow_field, size);
+        let flow_field_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Flow field buffer"),
+            contents: &flow_field_bytes,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
+
         let buffers = ProgramBuffers {
             map_size: size,
+            trail_format,
             num_bytes_screen_buffers: num_bytes,
             trail_buffer,
+            obstacle_buffer,
+            occupancy_buffer,
+            emitter_map_buffer,
+            flow_field_buffer,
         };
         buffers
     }
@@ -169,85 +272,257 @@ impl Program<'_> {
         if self.configuration.quit {
             return false;
         }
+        let min_delta_time = 1.0 / self.configuration.globals.max_frame_rate;
+        let elapsed = last_time.elapsed().as_secs_f32();
+        if elapsed < min_delta_time {
+            thread::sleep(Duration::from_secs_f32(min_delta_time - elapsed));
+        }
         let time = start.elapsed().as_secs_f32();
         let delta_time = last_time.elapsed().as_secs_f32();
-        let mut time_step = self.configuration.globals.time_step * rand::rng().random_range(0.95..1.05);
-        let min_delta_time = 1.0 / self.configuration.globals.max_frame_rate;
-        if delta_time < min_delta_time {
-            if self.configuration.globals.smoothen_after_max_frame_rate {
-                time_step = (time_step / min_delta_time) * delta_time;
-            } else {
-                if !self.configuration.playing {
-                    thread::sleep(Duration::from_secs_f32(min_delta_time - delta_time));
-                }
-            }
+        if self.configuration.respawn {
+            self.frame = 0;
+            self.accumulator = 0.0;
+            self.statistics_reader.discard();
+            self.slot_egui.local_state.statistics.clear();
         }
+        let time_step = self.configuration.globals.time_step;
         *last_time = Instant::now();
+        if self.configuration.globals.trail_format != self.program_buffers.trail_format {
+            // The trails can't be converted, but painted obstacles are kept
+            let pixels = self.read_obstacle_buffer();
+            self.configuration.obstacles = PixelMask::from_pixels(&pixels, self.program_buffers.map_size);
+            self.recreate_buffers();
+            self.first_frame = true;
+        }
         let screen_size = self.program_init.window.inner_size();
         let push_constants = ShaderConstants {
             screen_size: uvec2(screen_size.width, screen_size.height),
             map_size: self.program_buffers.map_size,
             time,
             time_step,
-            padding_1: 0.0,
-            padding_2: 0.0,
+            boundary_mode: self.configuration.globals.boundary_mode.encode(),
+            seed: self.configuration.globals.seed,
+            frame: self.frame,
+            num_emitters: self.configuration.emitters.len().min(MAX_EMITTERS) as u32,
+            num_reactions: self.configuration.reactions.len().min(MAX_REACTIONS) as u32,
+            deposition_mode: self.configuration.globals.deposition_mode.encode(),
+            trail_format: self.program_buffers.trail_format.encode(),
+            num_agent_types: self.configuration.agent_stats.len() as u32,
+            num_trails: self.configuration.num_trails() as u32,
             background_color: self.configuration.globals.background_color,
+            obstacle_color: self.configuration.globals.obstacle_color,
+            flow: FlowConstants {
+                mode: self.configuration.globals.flow_mode.encode(),
+                strength: self.configuration.globals.flow_strength,
+                direction: self.configuration.globals.flow_direction.to_radians(),
+                center: vec2(self.configuration.globals.flow_center_x, self.configuration.globals.flow_center_y),
+                noise_scale: self.configuration.globals.flow_noise_scale,
+                agent_drift: self.configuration.globals.flow_agent_drift,
+                padding: 0.0,
+            },
         };
         if self.configuration.reset_trails {
             self.configuration.reset_trails = false;
-            let bytes = Self::bytes_from_trail_map_size(self.program_buffers.map_size);
+            let bytes = Self::bytes_from_trail_map_size(self.program_buffers.map_size, self.program_buffers.trail_format);
             self.program_init.queue.write_buffer(&self.program_buffers.trail_buffer, 0, &bytes);
             self.program_init.queue.submit([]);
             self.first_frame = true;
         }
+        if self.configuration.download_obstacles {
+            self.configuration.download_obstacles = false;
+            let pixels = self.read_obstacle_buffer();
+            self.configuration.obstacles = PixelMask::from_pixels(&pixels, self.program_buffers.map_size);
+        }
+        if self.configuration.upload_obstacles {
+            self.configuration.upload_obstacles = false;
+            let bytes = Self::bytes_from_pixel_mask(&self.configuration.obstacles, self.program_buffers.map_size);
+            self.program_init.queue.write_buffer(&self.program_buffers.obstacle_buffer, 0, &bytes);
+            self.program_init.queue.submit([]);
+        }
+        if self.configuration.upload_emitters {
+            self.configuration.upload_emitters = false;
+            let map_size = self.program_buffers.map_size;
+            let map_bytes = Self::bytes_from_pixels(emitter_map(&self.configuration.emitters, map_size), map_size);
+            self.program_init.queue.write_buffer(&self.program_buffers.emitter_map_buffer, 0, &map_bytes);
+            let stats = emitter_stats(&self.configuration.emitters);
+            self.program_init.queue.write_buffer(&self.program_init.emitter_stats_buffer, 0, bytemuck::cast_slice(&stats));
+            self.program_init.queue.submit([]);
+        }
+        if self.configuration.upload_flow_field {
+            self.configuration.upload_flow_field = false;
+            let bytes = Self::bytes_from_flow_field(&self.configuration.flow_field, self.program_buffers.map_size);
+            self.program_init.queue.write_buffer(&self.program_buffers.flow_field_buffer, 0, &bytes);
+            self.program_init.queue.submit([]);
+        }
         // Update buffers
         if self.configuration.shader_config_changed {
             let trail_stats_bytes = Self::bytes_from_trail_stats(&self.configuration);
             self.program_init.queue.write_buffer(&self.program_init.trail_stats_buffer, 0, &trail_stats_bytes);
+            let reaction_bytes = Self::bytes_from_reactions(&self.configuration);
+            self.program_init.queue.write_buffer(&self.program_init.reaction_buffer, 0, &reaction_bytes);
             self.program_init.queue.submit([]);
         }
-        let frame = Frame {
+        self.slot_agents.update_buffers(&self.program_init, &self.program_buffers, &mut self.configuration);
+        let mut num_steps = 0;
+        if self.configuration.playing {
+            // A hitch (dragging the window, a slow frame) isn't caught up on all at once
+            self.accumulator += delta_time.min(MAX_DELTA_TIME) * self.configuration.globals.simulation_speed;
+            num_steps = ((self.accumulator / time_step) as u32).min(self.configuration.globals.compute_steps_per_render);
+            self.accumulator -= num_steps as f32 * time_step;
+            // When the steps are capped the simulation slows down instead of falling further behind
+            self.accumulator = self.accumulator.min(time_step);
+            // Render just one step the first frame, to show the spawn positions
+            if self.first_frame {
+                self.first_frame = false;
+                num_steps = 1;
+            }
+        } else {
+            self.accumulator = 0.0;
+        }
+        num_steps += std::mem::take(&mut self.configuration.advance_steps);
+        let mut frame = Frame {
             output,
             push_constants,
         };
-        if self.configuration.playing {
-            for _ in 0..self.configuration.globals.compute_steps_per_render {
-                self.slot_agents.on_loop(&self.program_init, &self.program_buffers, &frame, &mut self.configuration);
-                self.slot_diffuse.on_loop(&self.program_init, &self.program_buffers, &frame, &mut self.configuration);
-                // Render just one step the first frame, to show the spawn positions
-                if self.first_frame {
-                    self.first_frame = false;
-                    break;
-                }
+        for _ in 0..num_steps {
+            let sort_interval = self.configuration.globals.sort_interval;
+            if sort_interval != 0 && self.frame % sort_interval == 0 {
+                self.agent_sorter.sort(&self.program_init, &self.program_buffers, &self.slot_agents, &frame.push_constants);
+            }
+            self.compute_step(&mut frame);
+            let statistics_interval = self.configuration.globals.statistics_interval;
+            if statistics_interval != 0 && self.frame % statistics_interval == 0 {
+                self.statistics_reader.request(&self.program_init, &self.program_buffers, &self.slot_agents, &frame.push_constants, self.frame, self.configuration.globals.statistics_trail_totals);
             }
         }
+        if let Some(sample) = self.statistics_reader.poll(&self.program_init) {
+            self.slot_egui.local_state.statistics.push(sample);
+        }
+        if self.configuration.run_benchmark {
+            self.configuration.run_benchmark = false;
+            let result = self.run_benchmark(&mut frame);
+            self.slot_egui.local_state.benchmark_result = Some(result);
+        }
         self.slot_mouse.on_loop(&self.program_init, &self.program_buffers, &frame, &mut self.configuration);
         self.slot_render.on_loop(&self.program_init, &self.program_buffers, &frame, &mut self.configuration);
         self.slot_egui.on_loop(&self.program_init, &self.program_buffers, &frame, &mut self.configuration);
         true
     }
 
+    fn compute_step(&mut self, frame: &mut Frame<'_>) {
+        frame.push_constants.frame = self.frame;
+        self.frame = self.frame.wrapping_add(1);
+        self.slot_agents.on_loop(&self.program_init, &self.program_buffers, frame, &mut self.configuration);
+        self.slot_diffuse.on_loop(&self.program_init, &self.program_buffers, frame, &mut self.configuration);
+    }
+
+    // Runs BENCHMARK_STEPS steps without sorting and then as many with sorting, waiting for the gpu to finish each half.
+    // The unsorted half goes first, so it starts from the less mixed up agents.
+    fn run_benchmark(&mut self, frame: &mut Frame<'_>) -> BenchmarkResult {
+        let sort_interval = match self.configuration.globals.sort_interval {
+            0 => BENCHMARK_SORT_INTERVAL,
+            sort_interval => sort_interval,
+        };
+        let unsorted_steps_per_second = self.time_steps(frame, None);
+        let sorted_steps_per_second = self.time_steps(frame, Some(sort_interval));
+        BenchmarkResult {
+            num_agents: self.slot_agents.init.num_agents,
+            unsorted_steps_per_second,
+            sorted_steps_per_second,
+            sort_interval,
+        }
+    }
+
+    fn time_steps(&mut self, frame: &mut Frame<'_>, sort_interval: Option<u32>) -> f32 {
+        self.program_init.device.poll(wgpu::Maintain::Wait);
+        let start = Instant::now();
+        for step in 0..BENCHMARK_STEPS {
+            if let Some(sort_interval) = sort_interval {
+                if step % sort_interval == 0 {
+                    self.agent_sorter.sort(&self.program_init, &self.program_buffers, &self.slot_agents, &frame.push_constants);
+                }
+            }
+            self.compute_step(frame);
+        }
+        self.program_init.device.poll(wgpu::Maintain::Wait);
+        BENCHMARK_STEPS as f32 / start.elapsed().as_secs_f32()
+    }
+
     pub(crate) fn handle_input(&mut self, event: &WindowEvent) {
         let consumed = self.slot_egui.handle_input(&self.program_init.window, &event);
         if !consumed {
-            self.slot_mouse.handle_input(&mut self.configuration, &event);
+            self.slot_mouse.handle_input(&self.program_init, &self.program_buffers, &mut self.configuration, &event);
         }
     }
 
-    pub fn bytes_from_trail_map_size(size: UVec2) -> Vec<u8> {
+    pub fn bytes_from_trail_map_size(size: UVec2, trail_format: TrailFormat) -> Vec<u8> {
         let alignment = wgpu::COPY_BUFFER_ALIGNMENT as u32;
         let num_pixels = ((size.x * size.y).div_ceil(alignment) * alignment) as usize;
         let empty_bytes = std::iter::repeat(0 as u32)
-            .take(num_pixels * INTS_PER_PIXEL as usize)
+            .take(num_pixels * trail_format.ints_per_pixel() as usize)
             .flat_map(u32::to_ne_bytes)
             .collect::<Vec<_>>();
         empty_bytes
     }
+    pub fn bytes_from_occupancy_map_size(size: UVec2) -> Vec<u8> {
+        let alignment = wgpu::COPY_BUFFER_ALIGNMENT as u32;
+        let num_pixels = ((size.x * size.y).div_ceil(alignment) * alignment) as usize;
+        vec![0; num_pixels * std::mem::size_of::<u32>()]
+    }
+
+    pub fn bytes_from_pixel_mask(mask: &PixelMask, size: UVec2) -> Vec<u8> {
+        Self::bytes_from_pixels(mask.to_pixels(size), size)
+    }
+
+    // One u32 per pixel, padded to the copy alignment
+    pub fn bytes_from_pixels(mut pixels: Vec<u32>, size: UVec2) -> Vec<u8> {
+        let alignment = wgpu::COPY_BUFFER_ALIGNMENT as u32;
+        let num_pixels = ((size.x * size.y).div_ceil(alignment) * alignment) as usize;
+        pixels.resize(num_pixels, 0);
+        bytemuck::cast_slice(&pixels).to_vec()
+    }
+
+    // Blocks until the gpu is done, so only call this occasionally
+    fn read_obstacle_buffer(&self) -> Vec<u32> {
+        let size = self.program_buffers.obstacle_buffer.size();
+        let staging_buffer = self.program_init.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Obstacle staging buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder =
+            self.program_init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&self.program_buffers.obstacle_buffer, 0, &staging_buffer, 0, size);
+        self.program_init.queue.submit([encoder.finish()]);
+
+        let slice = staging_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.program_init.device.poll(wgpu::Maintain::Wait);
+        let pixels = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging_buffer.unmap();
+        pixels
+    }
+
+    // Always MAX_TRAILS long, so the buffer never has to be recreated
     fn bytes_from_trail_stats(configuration: &ConfigurationValues) -> Vec<u8> {
-        let trail_stats_bytes = configuration.trail_stats.iter().flat_map(|trail_stats|
-            bytemuck::bytes_of(trail_stats).to_vec()
-        ).collect::<Vec<_>>();
-        trail_stats_bytes
+        let mut trail_stats = configuration.trail_stats.iter()
+            .map(|trail_stats_all| trail_stats_all.shader_stats)
+            .take(MAX_TRAILS)
+            .collect::<Vec<_>>();
+        trail_stats.resize(MAX_TRAILS, bytemuck::Zeroable::zeroed());
+        bytemuck::cast_slice::<TrailStats, u8>(&trail_stats).to_vec()
+    }
+
+    fn bytes_from_flow_field(flow_field: &FlowField, size: UVec2) -> Vec<u8> {
+        bytemuck::cast_slice(&flow_field.to_pixels(size)).to_vec()
+    }
+
+    // Always MAX_REACTIONS long, so the buffer never has to be recreated
+    fn bytes_from_reactions(configuration: &ConfigurationValues) -> Vec<u8> {
+        let mut reactions = configuration.reactions.iter().copied().take(MAX_REACTIONS).collect::<Vec<_>>();
+        reactions.resize(MAX_REACTIONS, Reaction::default());
+        bytemuck::cast_slice(&reactions).to_vec()
     }
 }
 
@@ -1,12 +1,19 @@
-use glam::UVec2;
+use glam::{vec2, UVec2};
 use crate::configuration::AgentStatsAll;
 use crate::configuration::ConfigurationValues;
-use rand::Rng;
-use shared::{ShaderConstants, SpawnBox, SpawnMode};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use shared::{AdjustConstants, Agent, INACTIVE_AGENT_TYPE, ShaderConstants, SpawnBatch, SpawnBox, SpawnConstants, SpawnMode, TrailInteraction};
+use std::time::{Duration, Instant};
+use crate::agent_spawn::{AgentCount, AgentSpawner};
 use crate::program::*;
+use shared::simulation::{initial_countdown, SpawnDensity};
 use wgpu::util::DeviceExt;
+use bytemuck::Zeroable;
 
 const CS_ENTRY_POINT: &str = "main_cs";
+// How long a changed number of agents has to stay the same before the agents are adjusted to it
+const ADJUST_DELAY: Duration = Duration::from_millis(300);
 
 pub struct SlotAgents {
     pub init: SlotAgentsInit,
@@ -17,8 +24,18 @@ pub struct SlotAgentsInit {
     pub pipeline: wgpu::ComputePipeline,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub agents_buffer: wgpu::Buffer,
+    // One entry per agent type
     pub agent_stats_buffer: wgpu::Buffer,
+    // One row of interactions per agent type, with an entry per trail
+    pub interaction_buffer: wgpu::Buffer,
     pub num_agents: usize,
+    pub spawner: AgentSpawner,
+    // The num_agents of every agent type the agents were spawned or last adjusted with
+    pub spawned_counts: Vec<usize>,
+    // The num_agents that differ from spawned_counts, and since when, applied once they rested
+    pub changed_counts: Option<(Vec<usize>, Instant)>,
+    // Seeds the agents added by every adjustment differently
+    pub num_adjustments: u32,
 }
 
 pub struct SlotAgentsBuffers {
@@ -30,9 +47,6 @@ impl Slot for SlotAgents {
     type Buffers = SlotAgentsBuffers;
 
     fn create(program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, configuration: &ConfigurationValues) -> Self {
-        let mut num_agents = 0;
-        let agent_bytes = Self::bytes_from_agents(configuration, program_buffers.map_size, &mut num_agents);
-
         let agent_stats_bytes = Self::bytes_from_agent_stats(configuration);
         let agent_stats_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Agent stats buffer"),
@@ -41,14 +55,17 @@ impl Slot for SlotAgents {
                 | wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC,
         });
-
-        let agents_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Agent buffer"),
-            contents: &agent_bytes,
+        let interaction_bytes = Self::bytes_from_interactions(configuration);
+        let interaction_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Interaction buffer"),
+            contents: &interaction_bytes,
             usage: wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC,
         });
+
+        let spawner = AgentSpawner::create(program_init);
+        let (agents_buffer, num_agents) = Self::spawn_agents(program_init, &spawner, &agent_stats_buffer, configuration, program_buffers.map_size);
         let bind_group_layout = program_init.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
             entries: &[
@@ -82,6 +99,46 @@ impl Slot for SlotAgents {
                         ty: wgpu::BufferBindingType::Storage { read_only: false },
                     },
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
             ],
         });
 
@@ -109,7 +166,12 @@ impl Slot for SlotAgents {
             bind_group_layout,
             agents_buffer,
             agent_stats_buffer,
+            interaction_buffer,
             num_agents,
+            spawner,
+            spawned_counts: Self::agent_counts(configuration),
+            changed_counts: None,
+            num_adjustments: 0,
         };
         let buffers = Self::create_buffers(program_init, program_buffers, &init);
         Self {
@@ -135,6 +197,22 @@ impl Slot for SlotAgents {
                     binding: 2,
                     resource: program_buffers.trail_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: program_buffers.obstacle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: program_buffers.occupancy_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: program_buffers.flow_field_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: init.interaction_buffer.as_entire_binding(),
+                },
             ],
         });
         SlotAgentsBuffers {
@@ -147,28 +225,7 @@ impl Slot for SlotAgents {
         self.buffers = buffers;
     }
 
-    fn on_loop(&mut self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, program_frame: &Frame<'_>, configuration: &mut ConfigurationValues) {
-        // Update buffers
-        if configuration.shader_config_changed {
-            let agent_stats_bytes = Self::bytes_from_agent_stats(configuration);
-            program_init.queue.write_buffer(&self.init.agent_stats_buffer, 0, &agent_stats_bytes);
-        }
-        if configuration.respawn {
-            configuration.respawn = false;
-            let mut num_agents = 0;
-            let agent_bytes = Self::bytes_from_agents(configuration, program_buffers.map_size, &mut num_agents);
-            let agent_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Agent buffer recreated"),
-                contents: &agent_bytes,
-                usage: wgpu::BufferUsages::STORAGE
-                    | wgpu::BufferUsages::COPY_DST
-                    | wgpu::BufferUsages::COPY_SRC,
-            });
-            self.init.agents_buffer = agent_buffer;
-            self.init.num_agents = num_agents;
-            self.recreate_buffers(program_init, program_buffers);
-        }
-
+    fn on_loop(&mut self, program_init: &ProgramInit<'_>, _program_buffers: &ProgramBuffers, program_frame: &Frame<'_>, _configuration: &mut ConfigurationValues) {
         // Run compute pass
         let mut encoder =
             program_init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
@@ -189,42 +246,328 @@ impl Slot for SlotAgents {
 }
 
 impl SlotAgents {
+    // Runs every frame, also the ones without compute steps, so no change is missed
+    pub(crate) fn update_buffers(&mut self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, configuration: &mut ConfigurationValues) {
+        if configuration.shader_config_changed {
+            let agent_stats_bytes = Self::bytes_from_agent_stats(configuration);
+            let interaction_bytes = Self::bytes_from_interactions(configuration);
+            let agent_stats_replaced = write_or_replace_buffer(program_init, &mut self.init.agent_stats_buffer, &agent_stats_bytes, "Agent stats buffer");
+            let interactions_replaced = write_or_replace_buffer(program_init, &mut self.init.interaction_buffer, &interaction_bytes, "Interaction buffer");
+            if agent_stats_replaced || interactions_replaced {
+                self.recreate_buffers(program_init, program_buffers);
+            }
+        }
+        if configuration.respawn {
+            configuration.respawn = false;
+            let (agents_buffer, num_agents) = Self::spawn_agents(program_init, &self.init.spawner, &self.init.agent_stats_buffer, configuration, program_buffers.map_size);
+            self.init.agents_buffer = agents_buffer;
+            self.init.num_agents = num_agents;
+            self.init.spawned_counts = Self::agent_counts(configuration);
+            self.init.changed_counts = None;
+            self.init.spawner.discard_count();
+            self.init.num_adjustments = 0;
+            // The new agents claim their pixels on their first step
+            let occupancy_bytes = Program::bytes_from_occupancy_map_size(program_buffers.map_size);
+            program_init.queue.write_buffer(&program_buffers.occupancy_buffer, 0, &occupancy_bytes);
+            self.recreate_buffers(program_init, program_buffers);
+        } else {
+            self.adjust_agent_counts(program_init, program_buffers, configuration);
+        }
+    }
+
+    fn agent_counts(configuration: &ConfigurationValues) -> Vec<usize> {
+        configuration.agent_stats.iter().map(|agent_stats| agent_stats.num_agents).collect()
+    }
+
+    // Spawns or retires agents of the types whose num_agents changed, while the others keep running.
+    // A change is applied once num_agents rested for ADJUST_DELAY, so dragging a slider adjusts the agents once.
+    // The agents are counted on the gpu first and read back without stalling the frame,
+    // as dying, converting and reproducing agents change the counts and free and take slots.
+    fn adjust_agent_counts(&mut self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, configuration: &ConfigurationValues) {
+        // Polled every frame, so a count that is no longer wanted doesn't hold up the next one
+        let agent_count = self.init.spawner.poll_count(program_init);
+        let counts = Self::agent_counts(configuration);
+        if counts == self.init.spawned_counts {
+            self.init.changed_counts = None;
+            return;
+        }
+        match &self.init.changed_counts {
+            Some((changed, since)) if *changed == counts => {
+                if since.elapsed() < ADJUST_DELAY {
+                    return;
+                }
+            }
+            _ => {
+                self.init.changed_counts = Some((counts, Instant::now()));
+                return;
+            }
+        }
+        match agent_count {
+            // Only counts requested after the change rested get here
+            Some(agent_count) => {
+                self.apply_agent_counts(program_init, program_buffers, configuration, &agent_count);
+                self.init.spawned_counts = counts;
+                self.init.changed_counts = None;
+            }
+            None => self.init.spawner.request_count(program_init, &self.init.agents_buffer, self.init.num_agents, &self.init.agent_stats_buffer, counts.len()),
+        }
+    }
+
+    // Brings the changed types to num_agents per spawn mode from their live count, like at respawn.
+    // Added agents go into inactive slots, the buffer grows when there aren't enough of them.
+    // Which agents are retired and which slots are filled depends on the gpu, so runs with changes aren't reproducible.
+    fn apply_agent_counts(&mut self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, configuration: &ConfigurationValues, agent_count: &AgentCount) {
+        let mut retirements = Vec::new();
+        let mut spawn_batches = Vec::new();
+        let mut num_added = 0;
+        for (agent_type, agent_stats) in configuration.agent_stats.iter().enumerate() {
+            if self.init.spawned_counts.get(agent_type) == Some(&agent_stats.num_agents) {
+                continue;
+            }
+            let num_spawn_modes = agent_stats.spawn_mode.len();
+            let target = agent_stats.num_agents * num_spawn_modes;
+            let live = agent_count.agent_counts.get(agent_type).copied().unwrap_or(0);
+            if live > target {
+                retirements.push((agent_type as u32, live - target));
+            } else if live < target {
+                // Shared between the spawn modes, the first ones take the remainder
+                let missing = target - live;
+                for (mode_index, spawn_mode) in agent_stats.spawn_mode.iter().enumerate() {
+                    let num_agents = missing / num_spawn_modes + usize::from(mode_index < missing % num_spawn_modes);
+                    if num_agents == 0 {
+                        continue;
+                    }
+                    spawn_batches.push(SpawnBatch {
+                        spawn_mode: spawn_mode.encode(),
+                        first_agent: num_added as u32,
+                        num_agents: num_agents as u32,
+                        agent_type: agent_type as u32,
+                        _padding: 0,
+                    });
+                    num_added += num_agents;
+                }
+            }
+        }
+
+        let spawn_constants = SpawnConstants {
+            map_size: program_buffers.map_size,
+            num_agents: 0,
+            num_batches: 0,
+            seed: configuration.globals.seed,
+            density_size: configuration.spawn_image.size(),
+            density_centroid: configuration.spawn_image.centroid(),
+            _padding: 0,
+        };
+        let density = configuration.spawn_image.cumulative();
+        // Retire first, so the additions can take the freed slots
+        let mut free_slots = agent_count.inactive_slots;
+        for (retired_type, num_retired) in retirements {
+            let constants = AdjustConstants {
+                spawn: spawn_constants,
+                retired_type,
+                num_retired: num_retired as u32,
+                num_counted_types: 0,
+                _padding: 0,
+            };
+            self.init.spawner.adjust(program_init, program_buffers, &self.init.agents_buffer, self.init.num_agents, &self.init.agent_stats_buffer, &[], &density, &constants);
+            free_slots += num_retired;
+        }
+        if num_added > 0 {
+            if num_added > free_slots {
+                // Grow by half again at least, so raising the count in steps doesn't copy the buffer every time
+                let additional = usize::max(num_added - free_slots, self.init.num_agents / 2);
+                self.grow_agents_buffer(program_init, program_buffers, additional);
+            }
+            self.init.num_adjustments = self.init.num_adjustments.wrapping_add(1);
+            let constants = AdjustConstants {
+                spawn: SpawnConstants {
+                    num_agents: num_added as u32,
+                    num_batches: spawn_batches.len() as u32,
+                    seed: configuration.globals.seed.wrapping_add(self.init.num_adjustments),
+                    ..spawn_constants
+                },
+                retired_type: INACTIVE_AGENT_TYPE,
+                num_retired: 0,
+                num_counted_types: 0,
+                _padding: 0,
+            };
+            self.init.spawner.adjust(program_init, program_buffers, &self.init.agents_buffer, self.init.num_agents, &self.init.agent_stats_buffer, &spawn_batches, &density, &constants);
+        }
+    }
+
+    // Replaces the agents buffer with one that has `additional` inactive slots after the agents
+    fn grow_agents_buffer(&mut self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, additional: usize) {
+        let agents_size = (self.init.num_agents * std::mem::size_of::<Agent>()) as u64;
+        let agents_buffer = program_init.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Agent buffer grown"),
+            size: ((self.init.num_agents + additional) * std::mem::size_of::<Agent>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let spare_agent = Agent {
+            agent_type: INACTIVE_AGENT_TYPE,
+            ..Zeroable::zeroed()
+        };
+        program_init.queue.write_buffer(&agents_buffer, agents_size, bytemuck::cast_slice(&vec![spare_agent; additional]));
+        if agents_size > 0 {
+            let mut encoder =
+                program_init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            encoder.copy_buffer_to_buffer(&self.init.agents_buffer, 0, &agents_buffer, 0, agents_size);
+            program_init.queue.submit([encoder.finish()]);
+        }
+        self.init.agents_buffer = agents_buffer;
+        self.init.num_agents += additional;
+        self.recreate_buffers(program_init, program_buffers);
+    }
+
+    // Bindings can't be empty, so without agent types this is a single unused entry
     fn bytes_from_agent_stats(configuration: &ConfigurationValues) -> Vec<u8> {
-        let agent_stats_bytes = configuration.agent_stats.iter().flat_map(|stats_all|
+        let mut agent_stats_bytes = configuration.agent_stats.iter().flat_map(|stats_all|
             bytemuck::bytes_of(&stats_all.shader_stats).to_vec()
         ).collect::<Vec<_>>();
+        if agent_stats_bytes.is_empty() {
+            agent_stats_bytes = bytemuck::bytes_of(&shared::AgentStats::default()).to_vec();
+        }
         agent_stats_bytes
     }
 
+    // Rows are padded or cut to the number of trails
+    fn bytes_from_interactions(configuration: &ConfigurationValues) -> Vec<u8> {
+        let num_trails = configuration.num_trails();
+        let mut interactions = configuration.agent_stats.iter().flat_map(|stats_all| {
+            let mut row = stats_all.interactions.clone();
+            row.resize(num_trails, TrailInteraction::default());
+            row
+        }).collect::<Vec<_>>();
+        if interactions.is_empty() {
+            interactions.push(TrailInteraction::default());
+        }
+        bytemuck::cast_slice(&interactions).to_vec()
+    }
+
+    // Spawns on the gpu unless that is turned off, the cpu path is kept as a fallback.
+    // Returns the new agents buffer and its number of agents
+    fn spawn_agents(program_init: &ProgramInit<'_>, spawner: &AgentSpawner, agent_stats_buffer: &wgpu::Buffer, configuration: &ConfigurationValues, map_size: UVec2) -> (wgpu::Buffer, usize) {
+        let (spawn_batches, num_agents) = Self::spawn_batches(configuration);
+        if configuration.globals.gpu_spawning && num_agents > 0 {
+            let constants = SpawnConstants {
+                map_size,
+                num_agents: num_agents as u32,
+                num_batches: spawn_batches.len() as u32,
+                seed: configuration.globals.seed,
+                density_size: configuration.spawn_image.size(),
+                density_centroid: configuration.spawn_image.centroid(),
+                _padding: 0,
+            };
+            let density = configuration.spawn_image.cumulative();
+            let agents_buffer = spawner.spawn(program_init, agent_stats_buffer, &spawn_batches, &density, &constants);
+            return (agents_buffer, num_agents);
+        }
+        let mut num_agents = 0;
+        let agent_bytes = Self::bytes_from_agents(configuration, map_size, &mut num_agents);
+        let agents_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Agent buffer"),
+            contents: &agent_bytes,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+        (agents_buffer, num_agents)
+    }
+
+    // One batch per spawn mode of every agent type, in the same order as bytes_from_agents.
+    // Returns them with the number of agents including the spare slots after the last batch
+    fn spawn_batches(configuration: &ConfigurationValues) -> (Vec<SpawnBatch>, usize) {
+        let mut spawn_batches = Vec::new();
+        let mut num_agents = 0;
+        for (agent_type, agent_stats) in configuration.agent_stats.iter().enumerate() {
+            for spawn_mode in &agent_stats.spawn_mode {
+                spawn_batches.push(SpawnBatch {
+                    spawn_mode: spawn_mode.encode(),
+                    first_agent: num_agents as u32,
+                    num_agents: agent_stats.num_agents as u32,
+                    agent_type: agent_type as u32,
+                    _padding: 0,
+                });
+                num_agents += agent_stats.num_agents;
+            }
+        }
+        (spawn_batches, num_agents + configuration.globals.spare_agent_slots as usize)
+    }
+
     fn bytes_from_agents(configuration: &ConfigurationValues, size: UVec2, num_agents: &mut usize) -> Vec<u8> {
-        let agent_bytes = configuration.agent_stats
+        // Seeded so the same configuration always spawns the same agents
+        let mut rng = StdRng::seed_from_u64(configuration.globals.seed as u64);
+        let cumulative = configuration.spawn_image.cumulative();
+        let density = SpawnDensity {
+            cumulative: &cumulative,
+            size: configuration.spawn_image.size(),
+            centroid: configuration.spawn_image.centroid(),
+        };
+        let mut agent_bytes = configuration.agent_stats
             .iter()
             .enumerate()
             .flat_map(|(channel_index, agent_stats): (usize, &AgentStatsAll)| {
-                *num_agents += agent_stats.num_agents;
+                // Every spawn mode spawns num_agents agents
+                *num_agents += agent_stats.num_agents * agent_stats.spawn_mode.len();
                 agent_stats.spawn_mode.iter().flat_map(|spawn_mode: &SpawnMode|{
                 std::iter::repeat(())
                     .take(agent_stats.num_agents)
-                    .flat_map(move |()| {
-                        let agent = spawn_agent(size, &spawn_mode, channel_index as u32, agent_stats);
+                    .flat_map(|()| {
+                        let agent = spawn_agent(&mut rng, size, &spawn_mode, channel_index as u32, agent_stats, &density);
                         bytemuck::bytes_of(&agent).to_vec()
                     })
+                    .collect::<Vec<_>>()
                 }).collect::<Vec<_>>()
             }).collect::<Vec<_>>();
+        let spare_agent = Agent {
+            agent_type: INACTIVE_AGENT_TYPE,
+            ..Zeroable::zeroed()
+        };
+        for _ in 0..configuration.globals.spare_agent_slots {
+            agent_bytes.extend_from_slice(bytemuck::bytes_of(&spare_agent));
+        }
+        *num_agents += configuration.globals.spare_agent_slots as usize;
         agent_bytes
     }
 }
 
-fn spawn_agent(size: UVec2, spawn_mode: &SpawnMode, agent_type: u32, agent_stats: &AgentStatsAll) -> shared::Agent {
+// Writes the bytes into the buffer, or replaces the buffer if their size changed with the number of agent types or trails.
+// Returns true if the buffer was replaced, which means the bind group has to be recreated
+fn write_or_replace_buffer(program_init: &ProgramInit<'_>, buffer: &mut wgpu::Buffer, bytes: &[u8], label: &str) -> bool {
+    if buffer.size() == bytes.len() as u64 {
+        program_init.queue.write_buffer(buffer, 0, bytes);
+        return false;
+    }
+    *buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytes,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+    });
+    true
+}
+
+fn spawn_agent(rng: &mut impl Rng, size: UVec2, spawn_mode: &SpawnMode, agent_type: u32, agent_stats: &AgentStatsAll, density: &SpawnDensity<'_>) -> shared::Agent {
     let center_x = size.x as f32 / 2.0;
     let center_y = size.y as f32 / 2.0;
+    // Only draw from rng when needed, so seeds without a timeout variation spawn as before
+    let countdown = if agent_stats.shader_stats.timeout_variation != 0.0 {
+        initial_countdown(&agent_stats.shader_stats, rng.random())
+    } else {
+        agent_stats.shader_stats.timeout
+    };
     let create_agent = |x, y, angle| {
         shared::Agent {
             x,
             y,
             angle,
             agent_type,
-            countdown: agent_stats.shader_stats.timeout,
+            countdown,
+            energy: agent_stats.shader_stats.initial_energy,
         }
     };
     match spawn_mode {
@@ -232,18 +575,18 @@ fn spawn_agent(size: UVec2, spawn_mode: &SpawnMode, agent_type: u32, agent_stats
             create_agent(
                 size.x as f32 / 2.0,
                 size.y as f32 / 2.0,
-                get_random_angle(),
+                get_random_angle(rng),
             )
         }
         SpawnMode::PointFacingOutward { x, y } => {
             create_agent(
                 *x as f32,
                 *y as f32,
-                get_random_angle(),
+                get_random_angle(rng),
             )
         }
         SpawnMode::PointFacingClockwise { x, y, distance } => {
-            let random_angle = get_random_angle();
+            let random_angle = get_random_angle(rng);
             create_agent(
                 *x as f32 + random_angle.cos() * *distance as f32,
                 *y as f32 + random_angle.sin() * *distance as f32,
@@ -252,8 +595,8 @@ fn spawn_agent(size: UVec2, spawn_mode: &SpawnMode, agent_type: u32, agent_stats
         }
         SpawnMode::CircleFacingInward { max_distance } => {
             let max_number = 100000;
-            let random_angle = get_random_angle();
-            let random_fraction = rand::rng().random_range(0..max_number) as f32 / max_number as f32;
+            let random_angle = get_random_angle(rng);
+            let random_fraction = rng.random_range(0..max_number) as f32 / max_number as f32;
             let random_distance = random_fraction * *max_distance as f32;
             create_agent(
                 center_x + random_angle.cos() * random_distance,
@@ -263,13 +606,13 @@ fn spawn_agent(size: UVec2, spawn_mode: &SpawnMode, agent_type: u32, agent_stats
         }
         SpawnMode::EvenlyDistributed => {
             create_agent(
-                rand::rng().random_range(0..size.x * 10) as f32 / 10.0,
-                rand::rng().random_range(0..size.y * 10) as f32 / 10.0,
-                get_random_angle(),
+                rng.random_range(0..size.x * 10) as f32 / 10.0,
+                rng.random_range(0..size.y * 10) as f32 / 10.0,
+                get_random_angle(rng),
             )
         }
         SpawnMode::CircumferenceFacingInward { distance } => {
-            let random_angle = get_random_angle();
+            let random_angle = get_random_angle(rng);
             create_agent(
                 center_x + random_angle.cos() * *distance as f32,
                 center_y + random_angle.sin() * *distance as f32,
@@ -277,7 +620,7 @@ fn spawn_agent(size: UVec2, spawn_mode: &SpawnMode, agent_type: u32, agent_stats
             )
         }
         SpawnMode::CircumferenceFacingOutward { distance } => {
-            let random_angle = get_random_angle();
+            let random_angle = get_random_angle(rng);
             create_agent(
                 center_x + random_angle.cos() * *distance as f32,
                 center_y + random_angle.sin() * *distance as f32,
@@ -285,15 +628,15 @@ fn spawn_agent(size: UVec2, spawn_mode: &SpawnMode, agent_type: u32, agent_stats
             )
         }
         SpawnMode::CircumferenceFacingRandom { distance } => {
-            let random_angle = get_random_angle();
+            let random_angle = get_random_angle(rng);
             create_agent(
                 center_x + random_angle.cos() * *distance as f32,
                 center_y + random_angle.sin() * *distance as f32,
-                get_random_angle(),
+                get_random_angle(rng),
             )
         }
         SpawnMode::CircumferenceFacingClockwise { distance } => {
-            let random_angle = get_random_angle();
+            let random_angle = get_random_angle(rng);
             create_agent(
                 center_x + random_angle.cos() * *distance as f32,
                 center_y + random_angle.sin() * *distance as f32,
@@ -302,14 +645,19 @@ fn spawn_agent(size: UVec2, spawn_mode: &SpawnMode, agent_type: u32, agent_stats
         }
         SpawnMode::BoxFacingRandom { spawn_box: SpawnBox { left, top, box_width, box_height } } => {
             create_agent(
-                rand::rng().random_range(*left as f32..*left as f32 + *box_width as f32),
-                rand::rng().random_range(*top as f32..*top as f32 + *box_height as f32),
-                get_random_angle(),
+                rng.random_range(*left as f32..*left as f32 + *box_width as f32),
+                rng.random_range(*top as f32..*top as f32 + *box_height as f32),
+                get_random_angle(rng),
             )
         }
+        SpawnMode::FromImage { heading } => {
+            let random_angle = get_random_angle(rng);
+            let (position, angle) = density.sample(size, *heading, rng.random(), vec2(rng.random(), rng.random()), random_angle);
+            create_agent(position.x, position.y, angle)
+        }
     }
 }
 
-fn get_random_angle() -> f32 {
-    rand::rng().random_range(0.0..std::f32::consts::PI * 2.0)
+fn get_random_angle(rng: &mut impl Rng) -> f32 {
+    rng.random_range(0.0..std::f32::consts::PI * 2.0)
 }
\ No newline at end of file
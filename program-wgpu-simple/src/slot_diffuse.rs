@@ -1,5 +1,6 @@
 use crate::configuration::ConfigurationValues;
 use shared::ShaderConstants;
+use shared::simulation::DIFFUSE_GROUP_SIZE;
 use crate::program::*;
 use wgpu::util::DeviceExt;
 
@@ -58,6 +59,56 @@ impl Slot for SlotDiffuse {
                         ty: wgpu::BufferBindingType::Storage { read_only: false },
                     },
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
             ],
         });
 
@@ -92,7 +143,7 @@ impl Slot for SlotDiffuse {
     }
 
     fn create_buffers(program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, init: &Self::Init) -> Self::Buffers {
-        let empty_bytes = Program::bytes_from_trail_map_size(program_buffers.map_size);
+        let empty_bytes = Program::bytes_from_trail_map_size(program_buffers.map_size, program_buffers.trail_format);
         let diffuse_input_buffer = program_init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Diffuse intermediate buffer"),
             contents: &empty_bytes,
@@ -113,7 +164,27 @@ impl Slot for SlotDiffuse {
                 wgpu::BindGroupEntry {
                     binding: 2,
                     resource: program_buffers.trail_buffer.as_entire_binding(),
-                }
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: program_buffers.obstacle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: program_buffers.emitter_map_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: program_init.emitter_stats_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: program_init.reaction_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: program_buffers.flow_field_buffer.as_entire_binding(),
+                },
             ],
         });
         SlotDiffuseBuffers {
@@ -142,7 +213,7 @@ impl Slot for SlotDiffuse {
                 0,
                 bytemuck::bytes_of(&program_frame.push_constants),
             );
-            cpass.dispatch_workgroups(program_buffers.map_size.x.div_ceil(8), program_buffers.map_size.y.div_ceil(8), 1);
+            cpass.dispatch_workgroups(program_buffers.map_size.x.div_ceil(DIFFUSE_GROUP_SIZE), program_buffers.map_size.y.div_ceil(DIFFUSE_GROUP_SIZE), 1);
         }
         program_init.queue.submit([compute_encoder.finish()]);
     }
@@ -8,6 +8,17 @@ use egui_winit::State;
 use wgpu::StoreOp;
 use winit::event::WindowEvent;
 use crate::configuration_menu;
+use crate::statistics::Statistics;
+use crate::agent_sort::BenchmarkResult;
+use crate::statistics_panel;
+#[cfg(feature = "load-image")]
+use crate::pixel_mask::PixelMask;
+#[cfg(feature = "load-image")]
+use crate::emitters::{Emitter, EmitterShape};
+#[cfg(feature = "load-image")]
+use crate::flow_field::FlowField;
+#[cfg(feature = "load-image")]
+use crate::density_image::DensityImage;
 use crate::program::*;
 
 
@@ -18,10 +29,26 @@ pub struct SlotEgui {
 }
 
 pub struct LocalState {
+    // Steps taken by the "Advance" button
+    pub advance_step_count: u32,
+    pub show_statistics: bool,
+    pub statistics: Statistics,
+    pub benchmark_result: Option<BenchmarkResult>,
     #[cfg(feature = "save-preset")]
     pub file_picker_handle: Option<(String, Box<dyn Future<Output=Option<rfd::FileHandle>> + Unpin>)>,
     #[cfg(feature = "save-preset")]
     pub file_write_handle: Option<Pin<Box<dyn Future<Output=std::io::Result<()>>>>>,
+    #[cfg(feature = "load-image")]
+    pub image_picker_handle: Option<(ImageTarget, Box<dyn Future<Output=Option<rfd::FileHandle>> + Unpin>)>,
+}
+
+// What a loaded image is turned into
+#[cfg(feature = "load-image")]
+pub enum ImageTarget {
+    Obstacles,
+    EmitterRegion,
+    FlowField,
+    SpawnDensity,
 }
 
 impl Slot for SlotEgui {
@@ -51,10 +78,16 @@ impl Slot for SlotEgui {
             state: egui_state,
             renderer: egui_renderer,
             local_state: LocalState {
+                advance_step_count: 100,
+                show_statistics: false,
+                statistics: Statistics::default(),
+                benchmark_result: None,
                 #[cfg(feature = "save-preset")]
                 file_picker_handle: None,
                 #[cfg(feature = "save-preset")]
                 file_write_handle: None,
+                #[cfg(feature = "load-image")]
+                image_picker_handle: None,
             },
         }
     }
@@ -101,6 +134,45 @@ impl Slot for SlotEgui {
                 }
             }
         }
+        #[cfg(feature = "load-image")]
+        {
+            let mut ctx = futures::task::Context::from_waker(Waker::noop());
+            if let Some((target, picker_handle)) = &mut self.local_state.image_picker_handle {
+                let pinned = std::pin::pin!(picker_handle);
+                match pinned.poll(&mut ctx) {
+                    Poll::Ready(file_handle) => {
+                        if let Some(file_handle) = file_handle {
+                            let result = match target {
+                                ImageTarget::Obstacles => PixelMask::from_image(file_handle.path()).map(|mask| {
+                                    configuration.obstacles = mask;
+                                    configuration.upload_obstacles = true;
+                                }),
+                                ImageTarget::EmitterRegion => PixelMask::from_image(file_handle.path()).map(|mask| {
+                                    configuration.emitters.push(Emitter {
+                                        shape: EmitterShape::Region { mask },
+                                        trail_index: 0,
+                                        rate: configuration.globals.emitter_rate,
+                                    });
+                                    configuration.upload_emitters = true;
+                                }),
+                                ImageTarget::FlowField => FlowField::from_image(file_handle.path()).map(|flow_field| {
+                                    configuration.flow_field = flow_field;
+                                    configuration.upload_flow_field = true;
+                                }),
+                                ImageTarget::SpawnDensity => DensityImage::from_image(file_handle.path()).map(|image| {
+                                    configuration.spawn_image = image;
+                                }),
+                            };
+                            if let Err(error) = result {
+                                println!("Failed to load image: {}", error);
+                            }
+                        }
+                        self.local_state.image_picker_handle.take();
+                    }
+                    Poll::Pending => {}
+                }
+            }
+        }
 
         configuration.shader_config_changed = false;
         let window = program_init.window;
@@ -123,11 +195,16 @@ impl Slot for SlotEgui {
 
         let previous_configuration = configuration.clone();
         configuration_menu::render_configuration_menu(&self.state, window_size, configuration, &mut self.local_state);
+        statistics_panel::render_statistics_panel(&self.state, configuration, &mut self.local_state);
         if configuration.globals != previous_configuration.globals ||
             configuration.agent_stats != previous_configuration.agent_stats ||
-            configuration.trail_stats != previous_configuration.trail_stats {
+            configuration.trail_stats != previous_configuration.trail_stats ||
+            configuration.reactions != previous_configuration.reactions {
             configuration.shader_config_changed = true;
         }
+        if configuration.emitters != previous_configuration.emitters {
+            configuration.upload_emitters = true;
+        }
 
         self.state.egui_ctx().set_pixels_per_point(screen_descriptor.pixels_per_point);
 
@@ -2,6 +2,8 @@ use std::time::{Duration, Instant};
 use winit::event::{ElementState, MouseButton, WindowEvent};
 use crate::configuration::ConfigurationValues;
 use shared::{ClickMode, MouseConstants};
+use shared::simulation::map_from_screen_pos;
+use crate::emitters::{Emitter, EmitterShape};
 use crate::program::*;
 use glam::{uvec2, Vec2, vec2};
 
@@ -46,6 +48,16 @@ impl Slot for SlotMouse {
                         ty: wgpu::BufferBindingType::Storage { read_only: false },
                     },
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    },
+                },
             ],
         });
 
@@ -89,7 +101,11 @@ impl Slot for SlotMouse {
                 wgpu::BindGroupEntry {
                     binding: 0,
                     resource: program_buffers.trail_buffer.as_entire_binding(),
-                }
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: program_buffers.obstacle_buffer.as_entire_binding(),
+                },
             ],
         });
         SlotMouseBuffers {
@@ -112,7 +128,8 @@ impl Slot for SlotMouse {
             mouse_position: self.mouse_position,
             last_mouse_position: Default::default(),
             brush_size: configuration.globals.brush_size,
-            _padding: 0.0,
+            trail_format: program_buffers.trail_format.encode(),
+            num_trails: configuration.num_trails() as u32,
         };
         // Run compute pass
         let mut compute_encoder =
@@ -133,7 +150,7 @@ impl Slot for SlotMouse {
 }
 
 impl SlotMouse {
-    pub fn handle_input(&mut self, configuration: &mut ConfigurationValues, event: &WindowEvent) {
+    pub fn handle_input(&mut self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, configuration: &mut ConfigurationValues, event: &WindowEvent) {
         match event {
             WindowEvent::CursorMoved { device_id: _, position } => {
                 self.mouse_position = vec2(position.x as f32, position.y as f32);
@@ -153,6 +170,9 @@ impl SlotMouse {
                         }
                         ElementState::Released => {
                             if let Some(start) = self.mouse_click.take() {
+                                if let ClickMode::PaintObstacle | ClickMode::EraseObstacle = configuration.globals.click_mode {
+                                    configuration.download_obstacles = true;
+                                }
                                 if start.time.elapsed() > Duration::from_secs_f32(3.0) {
                                     if !configuration.show_menu {
                                         configuration.show_menu = true;
@@ -166,6 +186,28 @@ impl SlotMouse {
                                         ClickMode::PaintTrail(_) => {}
                                         ClickMode::ResetTrail(_) => {}
                                         ClickMode::ResetAllTrails => {}
+                                        ClickMode::PaintObstacle => {}
+                                        ClickMode::EraseObstacle => {}
+                                        ClickMode::PlaceEmitter(trail_index) => {
+                                            let screen_size = program_init.window.inner_size();
+                                            let map_pos = map_from_screen_pos(
+                                                self.mouse_position.as_ivec2(),
+                                                uvec2(screen_size.width, screen_size.height),
+                                                program_buffers.map_size,
+                                            );
+                                            if map_pos.x >= 0 && map_pos.y >= 0 && (trail_index as usize) < configuration.trail_stats.len() {
+                                                configuration.emitters.push(Emitter {
+                                                    shape: EmitterShape::Circle {
+                                                        x: map_pos.x as u32,
+                                                        y: map_pos.y as u32,
+                                                        radius: configuration.globals.brush_size as u32,
+                                                    },
+                                                    trail_index,
+                                                    rate: configuration.globals.emitter_rate,
+                                                });
+                                                configuration.upload_emitters = true;
+                                            }
+                                        }
                                     }
                                 }
                             }
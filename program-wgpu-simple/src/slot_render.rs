@@ -49,6 +49,16 @@ impl Slot for SlotRender {
                         ty: wgpu::BufferBindingType::Storage { read_only: false },
                     },
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    count: None,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
             ],
         });
         // Merged
@@ -122,6 +132,10 @@ impl Slot for SlotRender {
                     binding: 1,
                     resource: program_buffers.trail_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: program_buffers.obstacle_buffer.as_entire_binding(),
+                },
             ],
         });
         SlotRenderBuffers {
@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+#[cfg(feature = "save-preset")]
+use std::fmt::Write;
+use std::sync::mpsc;
+use shared::ShaderConstants;
+use shared::simulation::{STATISTICS_AGENT_OFFSET, TRAIL_TOTAL_SCALE};
+use crate::program::*;
+use crate::slot_agents::SlotAgents;
+
+const COUNT_ENTRY_POINT: &str = "count_agents_cs";
+const SUM_ENTRY_POINT: &str = "sum_trails_cs";
+// The oldest samples are dropped past this, at the default interval that is over an hour of simulation
+const MAX_SAMPLES: usize = 8192;
+
+pub struct StatisticsSample {
+    // Compute steps since the last respawn
+    pub frame: u32,
+    pub agent_counts: Vec<u32>,
+    // In saturated pixels, empty unless trail totals were enabled
+    pub trail_totals: Vec<f32>,
+}
+
+/// Time series of the samples since the last respawn
+#[derive(Default)]
+pub struct Statistics {
+    pub samples: VecDeque<StatisticsSample>,
+}
+
+impl Statistics {
+    pub fn push(&mut self, sample: StatisticsSample) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// One row per sample, agent types and trails that were added later are left empty in older rows
+    #[cfg(feature = "save-preset")]
+    pub fn to_csv(&self, agent_names: &[String], trail_names: &[String]) -> String {
+        let has_trail_totals = self.samples.iter().any(|sample| !sample.trail_totals.is_empty());
+        let mut csv = String::from("step");
+        for name in agent_names {
+            let _ = write!(csv, ",{}", csv_field(name));
+        }
+        if has_trail_totals {
+            for name in trail_names {
+                let _ = write!(csv, ",{}", csv_field(&format!("{} total", name)));
+            }
+        }
+        csv.push('\n');
+        for sample in &self.samples {
+            let _ = write!(csv, "{}", sample.frame);
+            for agent_type in 0..agent_names.len() {
+                csv.push(',');
+                if let Some(count) = sample.agent_counts.get(agent_type) {
+                    let _ = write!(csv, "{}", count);
+                }
+            }
+            if has_trail_totals {
+                for trail_index in 0..trail_names.len() {
+                    csv.push(',');
+                    if let Some(total) = sample.trail_totals.get(trail_index) {
+                        let _ = write!(csv, "{}", total);
+                    }
+                }
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+#[cfg(feature = "save-preset")]
+fn csv_field(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+struct PendingRead {
+    frame: u32,
+    num_agent_types: usize,
+    // Zero unless trail totals were requested
+    num_trails: usize,
+    // Receives whether mapping the staging buffer succeeded
+    mapped: mpsc::Receiver<bool>,
+    // Set on respawn, the sample belongs to the previous run
+    discarded: bool,
+}
+
+/// Counts the agents per type and sums the trails on the gpu, and reads the result back without stalling the frame.
+/// Only one sample is in flight at a time.
+pub struct StatisticsReader {
+    count_pipeline: wgpu::ComputePipeline,
+    sum_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // STATISTICS_AGENT_OFFSET trail totals and an agent count per agent type, grown as needed
+    statistics_buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    pending: Option<PendingRead>,
+}
+
+impl StatisticsReader {
+    pub fn create(program_init: &ProgramInit<'_>) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            count: None,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                has_dynamic_offset: false,
+                min_binding_size: None,
+                ty: wgpu::BufferBindingType::Storage { read_only },
+            },
+        };
+        let bind_group_layout = program_init.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, false),
+                storage_entry(2, false),
+            ],
+        });
+
+        let pipeline_layout = program_init.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Statistics pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::COMPUTE,
+                range: 0..std::mem::size_of::<ShaderConstants>() as u32,
+            }],
+        });
+        let create_pipeline = |entry_point| program_init.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            compilation_options: Default::default(),
+            cache: None,
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &program_init.module,
+            entry_point,
+        });
+        let count_pipeline = create_pipeline(COUNT_ENTRY_POINT);
+        let sum_pipeline = create_pipeline(SUM_ENTRY_POINT);
+
+        let (statistics_buffer, staging_buffer) = Self::create_buffers(program_init, STATISTICS_AGENT_OFFSET);
+        Self {
+            count_pipeline,
+            sum_pipeline,
+            bind_group_layout,
+            statistics_buffer,
+            staging_buffer,
+            pending: None,
+        }
+    }
+
+    fn create_buffers(program_init: &ProgramInit<'_>, len: usize) -> (wgpu::Buffer, wgpu::Buffer) {
+        let size = (len * std::mem::size_of::<u32>()) as u64;
+        let statistics_buffer = program_init.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Statistics buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = program_init.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Statistics staging buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (statistics_buffer, staging_buffer)
+    }
+
+    /// Takes a sample after compute step `frame`, unless the previous one is still being read back
+    pub fn request(&mut self, program_init: &ProgramInit<'_>, program_buffers: &ProgramBuffers, slot_agents: &SlotAgents, push_constants: &ShaderConstants, frame: u32, trail_totals: bool) {
+        if self.pending.is_some() {
+            return;
+        }
+        let num_agent_types = push_constants.num_agent_types as usize;
+        let len = STATISTICS_AGENT_OFFSET + num_agent_types;
+        let size = (len * std::mem::size_of::<u32>()) as u64;
+        if self.statistics_buffer.size() < size {
+            (self.statistics_buffer, self.staging_buffer) = Self::create_buffers(program_init, len);
+        }
+        // The agents buffer is replaced on respawn, so the bind group is made fresh for every sample
+        let bind_group = program_init.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Statistics bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: slot_agents.init.agents_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: program_buffers.trail_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.statistics_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder =
+            program_init.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.clear_buffer(&self.statistics_buffer, 0, None);
+        {
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.set_push_constants(0, bytemuck::bytes_of(push_constants));
+            cpass.set_pipeline(&self.count_pipeline);
+            cpass.dispatch_workgroups(slot_agents.init.num_agents.div_ceil(256) as u32, 1, 1);
+            if trail_totals {
+                cpass.set_pipeline(&self.sum_pipeline);
+                cpass.dispatch_workgroups(program_buffers.map_size.x.div_ceil(8), program_buffers.map_size.y.div_ceil(8), 1);
+            }
+        }
+        encoder.copy_buffer_to_buffer(&self.statistics_buffer, 0, &self.staging_buffer, 0, size);
+        program_init.queue.submit([encoder.finish()]);
+
+        let (sender, mapped) = mpsc::channel();
+        self.staging_buffer.slice(..size).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result.is_ok());
+        });
+        self.pending = Some(PendingRead {
+            frame,
+            num_agent_types,
+            num_trails: if trail_totals { push_constants.num_trails as usize } else { 0 },
+            mapped,
+            discarded: false,
+        });
+    }
+
+    /// Returns the requested sample once the gpu is done with it
+    pub fn poll(&mut self, program_init: &ProgramInit<'_>) -> Option<StatisticsSample> {
+        let pending = self.pending.as_ref()?;
+        program_init.device.poll(wgpu::Maintain::Poll);
+        let mapped = match pending.mapped.try_recv() {
+            Ok(mapped) => mapped,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => false,
+        };
+        let pending = self.pending.take()?;
+        if !mapped {
+            return None;
+        }
+        let size = ((STATISTICS_AGENT_OFFSET + pending.num_agent_types) * std::mem::size_of::<u32>()) as u64;
+        let values: Vec<u32> = bytemuck::cast_slice(&self.staging_buffer.slice(..size).get_mapped_range()).to_vec();
+        self.staging_buffer.unmap();
+        if pending.discarded {
+            return None;
+        }
+        Some(StatisticsSample {
+            frame: pending.frame,
+            agent_counts: values[STATISTICS_AGENT_OFFSET..].to_vec(),
+            trail_totals: values[..pending.num_trails].iter().map(|&total| total as f32 / TRAIL_TOTAL_SCALE).collect(),
+        })
+    }
+
+    /// Drops the sample in flight, it belongs to the previous run
+    pub fn discard(&mut self) {
+        if let Some(pending) = &mut self.pending {
+            pending.discarded = true;
+        }
+    }
+}
@@ -0,0 +1,106 @@
+#[cfg(feature = "save-preset")]
+use std::path::PathBuf;
+use egui::{Color32, Shape, Slider, Stroke, Ui, pos2, vec2};
+use egui::epaint::Hsva;
+use egui_winit::State;
+use crate::configuration::ConfigurationValues;
+use crate::slot_egui::LocalState;
+
+const PLOT_HEIGHT: f32 = 120.0;
+
+pub fn render_statistics_panel(
+    state: &State,
+    configuration: &mut ConfigurationValues,
+    local_state: &mut LocalState,
+) {
+    if !local_state.show_statistics {
+        return;
+    }
+    let mut open = true;
+    egui::Window::new("Statistics")
+        .open(&mut open)
+        .resizable(true)
+        .vscroll(true)
+        .show(state.egui_ctx(), |ui| {
+            ui.add(Slider::new(&mut configuration.globals.statistics_interval, 0..=600)
+                .text("Steps between samples (0 is off)"));
+            ui.checkbox(&mut configuration.globals.statistics_trail_totals, "Trail totals");
+
+            let agent_names = configuration.agent_stats.iter().map(|agent_stats| agent_stats.name.clone()).collect::<Vec<_>>();
+            let trail_names = configuration.trail_stats.iter().map(|trail| trail.name.clone()).collect::<Vec<_>>();
+            let samples = &local_state.statistics.samples;
+            if let Some(latest) = samples.back() {
+                ui.label(format!("Step {}", latest.frame));
+            }
+
+            ui.separator();
+            ui.label("Agents per type");
+            let agent_series = (0..agent_names.len())
+                .map(|agent_type| samples.iter().map(|sample| sample.agent_counts.get(agent_type).map(|&count| count as f32)).collect())
+                .collect::<Vec<_>>();
+            plot(ui, &agent_names, &agent_series);
+
+            if samples.iter().any(|sample| !sample.trail_totals.is_empty()) {
+                ui.separator();
+                ui.label("Trail totals, in saturated pixels");
+                let trail_series = (0..trail_names.len())
+                    .map(|trail_index| samples.iter().map(|sample| sample.trail_totals.get(trail_index).copied()).collect())
+                    .collect::<Vec<_>>();
+                plot(ui, &trail_names, &trail_series);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Clear").clicked() {
+                    local_state.statistics.clear();
+                }
+                #[cfg(feature = "save-preset")]
+                if ui.button("Export CSV").clicked() {
+                    let csv = local_state.statistics.to_csv(&agent_names, &trail_names);
+                    let picker_future = rfd::AsyncFileDialog::new()
+                        .add_filter("csv", &["csv"])
+                        .set_directory(std::env::current_dir().unwrap_or(PathBuf::from(".")))
+                        .save_file();
+                    local_state.file_picker_handle = Some((csv, Box::new(picker_future)));
+                }
+            });
+        });
+    local_state.show_statistics = open;
+}
+
+// Lines over the samples, each scaled to the largest value of all of them, followed by a legend with the latest values.
+// Missing values, from agent types or trails added during the run, leave gaps.
+fn plot(ui: &mut Ui, names: &[String], series: &[Vec<Option<f32>>]) {
+    let max = series.iter().flatten().flatten().fold(0.0f32, |max, &value| max.max(value));
+    let (response, painter) = ui.allocate_painter(vec2(ui.available_width(), PLOT_HEIGHT), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_stroke(rect, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+    for (index, values) in series.iter().enumerate() {
+        let stroke = Stroke::new(1.5_f32, series_color(index, series.len()));
+        let x_step = rect.width() / (values.len().max(2) - 1) as f32;
+        let mut line = Vec::new();
+        for (sample_index, value) in values.iter().enumerate() {
+            match value {
+                Some(value) => {
+                    let y = if max > 0.0 { value / max } else { 0.0 };
+                    line.push(pos2(rect.left() + sample_index as f32 * x_step, rect.bottom() - y * rect.height()));
+                }
+                None => {
+                    painter.add(Shape::line(std::mem::take(&mut line), stroke));
+                }
+            }
+        }
+        painter.add(Shape::line(line, stroke));
+    }
+    ui.label(format!("Max {}", max));
+    ui.horizontal_wrapped(|ui| {
+        for (index, (name, values)) in names.iter().zip(series).enumerate() {
+            let latest = values.last().copied().flatten().unwrap_or(0.0);
+            ui.colored_label(series_color(index, series.len()), format!("{}: {}", name, latest));
+        }
+    });
+}
+
+fn series_color(index: usize, count: usize) -> Color32 {
+    Hsva::new(index as f32 / count.max(1) as f32, 0.7, 0.9, 1.0).into()
+}
@@ -5,23 +5,11 @@ extern crate core;
 
 mod lerp_test;
 
-use core::f32::consts::PI;
 use glam::{Vec2, Vec4, vec2, UVec3, vec4};
 use shared::*;
-use shared::pixel_view::*;
+use shared::simulation::*;
 use spirv_std::{glam, spirv};
-use spirv_std::glam::{IVec2, ivec2, UVec2, uvec2};
-// Note: This cfg is incorrect on its surface, it really should be "are we compiling with std", but
-// we tie #[no_std] above to the same condition, so it's fine.
-#[cfg(target_arch = "spirv")]
-use spirv_std::num_traits::Float;
-use spirv_std::num_traits::Pow;
-
-
-enum Bounds {
-    InsideBounds,
-    OutsideBounds,
-}
+use spirv_std::glam::{ivec2, uvec2};
 
 #[spirv(compute(threads(256, 1, 1)))]
 pub fn main_cs(
@@ -30,296 +18,179 @@ pub fn main_cs(
     #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] agents_buffer: &mut [Agent],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] agent_stats_buffer: &[AgentStats],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] trail_buffer: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] obstacle_buffer: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] occupancy_buffer: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] flow_field: &[Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)] interaction_buffer: &[TrailInteraction],
 ) {
     let agent_index = id.x as usize + id.y as usize * 256 + id.z as usize * 256 * 256;
     if agent_index >= agents_buffer.len() {
         return;
     }
     let agent = &mut agents_buffer[agent_index];
-    if agent.agent_type as usize >= NUM_AGENT_TYPES {
-        return;
-    }
-    let agent_stats = &agent_stats_buffer[agent.agent_type as usize];
-
-    let map_size = constants.map_size;
-    let random = hash((agent.y * map_size.x as f32 + agent.x) as u32 + hash(id.x));
-
-    // Sensor based on sensory data
-    let weight_forward = sense(trail_buffer, map_size, agent, &agent_stats, 0.0);
-    let weight_left = sense(trail_buffer, map_size, agent, &agent_stats, agent_stats.sensor_angle_spacing * PI / 180.0);
-    let weight_right = sense(trail_buffer, map_size, agent, &agent_stats, -agent_stats.sensor_angle_spacing * PI / 180.0);
-
-    let random_steer_strength = random as f32 / u32::MAX as f32;
-    let turn_speed = agent_stats.turn_speed * PI;
-    let turn_speed_avoidance = agent_stats.turn_speed_avoidance * PI;
-
-    match (weight_left, weight_forward, weight_right) {
-        (None, _, Some(_)) => {
-            agent.angle -= random_steer_strength * turn_speed_avoidance * constants.time_step;
-        }
-        (Some(_), _, None) => {
-            agent.angle += random_steer_strength * turn_speed_avoidance * constants.time_step;
-        }
-        (Some(_), None, Some(_)) => {
-            agent.angle += (random_steer_strength - 0.5) * 2.0 * turn_speed_avoidance * constants.time_step;
-        }
-        (None, _, None) => {
-            agent.angle += 0.0;
-        }
-        (Some(weight_left), Some(weight_forward), Some(weight_right)) => {
-            // If center is stronger than edges, continue forward
-            if weight_forward > weight_left && weight_forward > weight_right {
-                agent.angle += 0.0;
-            }
-            // If edges are stronger than center, pick a direction randomly
-            else if weight_left > weight_forward && weight_right > weight_forward {
-                agent.angle += (random_steer_strength - 0.5) * 2.0 * turn_speed * constants.time_step;
-            }
-            // If there's a gradient in one direction, turn that way
-            else if weight_right > weight_left {
-                agent.angle -= random_steer_strength * turn_speed * constants.time_step;
-            } else if weight_left > weight_right {
-                agent.angle += random_steer_strength * turn_speed * constants.time_step;
-            }
-        }
+    let mut map = TrailMap::new(constants, trail_buffer, obstacle_buffer, occupancy_buffer);
+    if update_agent(id.x, constants, agent, agent_stats_buffer, interaction_buffer, &mut map, flow_field) {
+        reproduce(agent_index, constants, agent_stats_buffer, obstacle_buffer, occupancy_buffer, agents_buffer);
     }
+}
 
-    // Render each pixel inbetween here and the end of the streak we move this frame
-    let mut num_steps = agent_stats.velocity * constants.time_step;
-    let step_size = 1.0;
-    let mut step_pos = vec2(agent.x, agent.y);
-    let bounds: Bounds = 'clamp_block: loop {
-        while num_steps > step_size {
-            step_pos = step_pos + vec2(agent.angle.cos(), agent.angle.sin()) * step_size;
-            let bounds = process_pixel(trail_buffer, map_size, &agent_stats, agent_stats_buffer, agent, step_pos.as_ivec2());
-            if let Bounds::OutsideBounds = bounds {
-                break 'clamp_block bounds;
-            }
-            num_steps -= step_size;
-        }
-        // If we didn't go outside the window, which is the normal case,
-        // num_steps is now smaller than 1.0
-        // Do the last little leap
-        let previous = step_pos;
-        step_pos = step_pos + vec2(agent.angle.cos(), agent.angle.sin()) * num_steps;
-        if previous.as_ivec2() != step_pos.as_ivec2() {
-            let bounds = process_pixel(trail_buffer, map_size, &agent_stats, agent_stats_buffer, agent, step_pos.as_ivec2());
-            break 'clamp_block bounds;
-        }
-        break 'clamp_block if is_inside_bounds(step_pos.as_ivec2(), constants.map_size) {
-            Bounds::InsideBounds
-        } else {
-            Bounds::OutsideBounds
-        };
+// DIFFUSE_GROUP_SIZE by DIFFUSE_GROUP_SIZE
+#[spirv(compute(threads(8, 8, 1)))]
+pub fn diffuse_cs(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(local_invocation_index)] local_index: u32,
+    #[spirv(workgroup_id)] group_id: UVec3,
+    #[spirv(push_constant)] constants: &ShaderConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] trail_stats: &[TrailStats],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] trail_buffer: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] output_buffer: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] obstacle_buffer: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] emitter_map: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] emitter_stats: &[EmitterStats],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)] reactions: &[Reaction],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)] flow_field: &[Vec2],
+    #[spirv(workgroup)] tile: &mut [u32; DIFFUSE_TILE_LEN],
+) {
+    let group_id = uvec2(group_id.x, group_id.y);
+    let trail_format = constants.trail_format.decode();
+    load_diffuse_tile(local_index, group_id, constants.map_size, trail_format, trail_buffer, tile);
+    // Every invocation has to get here, even the ones outside the map
+    unsafe { spirv_std::arch::workgroup_memory_barrier_with_group_sync() };
+    let mut input = TrailTile::new(tile, group_id, trail_buffer, obstacle_buffer, constants);
+    let buffers = DiffusionBuffers {
+        trail_stats,
+        emitter_map,
+        emitter_stats,
+        reactions,
+        flow_field,
     };
-    if let Bounds::OutsideBounds = bounds {
-        step_pos.x = f32::min(constants.map_size.x as f32 - 1.01, f32::max(0.01, step_pos.x));
-        step_pos.y = f32::min(constants.map_size.y as f32 - 1.01, f32::max(0.01, step_pos.y));
-        agent.angle = (random as f32 / u32::MAX as f32) * 2.0 * PI;
-    }
-
-    agent.x = step_pos.x;
-    agent.y = step_pos.y;
-
-    if agent_stats.timeout > 0.01 {
-        agent.countdown -= constants.time_step;
-        if agent.countdown <= 0.0 && agent_stats.timeout_conversion < NUM_AGENT_TYPES as u32 {
-            agent.agent_type = agent_stats.timeout_conversion;
-            agent.countdown = agent_stats_buffer[agent_stats.timeout_conversion as usize].timeout;
-        }
-    }
+    diffuse_pixel(uvec2(id.x, id.y), constants, &buffers, &mut input, output_buffer);
 }
 
-fn sense(trail_buffer: &mut [u32], map_size: UVec2, agent: &Agent, agent_stats: &AgentStats, angle_offset: f32) -> Option<f32> {
-    let sensor_angle = agent.angle + angle_offset;
-    let sensor_center = ivec2(
-        (agent.x + sensor_angle.cos() * agent_stats.sensor_offset) as i32,
-        (agent.y + sensor_angle.sin() * agent_stats.sensor_offset) as i32,
-    );
-    let mut sum = 0.0;
-
-    for offset_x in -1..=1 {
-        for offset_y in -1..=1 {
-            let pos = sensor_center + ivec2(offset_x, offset_y);
-
-            if is_inside_bounds(pos, map_size) {
-                let pixel = get_pixel(trail_buffer, map_size, pos.as_uvec2());
-                for i in 0..NUM_TRAIL_STATS {
-                    let channel_stats = &agent_stats.interaction_channels[i];
-                    sum += pixel.get_frac(i) * channel_stats.attraction;
-                }
-            }
-        }
-    }
-    if sum > agent_stats.avoidance_threshold {
-        None
-    } else {
-        Some(sum)
-    }
+#[spirv(compute(threads(8, 8, 1)))]
+pub fn mouse_cs(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] mouse_constants: &MouseConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] trail_buffer: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] obstacle_buffer: &mut [u32],
+) {
+    brush_pixel(uvec2(id.x, id.y), mouse_constants, trail_buffer, obstacle_buffer);
 }
 
-
-fn process_pixel(trail_buffer: &mut [u32], map_size: UVec2, agent_stats: &AgentStats, agent_stats_list: &[AgentStats], agent: &mut Agent, position: IVec2) -> Bounds {
-    if is_inside_bounds(position, map_size) {
-        let mut pixel = get_pixel(trail_buffer, map_size, position.as_uvec2());
-        for trail_index in 0..NUM_TRAIL_STATS {
-            let interaction = agent_stats.interaction_channels[trail_index];
-            let mut value_frac = pixel.get_frac(trail_index as usize) as f32;
-            if interaction.conversion_enabled != 0 && value_frac > interaction.conversion_threshold && interaction.conversion < NUM_AGENT_TYPES as u32 {
-                agent.agent_type = interaction.conversion;
-                agent.countdown = agent_stats_list[interaction.conversion as usize].timeout;
-            }
-            value_frac += interaction.addition;
-            pixel.set_frac(trail_index, f32::min(value_frac, 1.0));
-        }
-        Bounds::InsideBounds
-    } else {
-        Bounds::OutsideBounds
+#[spirv(compute(threads(256, 1, 1)))]
+pub fn count_agents_cs(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &ShaderConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] agents_buffer: &[Agent],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] statistics_buffer: &mut [u32],
+) {
+    let agent_index = id.x as usize + id.y as usize * 256 + id.z as usize * 256 * 256;
+    if agent_index >= agents_buffer.len() {
+        return;
     }
+    count_agent(&agents_buffer[agent_index], constants.num_agent_types, statistics_buffer);
 }
 
-/// rust-gpu does not support returning a reference in an option it seems.
-/// As a workaround, it is the callers responsibility to make sure position is within bounds
-pub fn get_pixel<'pixel>(trail_buffer: &'pixel mut [u32], map_size: UVec2, position: UVec2) -> PixelView<'pixel> {
-    // if is_inside_bounds(position, constants) {
-    let pixel_index = (position.y as usize * map_size.x as usize + position.x as usize) * 4;
-    // Some(
-    // Safety: Safe since we're mutably borrowing different indices, which means there's not aliasing of mutable references
-    unsafe {
-        let a = &mut trail_buffer[pixel_index] as *mut _;
-        let b = &mut trail_buffer[pixel_index + 1] as *mut _;
-        let c = &mut trail_buffer[pixel_index + 2] as *mut _;
-        let d = &mut trail_buffer[pixel_index + 3] as *mut _;
-        PixelView::new(&mut *a, &mut *b, &mut *c, &mut *d)
-    }
-    // } else {
-    //     None
-    // }
+#[spirv(compute(threads(8, 8, 1)))]
+pub fn sum_trails_cs(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &ShaderConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] trail_buffer: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] statistics_buffer: &mut [u32],
+) {
+    sum_trail_pixel(uvec2(id.x, id.y), constants, trail_buffer, statistics_buffer);
 }
 
-fn is_inside_bounds(position: IVec2, map_size: UVec2) -> bool {
-    position.x >= 0 && position.x < map_size.x as i32 && position.y >= 0 && position.y < map_size.y as i32
+#[spirv(compute(threads(256, 1, 1)))]
+pub fn sort_count_cs(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &ShaderConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] agents_buffer: &[Agent],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] bins: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] ranks: &mut [u32],
+) {
+    let agent_index = id.x as usize + id.y as usize * 256 + id.z as usize * 256 * 256;
+    if agent_index >= agents_buffer.len() {
+        return;
+    }
+    count_sort_bin(agent_index, agents_buffer, constants.map_size, bins, ranks);
 }
 
-fn is_inside_bounds_u(position: UVec2, map_size: UVec2) -> bool {
-    position.x < map_size.x as u32 && position.y < map_size.y as u32
+#[spirv(compute(threads(1, 1, 1)))]
+pub fn sort_scan_cs(
+    #[spirv(push_constant)] constants: &ShaderConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] bins: &mut [u32],
+) {
+    scan_sort_bins(bins, num_sort_bins(constants.map_size));
 }
 
-
-const ENABLE_BLUR: bool = true;
-#[spirv(compute(threads(8, 8, 1)))]
-pub fn diffuse_cs(
+#[spirv(compute(threads(256, 1, 1)))]
+pub fn sort_scatter_cs(
     #[spirv(global_invocation_id)] id: UVec3,
     #[spirv(push_constant)] constants: &ShaderConstants,
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] trail_stats: &[TrailStats],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] trail_buffer: &mut [u32],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] output_buffer: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] agents_buffer: &[Agent],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] bins: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] ranks: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] sorted_buffer: &mut [Agent],
 ) {
-    let pos = uvec2(id.x, id.y);
-    let map_size = constants.map_size;
-    if !is_inside_bounds_u(pos, map_size) {
+    let agent_index = id.x as usize + id.y as usize * 256 + id.z as usize * 256 * 256;
+    if agent_index >= agents_buffer.len() {
         return;
     }
-    for channel_index in 0..NUM_TRAIL_STATS {
-        let evaporation_speed = trail_stats[channel_index].evaporation_speed;
-        let pixel = get_pixel(trail_buffer, map_size, pos);
-        let mut value = pixel.get_frac(channel_index);
-
-        if ENABLE_BLUR {
-            let diffusion_speed = trail_stats[channel_index].diffusion_speed;
-            let mut sum = 0.0;
-            for offset_x in -1..=1 {
-                for offset_y in -1..=1 {
-                    let sample_pos = pos.as_ivec2() + ivec2(offset_x, offset_y);
-                    if is_inside_bounds(sample_pos, map_size) {
-                        let pixel = get_pixel(trail_buffer, map_size, sample_pos.as_uvec2());
-                        sum += pixel.get_frac(channel_index);
-                    }
-                }
-            }
-
-            let blur_result = sum / 9.0;
-            value = lerp(
-                value,
-                blur_result,
-                (diffusion_speed / 100.0) * constants.time_step,
-            );
-        }
-
-        let evaporation_this_tick = (evaporation_speed / 100.0) * constants.time_step;
-        let new_value = f32::max(0.0, value - evaporation_this_tick);
-        let mut output_pixel = get_pixel(output_buffer, map_size, pos);
-        output_pixel.set_frac(channel_index, new_value);
-    }
+    scatter_agent(agent_index, agents_buffer, constants.map_size, bins, ranks, sorted_buffer);
 }
 
-
-#[spirv(compute(threads(8, 8, 1)))]
-pub fn mouse_cs(
+#[spirv(compute(threads(256, 1, 1)))]
+pub fn spawn_cs(
     #[spirv(global_invocation_id)] id: UVec3,
-    #[spirv(push_constant)] mouse_constants: &MouseConstants,
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] trail_buffer: &mut [u32],
+    #[spirv(push_constant)] constants: &SpawnConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] agents_buffer: &mut [Agent],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] agent_stats_buffer: &[AgentStats],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] spawn_batches: &[SpawnBatch],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] density_buffer: &[u32],
 ) {
-    if mouse_constants.mouse_down != 0 {
-        let map_pos = uvec2(id.x, id.y);
-        if !is_inside_bounds_u(map_pos, mouse_constants.map_size) {
-            return;
-        }
-        let screen_pos = screen_from_map_pos(map_pos, mouse_constants.map_size, mouse_constants.screen_size);
-        let brush_size = (mouse_constants.brush_size as f32 / mouse_constants.map_size.x as f32) * mouse_constants.screen_size.x as f32;
-        if within_range(screen_pos.as_vec2(), mouse_constants.mouse_position, brush_size) {
-            let mut pixel = get_pixel(trail_buffer, mouse_constants.map_size, map_pos);
-            match mouse_constants.click_mode.decode() {
-                ClickMode::Disabled => {}
-                ClickMode::ShowMenu => {}
-                ClickMode::PaintTrail(trail_index) => {
-                    if (trail_index as usize) < NUM_TRAIL_STATS {
-                        pixel.set_frac(trail_index as usize, 1.0);
-                    }
-                }
-                ClickMode::ResetTrail(trail_index) => {
-                    if (trail_index as usize) < NUM_TRAIL_STATS {
-                        pixel.set_frac(trail_index as usize, 1.0);
-                    }
-                }
-                ClickMode::ResetAllTrails => {
-                    for i in 0..NUM_TRAIL_STATS {
-                        pixel.set(i, 0x00);
-                    }
-                }
-            }
-        }
+    let agent_index = id.x as usize + id.y as usize * 256 + id.z as usize * 256 * 256;
+    if agent_index >= constants.num_agents as usize {
+        return;
     }
+    agents_buffer[agent_index] = spawn_agent(agent_index as u32, constants, spawn_batches, agent_stats_buffer, density_buffer);
 }
 
-fn screen_from_map_pos(map_pos: UVec2, map_size: UVec2, screen_size: UVec2) -> UVec2 {
-    ((map_pos.as_vec2() / map_size.as_vec2()) * screen_size.as_vec2()).as_uvec2()
-}
-fn map_from_screen_pos(screen_pos: IVec2, screen_size: UVec2, map_size: UVec2) -> IVec2 {
-    ((screen_pos.as_vec2() / screen_size.as_vec2()) * map_size.as_vec2()).as_ivec2()
-}
-
-fn within_range(first: Vec2, second: Vec2, distance: f32) -> bool {
-    let square_distance = (first.x - second.x).pow(2) + (first.y - second.y).pow(2);
-    square_distance < distance.pow(2)
+#[spirv(compute(threads(256, 1, 1)))]
+pub fn adjust_cs(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &AdjustConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] agents_buffer: &mut [Agent],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] agent_stats_buffer: &[AgentStats],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] spawn_batches: &[SpawnBatch],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] counters: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] density_buffer: &[u32],
+) {
+    let agent_index = id.x as usize + id.y as usize * 256 + id.z as usize * 256 * 256;
+    if agent_index >= agents_buffer.len() {
+        return;
+    }
+    adjust_agent(agent_index, constants, spawn_batches, agent_stats_buffer, density_buffer, agents_buffer, counters);
 }
 
-
 #[spirv(fragment)]
 pub fn main_fs(
     #[spirv(frag_coord)] in_frag_coord: Vec4,
     #[spirv(push_constant)] constants: &ShaderConstants,
     #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] trail_stats: &[TrailStats],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] trail_buffer: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] obstacle_buffer: &[u32],
     output: &mut Vec4,
 ) {
     let screen_position = ivec2(in_frag_coord.x as i32, in_frag_coord.y as i32);
     let map_position = map_from_screen_pos(screen_position, constants.screen_size, constants.map_size);
-    if is_inside_bounds(map_position, constants.map_size) {
+    if is_inside_bounds(map_position, constants.map_size) && is_obstacle(obstacle_buffer, constants.map_size, map_position.as_uvec2()) {
+        *output = constants.obstacle_color.inner;
+    } else if is_inside_bounds(map_position, constants.map_size) {
         let map_position = map_position.as_uvec2();
-        let pixel = get_pixel(trail_buffer, constants.map_size, map_position);
+        let pixel = get_pixel(trail_buffer, constants.map_size, constants.trail_format.decode(), map_position);
         let mut color = constants.background_color.inner;
-        for i in 0..NUM_TRAIL_STATS {
+        for i in 0..constants.num_trails as usize {
             match trail_stats[i].color_mode.decode() {
                 ColorMode::Disabled => {}
                 ColorMode::Add => {
@@ -354,17 +225,3 @@ pub fn main_vs(#[spirv(vertex_index)] vert_idx: i32, #[spirv(position)] builtin_
 
     *builtin_pos = pos.extend(0.0).extend(1.0);
 }
-
-fn lerp(from: f32, to: f32, interpolation: f32) -> f32 {
-    from + (to - from) * interpolation
-}
-
-fn hash(mut state: u32) -> u32 {
-    state ^= 2747636419u32;
-    state *= 2654435769u32;
-    state ^= state >> 16;
-    state *= 2654435769u32;
-    state ^= state >> 16;
-    state *= 2654435769u32;
-    state
-}
@@ -2,6 +2,9 @@
 #![cfg_attr(target_arch = "spirv", no_std)]
 
 pub mod pixel_view;
+#[cfg(not(target_arch = "spirv"))]
+pub mod reference;
+pub mod simulation;
 use bytemuck::{Pod, Zeroable};
 use core::f32::consts::PI;
 use glam::{UVec2, Vec2, Vec3, Vec4, vec3};
@@ -58,10 +61,64 @@ pub enum SpawnMode {
     BoxFacingRandom {
         spawn_box: SpawnBox,
     },
+    // Positions drawn from the density image of the configuration, scaled to the map
+    FromImage {
+        heading: SpawnHeading,
+    },
 }
 
+// Initial heading of agents spawned with SpawnMode::FromImage
 #[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, PartialEq)]
+#[repr(C)]
+pub enum SpawnHeading {
+    Random,
+    // Towards denser parts of the image
+    Gradient,
+    // Towards the center of mass of the image
+    Centroid,
+}
+
+#[cfg(not(target_arch = "spirv"))]
+impl Display for SpawnHeading {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpawnHeading::Random => f.write_str("Random"),
+            SpawnHeading::Gradient => f.write_str("Along the gradient"),
+            SpawnHeading::Centroid => f.write_str("Facing the centroid"),
+        }
+    }
+}
+
+impl SpawnHeading {
+    pub const fn encode(self) -> SpawnHeadingEncoded {
+        let number = match self {
+            SpawnHeading::Random => 0,
+            SpawnHeading::Gradient => 1,
+            SpawnHeading::Centroid => 2,
+        };
+        SpawnHeadingEncoded(number)
+    }
+}
+
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Pod, Zeroable, PartialEq, Default)]
+#[repr(C)]
+pub struct SpawnHeadingEncoded(u32);
+
+impl SpawnHeadingEncoded {
+    pub fn decode(self) -> SpawnHeading {
+        match self.0 {
+            1 => SpawnHeading::Gradient,
+            2 => SpawnHeading::Centroid,
+            _ => SpawnHeading::Random,
+        }
+    }
+}
+
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
 pub struct SpawnBox {
     pub left: u32,
     pub top: u32,
@@ -102,6 +159,7 @@ impl Display for SpawnMode {
                 f.write_str("Circumference facing clockwise")
             }
             SpawnMode::BoxFacingRandom { .. } => f.write_str("Box"),
+            SpawnMode::FromImage { .. } => f.write_str("From image"),
         }
     }
 }
@@ -119,6 +177,7 @@ impl SpawnMode {
             SpawnMode::CircumferenceFacingRandom { distance } => Some(*distance),
             SpawnMode::CircumferenceFacingClockwise { distance } => Some(*distance),
             SpawnMode::BoxFacingRandom { .. } => None,
+            SpawnMode::FromImage { .. } => None,
         }
     }
     pub fn spawn_box(&self) -> Option<SpawnBox> {
@@ -127,6 +186,124 @@ impl SpawnMode {
             _ => None,
         }
     }
+
+    pub fn encode(self) -> SpawnModeEncoded {
+        let mut encoded = SpawnModeEncoded::zeroed();
+        encoded.kind = match self {
+            SpawnMode::EvenlyDistributed => 0,
+            SpawnMode::CenterFacingOutward => 1,
+            SpawnMode::PointFacingOutward { x, y } => {
+                encoded.x = x;
+                encoded.y = y;
+                2
+            }
+            SpawnMode::PointFacingClockwise { x, y, distance } => {
+                encoded.x = x;
+                encoded.y = y;
+                encoded.distance = distance;
+                3
+            }
+            SpawnMode::CircleFacingInward { max_distance } => {
+                encoded.distance = max_distance;
+                4
+            }
+            SpawnMode::CircumferenceFacingInward { distance } => {
+                encoded.distance = distance;
+                5
+            }
+            SpawnMode::CircumferenceFacingOutward { distance } => {
+                encoded.distance = distance;
+                6
+            }
+            SpawnMode::CircumferenceFacingRandom { distance } => {
+                encoded.distance = distance;
+                7
+            }
+            SpawnMode::CircumferenceFacingClockwise { distance } => {
+                encoded.distance = distance;
+                8
+            }
+            SpawnMode::BoxFacingRandom { spawn_box } => {
+                encoded.spawn_box = spawn_box;
+                9
+            }
+            SpawnMode::FromImage { heading } => {
+                encoded.heading = heading.encode();
+                10
+            }
+        };
+        encoded
+    }
+}
+
+// Fields a spawn mode doesn't have are zero
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct SpawnModeEncoded {
+    kind: u32,
+    x: u32,
+    y: u32,
+    distance: u32,
+    spawn_box: SpawnBox,
+    heading: SpawnHeadingEncoded,
+}
+
+impl SpawnModeEncoded {
+    pub fn decode(self) -> SpawnMode {
+        match self.kind {
+            1 => SpawnMode::CenterFacingOutward,
+            2 => SpawnMode::PointFacingOutward { x: self.x, y: self.y },
+            3 => SpawnMode::PointFacingClockwise { x: self.x, y: self.y, distance: self.distance },
+            4 => SpawnMode::CircleFacingInward { max_distance: self.distance },
+            5 => SpawnMode::CircumferenceFacingInward { distance: self.distance },
+            6 => SpawnMode::CircumferenceFacingOutward { distance: self.distance },
+            7 => SpawnMode::CircumferenceFacingRandom { distance: self.distance },
+            8 => SpawnMode::CircumferenceFacingClockwise { distance: self.distance },
+            9 => SpawnMode::BoxFacingRandom { spawn_box: self.spawn_box },
+            10 => SpawnMode::FromImage { heading: self.heading.decode() },
+            _ => SpawnMode::EvenlyDistributed,
+        }
+    }
+}
+
+// A run of num_agents consecutive agents of one type spawned the same way, see `simulation::spawn_agent`
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct SpawnBatch {
+    pub spawn_mode: SpawnModeEncoded,
+    pub first_agent: u32,
+    pub num_agents: u32,
+    pub agent_type: u32,
+    pub _padding: u32,
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct SpawnConstants {
+    pub map_size: UVec2,
+    // Length of the agents buffer, agents after the last batch are spare slots
+    pub num_agents: u32,
+    pub num_batches: u32,
+    pub seed: u32,
+    // Size of the density image of SpawnMode::FromImage, zero without one
+    pub density_size: UVec2,
+    // Center of mass of the density image, in its pixels
+    pub density_centroid: Vec2,
+    pub _padding: u32,
+}
+
+// Changes the number of agents of one type in a running simulation, see `simulation::adjust_agent`
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct AdjustConstants {
+    // spawn.num_agents new agents are spawned into inactive slots
+    pub spawn: SpawnConstants,
+    pub retired_type: u32,
+    // Agents of retired_type made inactive
+    pub num_retired: u32,
+    // Agents of the types below this are counted per type, zero counts none of them
+    pub num_counted_types: u32,
+    pub _padding: u32,
 }
 
 #[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
@@ -138,6 +315,9 @@ pub enum ClickMode {
     PaintTrail(u32),
     ResetTrail(u32),
     ResetAllTrails,
+    PaintObstacle,
+    EraseObstacle,
+    PlaceEmitter(u32),
 }
 
 #[cfg(not(target_arch = "spirv"))]
@@ -149,6 +329,9 @@ impl Display for ClickMode {
             ClickMode::PaintTrail(_) => f.write_str("Paint trail"),
             ClickMode::ResetTrail(_) => f.write_str("Reset trail"),
             ClickMode::ResetAllTrails => f.write_str("Reset all trails"),
+            ClickMode::PaintObstacle => f.write_str("Paint obstacle"),
+            ClickMode::EraseObstacle => f.write_str("Erase obstacle"),
+            ClickMode::PlaceEmitter(_) => f.write_str("Place emitter"),
         }
     }
 }
@@ -161,6 +344,9 @@ impl ClickMode {
             ClickMode::PaintTrail(trail_index) => 256 + trail_index,
             ClickMode::ResetTrail(trail_index) => 512 + trail_index,
             ClickMode::ResetAllTrails => 2,
+            ClickMode::PaintObstacle => 3,
+            ClickMode::EraseObstacle => 4,
+            ClickMode::PlaceEmitter(trail_index) => 768 + trail_index,
         };
         ClickModeEncoded(number)
     }
@@ -176,8 +362,11 @@ impl ClickModeEncoded {
             0 => ClickMode::Disabled,
             1 => ClickMode::ShowMenu,
             2 => ClickMode::ResetAllTrails,
+            3 => ClickMode::PaintObstacle,
+            4 => ClickMode::EraseObstacle,
             trail_index @ 256..=511 => ClickMode::PaintTrail(trail_index - 256),
             trail_index @ 512..=767 => ClickMode::ResetTrail(trail_index - 512),
+            trail_index @ 768..=1023 => ClickMode::PlaceEmitter(trail_index - 768),
             _ => ClickMode::Disabled,
         }
     }
@@ -237,6 +426,340 @@ impl ColorModeEncoded {
     }
 }
 
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq, Default)]
+#[repr(C)]
+pub enum BoundaryMode {
+    // Agents are clamped to the edge of the map and trails fade out along the borders
+    #[default]
+    Clamp,
+    // The map is a torus, anything leaving one edge enters on the opposite edge
+    Wrap,
+}
+
+#[cfg(not(target_arch = "spirv"))]
+impl Display for BoundaryMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoundaryMode::Clamp => f.write_str("Clamp"),
+            BoundaryMode::Wrap => f.write_str("Wrap around"),
+        }
+    }
+}
+
+impl BoundaryMode {
+    pub const fn encode(self) -> BoundaryModeEncoded {
+        let number = match self {
+            BoundaryMode::Clamp => 0,
+            BoundaryMode::Wrap => 1,
+        };
+        BoundaryModeEncoded(number)
+    }
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct BoundaryModeEncoded(u32);
+
+impl BoundaryModeEncoded {
+    pub fn decode(self) -> BoundaryMode {
+        match self.0 {
+            1 => BoundaryMode::Wrap,
+            _ => BoundaryMode::Clamp,
+        }
+    }
+}
+
+// How the channels of a pixel are stored in the trail buffer, see [`pixel_view`]
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq, Default)]
+#[repr(C)]
+pub enum TrailFormat {
+    // 16 channels of 8 bit fixed point, in the memory of the default
+    Fixed8,
+    // 8 channels of 15 bit fixed point in 16 bit halves
+    #[default]
+    Fixed16,
+    // 8 channels of half precision floats, more precision close to zero
+    Float16,
+    // Full precision floats, one int per channel
+    Float32 {
+        channels: u32,
+    },
+}
+
+#[cfg(not(target_arch = "spirv"))]
+impl Display for TrailFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrailFormat::Fixed8 => f.write_str("8 bit fixed, 16 channels"),
+            TrailFormat::Fixed16 => f.write_str("16 bit fixed, 8 channels"),
+            TrailFormat::Float16 => f.write_str("16 bit float, 8 channels"),
+            TrailFormat::Float32 { .. } => write!(f, "32 bit float, {} channels", self.num_channels()),
+        }
+    }
+}
+
+impl TrailFormat {
+    pub const fn encode(self) -> TrailFormatEncoded {
+        let mut encoded = TrailFormatEncoded { kind: 0, channels: 0 };
+        encoded.kind = match self {
+            TrailFormat::Fixed8 => 0,
+            TrailFormat::Fixed16 => 1,
+            TrailFormat::Float16 => 2,
+            TrailFormat::Float32 { channels } => {
+                encoded.channels = channels;
+                3
+            }
+        };
+        encoded
+    }
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct TrailFormatEncoded {
+    kind: u32,
+    channels: u32,
+}
+
+impl TrailFormatEncoded {
+    pub fn decode(self) -> TrailFormat {
+        match self.kind {
+            0 => TrailFormat::Fixed8,
+            2 => TrailFormat::Float16,
+            3 => TrailFormat::Float32 { channels: self.channels },
+            _ => TrailFormat::Fixed16,
+        }
+    }
+}
+
+// How agents add to the trail buffer
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq, Default)]
+#[repr(C)]
+pub enum DepositionMode {
+    // Plain read-modify-write, deposits get lost when agents hit the same pixel in the same step
+    #[default]
+    Fast,
+    // Compare-and-swap loops, every deposit lands no matter how the gpu schedules the agents
+    Atomic,
+}
+
+#[cfg(not(target_arch = "spirv"))]
+impl Display for DepositionMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DepositionMode::Fast => f.write_str("Fast"),
+            DepositionMode::Atomic => f.write_str("Atomic"),
+        }
+    }
+}
+
+impl DepositionMode {
+    pub const fn encode(self) -> DepositionModeEncoded {
+        let number = match self {
+            DepositionMode::Fast => 0,
+            DepositionMode::Atomic => 1,
+        };
+        DepositionModeEncoded(number)
+    }
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct DepositionModeEncoded(u32);
+
+impl DepositionModeEncoded {
+    pub fn decode(self) -> DepositionMode {
+        match self.0 {
+            1 => DepositionMode::Atomic,
+            _ => DepositionMode::Fast,
+        }
+    }
+}
+
+// Flow field that advects the trails and optionally pushes the agents
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq, Default)]
+#[repr(C)]
+pub enum FlowMode {
+    #[default]
+    None,
+    // The same direction everywhere
+    Uniform,
+    // Circles around a center point
+    Vortex,
+    // Divergence free swirls from the curl of a noise field
+    CurlNoise,
+    // Per pixel vectors loaded from an image
+    Image,
+}
+
+#[cfg(not(target_arch = "spirv"))]
+impl Display for FlowMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlowMode::None => f.write_str("None"),
+            FlowMode::Uniform => f.write_str("Uniform"),
+            FlowMode::Vortex => f.write_str("Vortex"),
+            FlowMode::CurlNoise => f.write_str("Curl noise"),
+            FlowMode::Image => f.write_str("Image"),
+        }
+    }
+}
+
+impl FlowMode {
+    pub const fn encode(self) -> FlowModeEncoded {
+        let number = match self {
+            FlowMode::None => 0,
+            FlowMode::Uniform => 1,
+            FlowMode::Vortex => 2,
+            FlowMode::CurlNoise => 3,
+            FlowMode::Image => 4,
+        };
+        FlowModeEncoded(number)
+    }
+}
+
+#[derive(Copy, Clone, Pod, Zeroable, Default)]
+#[repr(C)]
+pub struct FlowModeEncoded(u32);
+
+impl FlowModeEncoded {
+    pub fn decode(self) -> FlowMode {
+        match self.0 {
+            1 => FlowMode::Uniform,
+            2 => FlowMode::Vortex,
+            3 => FlowMode::CurlNoise,
+            4 => FlowMode::Image,
+            _ => FlowMode::None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Pod, Zeroable, Default)]
+#[repr(C)]
+pub struct FlowConstants {
+    pub mode: FlowModeEncoded,
+    // Speed of the flow in pixels per second
+    pub strength: f32,
+    // Direction of the uniform flow in radians
+    pub direction: f32,
+    // Center of the vortex as a fraction of the map size
+    pub center: Vec2,
+    // Size of the curl noise swirls is roughly 1 / noise_scale pixels
+    pub noise_scale: f32,
+    // How much of the flow is added to the agents' movement, zero leaves the agents alone
+    pub agent_drift: f32,
+    pub padding: f32,
+}
+
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq)]
+#[repr(C)]
+pub enum EdgeBehavior {
+    // Clamp to the edge and pick a new random heading
+    RandomAngle,
+    // Mirror the heading at the wall
+    Reflect,
+    // Remove the agent from the simulation
+    Absorb,
+}
+
+#[cfg(not(target_arch = "spirv"))]
+impl Display for EdgeBehavior {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EdgeBehavior::RandomAngle => f.write_str("Random angle"),
+            EdgeBehavior::Reflect => f.write_str("Reflect"),
+            EdgeBehavior::Absorb => f.write_str("Absorb"),
+        }
+    }
+}
+
+impl EdgeBehavior {
+    pub const fn encode(self) -> EdgeBehaviorEncoded {
+        let number = match self {
+            EdgeBehavior::RandomAngle => 0,
+            EdgeBehavior::Reflect => 1,
+            EdgeBehavior::Absorb => 2,
+        };
+        EdgeBehaviorEncoded(number)
+    }
+}
+
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Pod, Zeroable, PartialEq, Default)]
+#[repr(C)]
+pub struct EdgeBehaviorEncoded(u32);
+
+impl EdgeBehaviorEncoded {
+    pub fn decode(self) -> EdgeBehavior {
+        match self.0 {
+            1 => EdgeBehavior::Reflect,
+            2 => EdgeBehavior::Absorb,
+            _ => EdgeBehavior::RandomAngle,
+        }
+    }
+}
+
+// How an agent turns towards what its sensors pick up, when none of them are saturated
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq)]
+#[repr(C)]
+pub enum SteeringModel {
+    // Keep going if forward is strongest, otherwise turn a random amount up to turn_speed towards the stronger side
+    Classic,
+    // Jones's rule: always rotate by the full turn_speed towards the strongest sensor
+    Jones,
+    // Turn by the average side of the sensors, weighted by the softmax of their values times steering_gain
+    Softmax,
+    // Sage Jenson's rule: turn by turn_speed times the difference between the sides times steering_gain,
+    // multiplied by a random factor
+    Multiplicative,
+}
+
+#[cfg(not(target_arch = "spirv"))]
+impl Display for SteeringModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SteeringModel::Classic => f.write_str("Classic"),
+            SteeringModel::Jones => f.write_str("Jones"),
+            SteeringModel::Softmax => f.write_str("Softmax"),
+            SteeringModel::Multiplicative => f.write_str("Multiplicative (Sage Jenson)"),
+        }
+    }
+}
+
+impl SteeringModel {
+    pub const fn encode(self) -> SteeringModelEncoded {
+        let number = match self {
+            SteeringModel::Classic => 0,
+            SteeringModel::Jones => 1,
+            SteeringModel::Softmax => 2,
+            SteeringModel::Multiplicative => 3,
+        };
+        SteeringModelEncoded(number)
+    }
+}
+
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Pod, Zeroable, PartialEq, Default)]
+#[repr(C)]
+pub struct SteeringModelEncoded(u32);
+
+impl SteeringModelEncoded {
+    pub fn decode(self) -> SteeringModel {
+        match self.0 {
+            1 => SteeringModel::Jones,
+            2 => SteeringModel::Softmax,
+            3 => SteeringModel::Multiplicative,
+            _ => SteeringModel::Classic,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
 pub struct ShaderConstants {
@@ -244,9 +767,25 @@ pub struct ShaderConstants {
     pub map_size: UVec2,
     pub time: f32,
     pub time_step: f32,
-    pub padding_1: f32,
-    pub padding_2: f32,
+    pub boundary_mode: BoundaryModeEncoded,
+    // Together with frame, seeds the random numbers of the shaders
+    pub seed: u32,
+    // Number of compute steps since the agents were spawned
+    pub frame: u32,
+    // Number of used entries in the emitter stats buffer
+    pub num_emitters: u32,
+    // Number of used entries in the reaction buffer
+    pub num_reactions: u32,
+    pub deposition_mode: DepositionModeEncoded,
+    pub trail_format: TrailFormatEncoded,
+    // Number of entries in the agent stats buffer, agents of other types are skipped
+    pub num_agent_types: u32,
+    // Number of used channels of every pixel, at most the channels of the trail format.
+    // Also the length of a row of the interaction buffer
+    pub num_trails: u32,
     pub background_color: Color,
+    pub obstacle_color: Color,
+    pub flow: FlowConstants,
 }
 
 #[derive(Copy, Clone, Pod, Zeroable)]
@@ -259,30 +798,84 @@ pub struct MouseConstants {
     pub mouse_position: Vec2,
     pub last_mouse_position: Vec2,
     pub brush_size: f32,
-    pub _padding: f32,
+    pub trail_format: TrailFormatEncoded,
+    pub num_trails: u32,
 }
 
-pub const NUM_AGENT_TYPES: usize = 10;
+// Agents with this type are skipped by the simulation
+pub const INACTIVE_AGENT_TYPE: u32 = u32::MAX;
+// Slot taken by a child that is still being written, skipped like an inactive agent
+pub const CLAIMED_AGENT_TYPE: u32 = u32::MAX - 1;
+
+pub const MAX_SENSORS: usize = 8;
 
 #[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
-#[derive(Copy, Clone, PartialEq, Default, Pod, Zeroable)]
+#[derive(Copy, Clone, PartialEq, Pod, Zeroable)]
 #[repr(C)]
 pub struct AgentStats {
     // Pixels travelled per second
     pub velocity: f32,
     pub turn_speed: f32,
     pub turn_speed_avoidance: f32,
-    // Maximum value is 9.0
+    // Maximum value is the number of pixels in a sensor, 9.0 with the default kernel radius
     // Minimum value is 0.0
-    // Setting a value over 9 effectively disables avoidance of saturated trails
+    // Setting a value over the maximum effectively disables avoidance of saturated trails
     pub avoidance_threshold: f32,
     pub sensor_angle_spacing: f32,
     pub sensor_offset: f32,
+    // Between 1 and MAX_SENSORS
+    pub sensor_count: u32,
+    // Each sensor sums a square of (2 * radius + 1)^2 pixels
+    pub sensor_kernel_radius: u32,
+    // If non-zero, sensor i is placed at sensor_angles[i] (degrees, positive is to the left)
+    // and sensor_offsets[i], instead of spreading the sensors evenly with sensor_angle_spacing
+    pub custom_sensors: u32,
+    pub sensor_angles: [f32; MAX_SENSORS],
+    pub sensor_offsets: [f32; MAX_SENSORS],
     pub timeout: f32,
     pub timeout_conversion: u32,
-    pub interaction_channels: [TrailInteraction; NUM_TRAIL_STATS],
+    // The countdown of a new agent is timeout plus or minus up to this many seconds,
+    // so converted agents don't all time out together
+    pub timeout_variation: f32,
+    // What happens when the agent runs into the edge of the map or an obstacle
+    pub edge_behavior: EdgeBehaviorEncoded,
+    // Added to the sensed value for every sensor pixel covered by an obstacle
+    pub obstacle_attraction: f32,
+    // Energy of newly spawned agents
+    pub initial_energy: f32,
+    // Energy spent per pixel travelled
+    pub energy_cost: f32,
+    // If non-zero, agents die when their energy runs out
+    pub starvation_enabled: u32,
+    // If non-zero, agents with at least reproduction_energy split in two, if there is a free agent slot
+    pub reproduction_enabled: u32,
+    pub reproduction_energy: f32,
+    // If non-zero, agents of this type can't move into a pixel held by another such agent,
+    // like in the original Jones model
+    pub exclusive_occupancy: u32,
+    pub steering_model: SteeringModelEncoded,
+    // How sharply the Softmax and Multiplicative steering models react to differences between the sensors
+    pub steering_gain: f32,
+    // If non-zero, sensors interpolate between the pixels around them instead of reading the pixels they are in
+    pub bilinear_sensing: u32,
+    // If non-zero, trails are deposited into the four pixels around the agent, weighted by distance,
+    // and for every fraction of a pixel travelled instead of once per pixel entered
+    pub splat_deposition: u32,
 }
 
+impl Default for AgentStats {
+    fn default() -> Self {
+        Self {
+            sensor_count: 3,
+            sensor_kernel_radius: 1,
+            steering_gain: 1.0,
+            ..Zeroable::zeroed()
+        }
+    }
+}
+
+// How an agent type reacts to and adds to one trail.
+// The interaction buffer holds a row of num_trails of these per agent type, see [`simulation::InteractionRow`].
 #[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, PartialEq, Default, Pod, Zeroable)]
 #[repr(C)]
@@ -292,9 +885,65 @@ pub struct TrailInteraction {
     pub conversion_enabled: u32,
     pub conversion_threshold: f32,
     pub conversion: u32,
+    // If non-zero, instead of converting as soon as conversion_threshold is exceeded the agent converts
+    // with a chance of conversion_rate * concentration per second while above the threshold
+    pub conversion_rate: f32,
+    // Energy gained per second while standing on a saturated pixel of this trail
+    pub energy_gain: f32,
+    // Chemokinesis: velocity and turn_speed are scaled by 1.0 plus the sum of coefficient * concentration
+    // over the trails under the agent, never below zero. Negative values slow agents down in rich areas.
+    pub speed_coefficient: f32,
+    pub turn_coefficient: f32,
 }
 
-pub const NUM_TRAIL_STATS: usize = 6;
+// The trail format with the most channels has room for this many trails, see [`TrailFormat::num_channels`]
+pub const MAX_TRAILS: usize = pixel_view::MAX_CHANNELS as usize;
+
+// Size of the emitter stats buffer
+pub const MAX_EMITTERS: usize = 256;
+
+// Size of the reaction buffer
+pub const MAX_REACTIONS: usize = 16;
+// Used for the catalyst or target of a reaction that has none
+pub const NO_CHANNEL: u32 = u32::MAX;
+
+// Moves rate * source * catalyst^catalyst_exponent per second from trail source to trail target,
+// in every pixel during the diffuse pass. Reactions are applied in order.
+// Without a catalyst the reaction is linear, without a target the source just decays.
+// For example Gray-Scott's U + 2V -> 3V is source U, catalyst V with exponent 2.0 and target V.
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Reaction {
+    pub source: u32,
+    pub catalyst: u32,
+    pub catalyst_exponent: f32,
+    pub target: u32,
+    pub rate: f32,
+}
+
+impl Default for Reaction {
+    fn default() -> Self {
+        Self {
+            source: 0,
+            catalyst: NO_CHANNEL,
+            catalyst_exponent: 1.0,
+            target: NO_CHANNEL,
+            rate: 0.0,
+        }
+    }
+}
+
+// An emitter adds to a trail channel every step in the pixels it covers, or removes from it if rate is negative.
+// Which pixels are covered is stored in a separate emitter map, holding the emitter index + 1 per pixel.
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq, Default, Pod, Zeroable)]
+#[repr(C)]
+pub struct EmitterStats {
+    pub trail_index: u32,
+    // Fraction of a saturated pixel per second
+    pub rate: f32,
+}
 
 #[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, PartialEq, Pod, Zeroable)]
@@ -309,9 +958,101 @@ pub struct TrailStats {
     // Reaching 86% takes 1 second if set to 200%.
     // Reaching 63% takes 1 second if set to 100%.
     pub diffusion_speed: f32,
-    pub padding_1: f32,
+    // Fraction of the way to saturation the channel is pulled per second,
+    // the feed term of Gray-Scott style reactions
+    pub feed_rate: f32,
     pub color_mode: ColorModeEncoded,
     pub color: Color,
+    pub kernel: DiffusionKernel,
+}
+
+// Largest radius of any diffusion kernel, bounded by the workgroup memory diffuse_cs caches the trails in
+pub const MAX_KERNEL_RADIUS: u32 = 6;
+
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq)]
+#[repr(C)]
+pub enum KernelShape {
+    // No diffusion
+    None,
+    // Every pixel within the radius weighs the same
+    Box,
+    Gaussian,
+    // Gaussian stretched along a direction
+    Anisotropic,
+}
+
+#[cfg(not(target_arch = "spirv"))]
+impl Display for KernelShape {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KernelShape::None => f.write_str("None"),
+            KernelShape::Box => f.write_str("Box"),
+            KernelShape::Gaussian => f.write_str("Gaussian"),
+            KernelShape::Anisotropic => f.write_str("Anisotropic"),
+        }
+    }
+}
+
+impl KernelShape {
+    pub const fn encode(self) -> KernelShapeEncoded {
+        let number = match self {
+            KernelShape::None => 0,
+            KernelShape::Box => 1,
+            KernelShape::Gaussian => 2,
+            KernelShape::Anisotropic => 3,
+        };
+        KernelShapeEncoded(number)
+    }
+}
+
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Pod, Zeroable, PartialEq)]
+#[repr(C)]
+pub struct KernelShapeEncoded(u32);
+
+impl KernelShapeEncoded {
+    pub fn decode(self) -> KernelShape {
+        match self.0 {
+            1 => KernelShape::Box,
+            2 => KernelShape::Gaussian,
+            3 => KernelShape::Anisotropic,
+            _ => KernelShape::None,
+        }
+    }
+}
+
+// The blur the diffusion of a trail lerps towards
+#[cfg_attr(not(target_arch = "spirv"), derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct DiffusionKernel {
+    pub shape: KernelShapeEncoded,
+    // Radius of KernelShape::Box in pixels
+    pub radius: u32,
+    // Standard deviation of the gaussian shapes in pixels, they are cut off at three sigma
+    pub sigma: f32,
+    // Direction of the long axis of KernelShape::Anisotropic in degrees
+    pub direction: f32,
+    // Sigma across the long axis of KernelShape::Anisotropic, relative to sigma
+    pub ratio: f32,
+    pub padding_1: f32,
+    pub padding_2: f32,
+    pub padding_3: f32,
+}
+
+impl DiffusionKernel {
+    // The original fixed blur
+    pub const BOX_3X3: Self = Self {
+        shape: KernelShape::Box.encode(),
+        radius: 1,
+        sigma: 1.0,
+        direction: 0.0,
+        ratio: 0.5,
+        padding_1: 0.0,
+        padding_2: 0.0,
+        padding_3: 0.0,
+    };
 }
 
 #[derive(Copy, Clone, PartialEq, Pod, Zeroable)]
@@ -388,6 +1129,7 @@ pub struct Agent {
     pub angle: f32,
     pub agent_type: u32,
     pub countdown: f32,
+    pub energy: f32,
 }
 
 pub fn saturate(x: f32) -> f32 {
@@ -437,6 +1179,71 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_boundary_mode_encoding() {
+        for value in 0..u16::MAX as u32 {
+            let reference = BoundaryModeEncoded(value);
+            let boundary_mode = reference.decode();
+            let encoded = boundary_mode.encode();
+            match boundary_mode {
+                BoundaryMode::Clamp => {}
+                _ => {
+                    assert_eq!(reference.0, encoded.0)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_spawn_mode_encoding() {
+        let spawn_modes = [
+            SpawnMode::EvenlyDistributed,
+            SpawnMode::CenterFacingOutward,
+            SpawnMode::PointFacingOutward { x: 1, y: 2 },
+            SpawnMode::PointFacingClockwise { x: 1, y: 2, distance: 3 },
+            SpawnMode::CircleFacingInward { max_distance: 4 },
+            SpawnMode::CircumferenceFacingInward { distance: 5 },
+            SpawnMode::CircumferenceFacingOutward { distance: 6 },
+            SpawnMode::CircumferenceFacingRandom { distance: 7 },
+            SpawnMode::CircumferenceFacingClockwise { distance: 8 },
+            SpawnMode::BoxFacingRandom { spawn_box: SpawnBox { left: 1, top: 2, box_width: 3, box_height: 4 } },
+            SpawnMode::FromImage { heading: SpawnHeading::Random },
+            SpawnMode::FromImage { heading: SpawnHeading::Gradient },
+            SpawnMode::FromImage { heading: SpawnHeading::Centroid },
+        ];
+        for spawn_mode in spawn_modes {
+            assert!(spawn_mode.encode().decode() == spawn_mode);
+        }
+    }
+
+    #[test]
+    fn test_trail_format_encoding() {
+        let trail_formats = [
+            TrailFormat::Fixed8,
+            TrailFormat::Fixed16,
+            TrailFormat::Float16,
+            TrailFormat::Float32 { channels: 3 },
+        ];
+        for trail_format in trail_formats {
+            assert!(trail_format.encode().decode() == trail_format);
+        }
+    }
+
+    #[test]
+    fn test_edge_behavior_encoding() {
+        for value in 0..u16::MAX as u32 {
+            let reference = EdgeBehaviorEncoded(value);
+            let edge_behavior = reference.decode();
+            let encoded = edge_behavior.encode();
+            match edge_behavior {
+                EdgeBehavior::RandomAngle => {}
+                _ => {
+                    assert_eq!(reference.0, encoded.0)
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_color_mode_encoding() {
         for value in 0..u16::MAX as u32 {
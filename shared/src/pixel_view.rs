@@ -1,69 +1,143 @@
+use crate::TrailFormat;
+
 pub struct PixelView<'storage> {
-    storage_one: &'storage mut u32,
-    storage_two: &'storage mut u32,
-    storage_three: &'storage mut u32,
-    storage_four: &'storage mut u32,
+    storage: &'storage mut [u32],
+    // Index of the first int of the pixel
+    offset: usize,
+    format: TrailFormat,
 }
 
 impl<'storage> PixelView<'storage> {
-    pub fn new(
-        storage_one: &'storage mut u32,
-        storage_two: &'storage mut u32,
-        storage_three: &'storage mut u32,
-        storage_four: &'storage mut u32,
-    ) -> Self {
+    pub fn new(storage: &'storage mut [u32], offset: usize, format: TrailFormat) -> Self {
         PixelView {
-            storage_one,
-            storage_two,
-            storage_three,
-            storage_four,
+            storage,
+            offset,
+            format,
         }
     }
+    /// Raw bits of a channel
     pub fn get(&self, index: usize) -> u32 {
-        match index {
-            0 => (*self.storage_one >> 0) & 0xFFFF,
-            1 => (*self.storage_one >> 16) & 0xFFFF,
-            2 => (*self.storage_two >> 0) & 0xFFFF,
-            3 => (*self.storage_two >> 16) & 0xFFFF,
-            4 => (*self.storage_three >> 0) & 0xFFFF,
-            5 => (*self.storage_three >> 16) & 0xFFFF,
-            6 => (*self.storage_four >> 0) & 0xFFFF,
-            _ => (*self.storage_four >> 16) & 0xFFFF,
-        }
+        let (int_index, shift) = self.format.channel_location(index);
+        (self.storage[self.offset + int_index] >> shift) & self.format.mask()
     }
     pub fn get_frac(&self, index: usize) -> f32 {
-        frac_from_int(self.get(index))
+        self.format.frac_from_int(self.get(index))
     }
     pub fn set(&mut self, index: usize, value: u32) {
-        match index {
-            0 => *self.storage_one = *self.storage_one & 0xFFFF0000 | (value & 0xFFFF) << 0,
-            1 => *self.storage_one = *self.storage_one & 0x0000FFFF | ((value & 0xFFFF) << 16),
-            2 => *self.storage_two = *self.storage_two & 0xFFFF0000 | (value & 0xFFFF) << 0,
-            3 => *self.storage_two = *self.storage_two & 0x0000FFFF | ((value & 0xFFFF) << 16),
-            4 => *self.storage_three = *self.storage_three & 0xFFFF0000 | (value & 0xFFFF) << 0,
-            5 => *self.storage_three = *self.storage_three & 0x0000FFFF | ((value & 0xFFFF) << 16),
-            6 => *self.storage_four = *self.storage_four & 0xFFFF0000 | (value & 0xFFFF) << 0,
-            _ => *self.storage_four = *self.storage_four & 0x0000FFFF | ((value & 0xFFFF) << 16),
-        }
+        let (int_index, shift) = self.format.channel_location(index);
+        let mask = self.format.mask() << shift;
+        let int = &mut self.storage[self.offset + int_index];
+        *int = (*int & !mask) | ((value << shift) & mask);
     }
     pub fn set_frac(&mut self, index: usize, value: f32) {
-        self.set(index, int_from_frac(value));
+        self.set(index, self.format.int_from_frac(value));
     }
 }
 
-pub const NUM_CHANNELS: u32 = 8;
-pub const INTS_PER_PIXEL: u32 = NUM_CHANNELS.div_ceil(2);
-pub const PIXEL_MAX: u32 = 2u32.pow(15) - 1;
+// Channels of the format with the most of them, Fixed8
+pub const MAX_CHANNELS: u32 = 16;
+// Ints per pixel of the largest format. Also the most channels of Float32,
+// as the diffuse tile has to fit in the workgroup memory wgpu guarantees.
+pub const MAX_INTS_PER_PIXEL: u32 = 8;
+pub const FIXED_8_MAX: u32 = 2u32.pow(8) - 1;
+pub const FIXED_16_MAX: u32 = 2u32.pow(15) - 1;
+
+impl TrailFormat {
+    pub fn bits(self) -> u32 {
+        match self {
+            TrailFormat::Fixed8 => 8,
+            TrailFormat::Fixed16 | TrailFormat::Float16 => 16,
+            TrailFormat::Float32 { .. } => 32,
+        }
+    }
+
+    /// Number of trails a pixel has room for
+    pub fn num_channels(self) -> u32 {
+        match self {
+            TrailFormat::Fixed8 => MAX_CHANNELS,
+            TrailFormat::Fixed16 | TrailFormat::Float16 => 8,
+            TrailFormat::Float32 { channels } => channels.clamp(1, MAX_INTS_PER_PIXEL),
+        }
+    }
+
+    pub fn ints_per_pixel(self) -> u32 {
+        (self.num_channels() * self.bits()).div_ceil(32)
+    }
 
-pub fn frac_from_int(value: u32) -> f32 {
-    value as f32 / PIXEL_MAX as f32
+    /// Mask of the bits of a channel, before shifting it into place
+    pub fn mask(self) -> u32 {
+        if self.bits() == 32 {
+            u32::MAX
+        } else {
+            (1 << self.bits()) - 1
+        }
+    }
+
+    /// Which int of a pixel holds the channel, and how far the channel is shifted within it
+    pub fn channel_location(self, index: usize) -> (usize, u32) {
+        let channels_per_int = (32 / self.bits()) as usize;
+        (index / channels_per_int, (index % channels_per_int) as u32 * self.bits())
+    }
+
+    pub fn frac_from_int(self, value: u32) -> f32 {
+        match self {
+            TrailFormat::Fixed8 => value as f32 / FIXED_8_MAX as f32,
+            TrailFormat::Fixed16 => value as f32 / FIXED_16_MAX as f32,
+            TrailFormat::Float16 => f32_from_f16_bits(value),
+            TrailFormat::Float32 { .. } => f32::from_bits(value),
+        }
+    }
+
+    /// Values are clamped between 0.0 and 1.0 in every format
+    pub fn int_from_frac(self, value: f32) -> u32 {
+        match self {
+            TrailFormat::Fixed8 => fixed_from_frac(value, FIXED_8_MAX),
+            TrailFormat::Fixed16 => fixed_from_frac(value, FIXED_16_MAX),
+            TrailFormat::Float16 => f16_bits_from_f32(clamp_frac(value)),
+            TrailFormat::Float32 { .. } => clamp_frac(value).to_bits(),
+        }
+    }
 }
 
-pub fn int_from_frac(value: f32) -> u32 {
+fn fixed_from_frac(value: f32, max: u32) -> u32 {
     if value > 0.999 {
-        PIXEL_MAX
+        max
+    } else {
+        // Negative values saturate to zero
+        (value * max as f32) as u32
+    }
+}
+
+fn clamp_frac(value: f32) -> f32 {
+    value.clamp(0.0, 1.0)
+}
+
+/// Truncating conversion of a positive f32 to the bits of an f16.
+/// Written out by hand so the cpu and the shaders round the same way.
+pub fn f16_bits_from_f32(value: f32) -> u32 {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    if exponent >= 31 {
+        // Largest finite f16
+        0x7BFF
+    } else if exponent > 0 {
+        ((exponent as u32) << 10) | ((bits >> 13) & 0x3FF)
+    } else if exponent >= -10 {
+        // Subnormal, the implicit leading one becomes explicit
+        ((bits & 0x7FFFFF) | 0x800000) >> (14 - exponent) as u32
+    } else {
+        0
+    }
+}
+
+/// Inverse of [`f16_bits_from_f32`], ignoring the sign
+pub fn f32_from_f16_bits(bits: u32) -> f32 {
+    let exponent = (bits >> 10) & 0x1F;
+    let mantissa = bits & 0x3FF;
+    if exponent == 0 {
+        mantissa as f32 / 16777216.0
     } else {
-        (value * PIXEL_MAX as f32) as u32
+        f32::from_bits(((exponent + 127 - 15) << 23) | (mantissa << 13))
     }
 }
 
@@ -73,11 +147,8 @@ mod test {
 
     #[test]
     fn test_set() {
-        let mut a = 0x12345678;
-        let mut b = 0x98765432;
-        let mut c = 0x12345678;
-        let mut d = 0x98765432;
-        let mut pixel = PixelView::new([&mut a, &mut b, &mut c, &mut d]);
+        let mut storage = [0x12345678, 0x98765432, 0x12345678, 0x98765432];
+        let mut pixel = PixelView::new(&mut storage, 0, TrailFormat::Fixed16);
         pixel.set(0, 0x2BCD);
         pixel.set(1, 0x4DEF);
         pixel.set(2, 0xFEDC);
@@ -98,26 +169,67 @@ mod test {
 
     #[test]
     fn test_set_frac() {
-        let mut a = 0x12345678;
-        let mut b = 0x98765432;
-        let mut c = 0x12345678;
-        let mut d = 0x98765432;
-        let mut pixel = PixelView::new([&mut a, &mut b, &mut c, &mut d]);
-        pixel.set_frac(0, 0.5);
-        pixel.set_frac(1, 0.25);
-        pixel.set_frac(2, 0.75);
-        pixel.set_frac(3, 0.125);
-        pixel.set_frac(4, 0.5);
-        pixel.set_frac(5, 0.25);
-        pixel.set_frac(6, 0.75);
-        pixel.set_frac(7, 0.125);
-        assert!(f32::abs(pixel.get_frac(0) - 0.5) < 0.01);
-        assert!(f32::abs(pixel.get_frac(1) - 0.25) < 0.01);
-        assert!(f32::abs(pixel.get_frac(2) - 0.75) < 0.01);
-        assert!(f32::abs(pixel.get_frac(3) - 0.125) < 0.01);
-        assert!(f32::abs(pixel.get_frac(4) - 0.5) < 0.01);
-        assert!(f32::abs(pixel.get_frac(5) - 0.25) < 0.01);
-        assert!(f32::abs(pixel.get_frac(6) - 0.75) < 0.01);
-        assert!(f32::abs(pixel.get_frac(7) - 0.125) < 0.01);
+        for format in [TrailFormat::Fixed8, TrailFormat::Fixed16, TrailFormat::Float16, TrailFormat::Float32 { channels: 5 }] {
+            // Garbage around the pixel must survive
+            let mut storage = [0x12345678; 2 + MAX_INTS_PER_PIXEL as usize];
+            let ints_per_pixel = format.ints_per_pixel() as usize;
+            let mut pixel = PixelView::new(&mut storage, 1, format);
+            let values = [0.5, 0.25, 0.75, 0.125].repeat(MAX_CHANNELS as usize / 4)[..format.num_channels() as usize].to_vec();
+            for (index, value) in values.iter().enumerate() {
+                pixel.set_frac(index, *value);
+            }
+            for (index, value) in values.iter().enumerate() {
+                assert!(f32::abs(pixel.get_frac(index) - value) < 0.01);
+            }
+            pixel.set_frac(0, 2.0);
+            assert_eq!(pixel.get_frac(0), 1.0);
+            pixel.set_frac(0, -1.0);
+            assert_eq!(pixel.get_frac(0), 0.0);
+            assert_eq!(storage[0], 0x12345678);
+            assert_eq!(storage[1 + ints_per_pixel], 0x12345678);
+        }
+    }
+
+    #[test]
+    fn test_precision() {
+        let step = |format: TrailFormat| format.frac_from_int(format.int_from_frac(0.001));
+        assert_eq!(step(TrailFormat::Fixed8), 0.0);
+        assert!(f32::abs(step(TrailFormat::Fixed16) - 0.001) < 0.0001);
+        assert!(f32::abs(step(TrailFormat::Float16) - 0.001) < 0.000001);
+        assert_eq!(step(TrailFormat::Float32 { channels: 1 }), 0.001);
+    }
+
+    #[test]
+    fn test_num_channels() {
+        assert_eq!((TrailFormat::Fixed8.num_channels(), TrailFormat::Fixed8.ints_per_pixel()), (16, 4));
+        assert_eq!((TrailFormat::Fixed16.num_channels(), TrailFormat::Fixed16.ints_per_pixel()), (8, 4));
+        assert_eq!((TrailFormat::Float16.num_channels(), TrailFormat::Float16.ints_per_pixel()), (8, 4));
+        assert_eq!(TrailFormat::Float32 { channels: 3 }.ints_per_pixel(), 3);
+        assert_eq!(TrailFormat::Float32 { channels: 0 }.num_channels(), 1);
+        assert_eq!(TrailFormat::Float32 { channels: 100 }.num_channels(), MAX_INTS_PER_PIXEL);
+    }
+
+    #[test]
+    fn test_f16() {
+        for value in [0.0, 1.0, 0.5, 0.375, 2.0f32.powi(-14), 2.0f32.powi(-20), 0.000061] {
+            let bits = f16_bits_from_f32(value);
+            // Agrees with the bit layout of IEEE half floats
+            assert_eq!(bits, half_bits(value), "{}", value);
+            assert!(f32::abs(f32_from_f16_bits(bits) - value) <= value / 1024.0, "{}", value);
+        }
+    }
+
+    // Reference conversion through f64 arithmetic, for values that are positive and in range
+    fn half_bits(value: f32) -> u32 {
+        if value == 0.0 {
+            return 0;
+        }
+        let exponent = (value as f64).log2().floor() as i32;
+        if exponent < -14 {
+            (value as f64 * 2f64.powi(24)).floor() as u32
+        } else {
+            let mantissa = ((value as f64 / 2f64.powi(exponent) - 1.0) * 1024.0).floor() as u32;
+            (((exponent + 15) as u32) << 10) | mantissa
+        }
     }
 }
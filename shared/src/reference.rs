@@ -0,0 +1,1263 @@
+//! CPU reference implementation of `main_cs`, `diffuse_cs`, `mouse_cs`, `spawn_cs`, `adjust_cs` and the statistics shaders.
+//! Runs the kernels from [`crate::simulation`] sequentially over the same buffer layouts the GPU uses,
+//! so behaviour can be checked on machines without a GPU.
+use crate::pixel_view::PixelView;
+use crate::simulation::*;
+use crate::*;
+use glam::{UVec2, Vec2, uvec2};
+
+pub struct ReferenceSimulation {
+    pub map_size: UVec2,
+    pub boundary_mode: BoundaryMode,
+    pub deposition_mode: DepositionMode,
+    // Change with `set_trail_format`, which resizes the trail buffer
+    trail_format: TrailFormat,
+    pub seed: u32,
+    // Incremented by every step, like the frame counter of the program
+    pub frame: u32,
+    pub agents: Vec<Agent>,
+    pub agent_stats: Vec<AgentStats>,
+    // One row of trail_stats.len() interactions per agent type
+    pub interactions: Vec<TrailInteraction>,
+    pub trail_stats: Vec<TrailStats>,
+    pub trail_buffer: Vec<u32>,
+    // One u32 per pixel, non-zero pixels are obstacles
+    pub obstacle_buffer: Vec<u32>,
+    // One u32 per pixel, the invocation id + 1 of the exclusive agent holding the pixel or zero
+    pub occupancy_buffer: Vec<u32>,
+    // One u32 per pixel, the index + 1 of the emitter covering the pixel or zero
+    pub emitter_map: Vec<u32>,
+    pub emitter_stats: Vec<EmitterStats>,
+    pub reactions: Vec<Reaction>,
+    pub flow: FlowConstants,
+    // One vector per pixel, used by FlowMode::Image
+    pub flow_field: Vec<Vec2>,
+    // Mirrors the intermediate buffer in SlotDiffuse
+    diffuse_input_buffer: Vec<u32>,
+    // Mirrors the workgroup memory of diffuse_cs
+    diffuse_tile: Vec<u32>,
+}
+
+impl ReferenceSimulation {
+    pub fn new(map_size: UVec2, agents: Vec<Agent>, agent_stats: Vec<AgentStats>, interactions: Vec<TrailInteraction>, trail_stats: Vec<TrailStats>) -> Self {
+        assert_eq!(interactions.len(), agent_stats.len() * trail_stats.len());
+        assert!(trail_stats.len() <= MAX_TRAILS);
+        let trail_format = TrailFormat::default();
+        let trail_buffer = vec![0; (map_size.x * map_size.y * trail_format.ints_per_pixel()) as usize];
+        Self {
+            map_size,
+            boundary_mode: BoundaryMode::Clamp,
+            deposition_mode: DepositionMode::Fast,
+            trail_format,
+            seed: 0,
+            frame: 0,
+            agents,
+            agent_stats,
+            interactions,
+            trail_stats,
+            diffuse_input_buffer: trail_buffer.clone(),
+            diffuse_tile: vec![0; DIFFUSE_TILE_LEN],
+            trail_buffer,
+            obstacle_buffer: vec![0; (map_size.x * map_size.y) as usize],
+            occupancy_buffer: vec![0; (map_size.x * map_size.y) as usize],
+            emitter_map: vec![0; (map_size.x * map_size.y) as usize],
+            emitter_stats: Vec::new(),
+            reactions: Vec::new(),
+            flow: FlowConstants::default(),
+            flow_field: vec![Vec2::ZERO; (map_size.x * map_size.y) as usize],
+        }
+    }
+
+    pub fn trail_format(&self) -> TrailFormat {
+        self.trail_format
+    }
+
+    /// Clears the trails, like recreating the buffers in the program does
+    pub fn set_trail_format(&mut self, trail_format: TrailFormat) {
+        self.trail_format = trail_format;
+        self.trail_buffer = vec![0; (self.map_size.x * self.map_size.y * trail_format.ints_per_pixel()) as usize];
+        self.diffuse_input_buffer = self.trail_buffer.clone();
+    }
+
+    /// It is the callers responsibility to make sure position is within bounds
+    pub fn pixel(&mut self, position: UVec2) -> PixelView<'_> {
+        get_pixel(&mut self.trail_buffer, self.map_size, self.trail_format, position)
+    }
+
+    pub fn shader_constants(&self, time: f32, time_step: f32) -> ShaderConstants {
+        assert!(self.trail_stats.len() <= self.trail_format.num_channels() as usize);
+        ShaderConstants {
+            screen_size: self.map_size,
+            map_size: self.map_size,
+            time,
+            time_step,
+            boundary_mode: self.boundary_mode.encode(),
+            seed: self.seed,
+            frame: self.frame,
+            num_emitters: self.emitter_stats.len() as u32,
+            num_reactions: self.reactions.len() as u32,
+            deposition_mode: self.deposition_mode.encode(),
+            trail_format: self.trail_format.encode(),
+            num_agent_types: self.agent_stats.len() as u32,
+            num_trails: self.trail_stats.len() as u32,
+            background_color: Color::new(0.0, 0.0, 0.0, 1.0),
+            obstacle_color: Color::new(1.0, 1.0, 1.0, 1.0),
+            flow: self.flow,
+        }
+    }
+
+    /// Equivalent of dispatching `main_cs` over every agent
+    pub fn update_agents(&mut self, constants: &ShaderConstants) {
+        for agent_index in 0..self.agents.len() {
+            let agent = &mut self.agents[agent_index];
+            let mut map = TrailMap::new(constants, &mut self.trail_buffer, &self.obstacle_buffer, &mut self.occupancy_buffer);
+            if update_agent(agent_index as u32, constants, agent, &self.agent_stats, &self.interactions, &mut map, &self.flow_field) {
+                reproduce(agent_index, constants, &self.agent_stats, &self.obstacle_buffer, &mut self.occupancy_buffer, &mut self.agents);
+            }
+        }
+    }
+
+    /// Equivalent of the copy to the intermediate buffer followed by dispatching `diffuse_cs`
+    pub fn diffuse(&mut self, constants: &ShaderConstants) {
+        self.diffuse_input_buffer.copy_from_slice(&self.trail_buffer);
+        let num_groups = (self.map_size + DIFFUSE_GROUP_SIZE - 1) / DIFFUSE_GROUP_SIZE;
+        for group_y in 0..num_groups.y {
+            for group_x in 0..num_groups.x {
+                let group_id = uvec2(group_x, group_y);
+                for local_index in 0..DIFFUSE_GROUP_SIZE * DIFFUSE_GROUP_SIZE {
+                    load_diffuse_tile(local_index, group_id, self.map_size, self.trail_format, &self.diffuse_input_buffer, &mut self.diffuse_tile);
+                }
+                let mut input = TrailTile::new(&mut self.diffuse_tile, group_id, &mut self.diffuse_input_buffer, &self.obstacle_buffer, constants);
+                let buffers = DiffusionBuffers {
+                    trail_stats: &self.trail_stats,
+                    emitter_map: &self.emitter_map,
+                    emitter_stats: &self.emitter_stats,
+                    reactions: &self.reactions,
+                    flow_field: &self.flow_field,
+                };
+                for local_y in 0..DIFFUSE_GROUP_SIZE {
+                    for local_x in 0..DIFFUSE_GROUP_SIZE {
+                        let pos = group_id * DIFFUSE_GROUP_SIZE + uvec2(local_x, local_y);
+                        diffuse_pixel(pos, constants, &buffers, &mut input, &mut self.trail_buffer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Equivalent of dispatching `mouse_cs`
+    pub fn brush(&mut self, mouse_constants: &MouseConstants) {
+        for y in 0..self.map_size.y {
+            for x in 0..self.map_size.x {
+                brush_pixel(uvec2(x, y), mouse_constants, &mut self.trail_buffer, &mut self.obstacle_buffer);
+            }
+        }
+    }
+
+    /// Equivalent of `AgentSpawner::spawn`, dispatching `spawn_cs` over a new agents buffer of `constants.num_agents`
+    pub fn spawn(&mut self, constants: &SpawnConstants, spawn_batches: &[SpawnBatch], density_buffer: &[u32]) {
+        self.agents = (0..constants.num_agents)
+            .map(|agent_index| spawn_agent(agent_index, constants, spawn_batches, &self.agent_stats, density_buffer))
+            .collect();
+        self.occupancy_buffer.fill(0);
+    }
+
+    /// Equivalent of one dispatch of `adjust_cs` by `AgentSpawner::adjust`, which clears the occupancy buffer
+    /// after retiring agents, as they don't free their pixels.
+    /// Returns the counters, the number of inactive slots and of agents of the retired type before the adjustment,
+    /// followed by the agents of every counted type.
+    pub fn adjust(&mut self, constants: &AdjustConstants, spawn_batches: &[SpawnBatch], density_buffer: &[u32]) -> Vec<u32> {
+        let mut counters = vec![0; ADJUST_AGENT_OFFSET + constants.num_counted_types as usize];
+        for agent_index in 0..self.agents.len() {
+            adjust_agent(agent_index, constants, spawn_batches, &self.agent_stats, density_buffer, &mut self.agents, &mut counters);
+        }
+        if constants.num_retired > 0 {
+            self.occupancy_buffer.fill(0);
+        }
+        counters
+    }
+
+    /// Equivalent of `AgentSorter::sort`, dispatching `sort_count_cs`, `sort_scan_cs` and `sort_scatter_cs`
+    /// and clearing the occupancy buffer, as the agents' ids change
+    pub fn sort_agents(&mut self) {
+        let mut bins = vec![0; num_sort_bins(self.map_size) as usize];
+        let mut ranks = vec![0; self.agents.len()];
+        for agent_index in 0..self.agents.len() {
+            count_sort_bin(agent_index, &self.agents, self.map_size, &mut bins, &mut ranks);
+        }
+        scan_sort_bins(&mut bins, num_sort_bins(self.map_size));
+        let mut sorted = self.agents.clone();
+        for agent_index in 0..self.agents.len() {
+            scatter_agent(agent_index, &self.agents, self.map_size, &bins, &ranks, &mut sorted);
+        }
+        self.agents = sorted;
+        self.occupancy_buffer.fill(0);
+    }
+
+    /// Equivalent of dispatching `count_agents_cs` and `sum_trails_cs` into a cleared statistics buffer
+    pub fn statistics(&mut self, constants: &ShaderConstants) -> Vec<u32> {
+        let mut statistics_buffer = vec![0; STATISTICS_AGENT_OFFSET + self.agent_stats.len()];
+        for agent in &self.agents {
+            count_agent(agent, constants.num_agent_types, &mut statistics_buffer);
+        }
+        for y in 0..self.map_size.y {
+            for x in 0..self.map_size.x {
+                sum_trail_pixel(uvec2(x, y), constants, &mut self.trail_buffer, &mut statistics_buffer);
+            }
+        }
+        statistics_buffer
+    }
+
+    /// One compute step, in the same order as `Program::on_loop`
+    pub fn step(&mut self, constants: &ShaderConstants) {
+        self.update_agents(constants);
+        self.diffuse(constants);
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// FNV-1a over the trail buffer and the raw bytes of every agent
+    pub fn checksum(&self) -> u64 {
+        let agent_bytes: &[u8] = bytemuck::cast_slice(&self.agents);
+        let trail_bytes: &[u8] = bytemuck::cast_slice(&self.trail_buffer);
+        agent_bytes.iter().chain(trail_bytes.iter()).fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::reference::*;
+    use core::f32::consts::PI;
+    use glam::vec2;
+
+    const MAP_SIZE: UVec2 = uvec2(64, 48);
+    const TIME_STEP: f32 = 1.0 / 60.0;
+    const NUM_TRAILS: usize = 6;
+
+    // Only the first two agent types are used
+    fn agent_stats() -> Vec<AgentStats> {
+        let first = AgentStats {
+            velocity: 40.0,
+            turn_speed: 80.0,
+            turn_speed_avoidance: 30.0,
+            avoidance_threshold: 20.0,
+            sensor_angle_spacing: 60.0,
+            sensor_offset: 9.0,
+            ..Default::default()
+        };
+        let second = AgentStats {
+            velocity: 25.0,
+            timeout: 2.0,
+            timeout_conversion: 0,
+            ..first
+        };
+        vec![first, second]
+    }
+
+    fn interactions() -> Vec<TrailInteraction> {
+        let mut interactions = vec![TrailInteraction::default(); 2 * NUM_TRAILS];
+        interactions[0] = TrailInteraction {
+            attraction: 1.0,
+            addition: 0.2,
+            ..Default::default()
+        };
+        interactions[1] = TrailInteraction {
+            attraction: -1.0,
+            conversion_enabled: 1,
+            conversion_threshold: 0.8,
+            conversion: 1,
+            ..Default::default()
+        };
+        interactions[NUM_TRAILS + 1] = TrailInteraction {
+            attraction: 1.0,
+            addition: 0.2,
+            ..Default::default()
+        };
+        interactions
+    }
+
+    fn trail_stats() -> Vec<TrailStats> {
+        vec![
+            TrailStats {
+                evaporation_speed: 50.0,
+                diffusion_speed: 480.0,
+                feed_rate: 0.0,
+                color_mode: ColorMode::Add.encode(),
+                color: Color::new(1.0, 1.0, 1.0, 1.0),
+                kernel: DiffusionKernel::BOX_3X3,
+            };
+            NUM_TRAILS
+        ]
+    }
+
+    fn agents(num_agents: u32) -> Vec<Agent> {
+        let center = MAP_SIZE.as_vec2() / 2.0;
+        (0..num_agents)
+            .map(|index| {
+                let angle = index as f32 / num_agents as f32 * 2.0 * PI;
+                let position = center + vec2(angle.cos(), angle.sin()) * 10.0;
+                Agent {
+                    x: position.x,
+                    y: position.y,
+                    angle,
+                    agent_type: index % 2,
+                    countdown: 0.0,
+                    energy: 0.0,
+                }
+            })
+            .collect()
+    }
+
+    fn run(steps: u32) -> ReferenceSimulation {
+        run_seeded(steps, 0)
+    }
+
+    fn run_seeded(steps: u32, seed: u32) -> ReferenceSimulation {
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, agents(200), agent_stats(), interactions(), trail_stats());
+        simulation.seed = seed;
+        for step in 0..steps {
+            let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+            simulation.step(&constants);
+        }
+        simulation
+    }
+
+    #[test]
+    fn test_is_deterministic() {
+        assert_eq!(run(20).checksum(), run(20).checksum());
+    }
+
+    #[test]
+    fn test_seed_changes_run() {
+        assert_eq!(run_seeded(20, 7).checksum(), run_seeded(20, 7).checksum());
+        assert_ne!(run_seeded(100, 7).checksum(), run_seeded(100, 8).checksum());
+    }
+
+    #[test]
+    fn test_agents_stay_inside_map() {
+        let simulation = run(300);
+        for agent in &simulation.agents {
+            assert!(is_inside_bounds(vec2(agent.x, agent.y).as_ivec2(), MAP_SIZE));
+        }
+    }
+
+    #[test]
+    fn test_golden_checksum() {
+        assert_eq!(run(300).checksum(), GOLDEN_300_STEPS);
+    }
+
+    #[test]
+    fn test_atomic_deposition_matches_fast_path() {
+        // Sequentially there are no races, so both paths have to deposit the same
+        let run_with = |deposition_mode| {
+            let mut simulation = ReferenceSimulation::new(MAP_SIZE, agents(200), agent_stats(), interactions(), trail_stats());
+            simulation.deposition_mode = deposition_mode;
+            for step in 0..100 {
+                let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+                simulation.step(&constants);
+            }
+            simulation.checksum()
+        };
+        assert_eq!(run_with(DepositionMode::Fast), run_with(DepositionMode::Atomic));
+    }
+
+    #[test]
+    fn test_diffuse_golden_checksum() {
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats());
+        let mouse_constants = MouseConstants {
+            screen_size: MAP_SIZE,
+            map_size: MAP_SIZE,
+            click_mode: ClickMode::PaintTrail(2).encode(),
+            mouse_down: 1,
+            mouse_position: vec2(20.0, 20.0),
+            last_mouse_position: vec2(20.0, 20.0),
+            brush_size: 6.0,
+            trail_format: simulation.trail_format().encode(),
+            num_trails: NUM_TRAILS as u32,
+        };
+        simulation.brush(&mouse_constants);
+        let painted = simulation.trail_buffer.iter().filter(|value| **value != 0).count();
+        assert_eq!(painted, 109);
+        for step in 0..200 {
+            let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+            simulation.diffuse(&constants);
+        }
+        assert_eq!(simulation.checksum(), GOLDEN_DIFFUSE_200_STEPS);
+    }
+
+    #[test]
+    fn test_wrap_moves_agent_across_edge() {
+        let agent = Agent {
+            x: 62.5,
+            y: 20.5,
+            angle: 0.0,
+            agent_type: 0,
+            countdown: 0.0,
+            energy: 0.0,
+        };
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![agent], agent_stats(), interactions(), trail_stats());
+        simulation.boundary_mode = BoundaryMode::Wrap;
+        let constants = simulation.shader_constants(0.0, 0.1);
+        simulation.update_agents(&constants);
+        let agent = simulation.agents[0];
+        assert!((agent.x - 2.5).abs() < 0.001);
+        assert!((agent.y - 20.5).abs() < 0.001);
+        assert_eq!(agent.angle, 0.0);
+        for x in [63, 0, 1, 2] {
+            let pixel = simulation.pixel(uvec2(x, 20));
+            assert!(pixel.get_frac(0) > 0.1);
+        }
+    }
+
+    fn run_into_edge(edge_behavior: EdgeBehavior) -> Agent {
+        let agent = Agent {
+            x: 62.5,
+            y: 20.5,
+            angle: 0.0,
+            agent_type: 0,
+            countdown: 0.0,
+            energy: 0.0,
+        };
+        let mut agent_stats = agent_stats();
+        agent_stats[0].edge_behavior = edge_behavior.encode();
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![agent], agent_stats, interactions(), trail_stats());
+        let constants = simulation.shader_constants(0.0, 0.1);
+        simulation.update_agents(&constants);
+        simulation.agents[0]
+    }
+
+    #[test]
+    fn test_edge_behaviors() {
+        let agent = run_into_edge(EdgeBehavior::Reflect);
+        assert_eq!(agent.angle, PI);
+        assert_eq!(agent.agent_type, 0);
+        assert!(agent.x < MAP_SIZE.x as f32 - 1.0);
+
+        let agent = run_into_edge(EdgeBehavior::Absorb);
+        assert_eq!(agent.agent_type, INACTIVE_AGENT_TYPE);
+
+        let agent = run_into_edge(EdgeBehavior::RandomAngle);
+        assert_eq!(agent.agent_type, 0);
+        assert_ne!(agent.angle, 0.0);
+    }
+
+    fn lone_agent(energy: f32) -> Agent {
+        Agent {
+            x: 20.5,
+            y: 20.5,
+            angle: 0.0,
+            agent_type: 0,
+            countdown: 0.0,
+            energy,
+        }
+    }
+
+    #[test]
+    fn test_runtime_counts() {
+        // One agent type depositing into the last of MAX_TRAILS trails, which only Fixed8 has room for
+        let mut interactions = vec![TrailInteraction::default(); MAX_TRAILS];
+        interactions[MAX_TRAILS - 1].addition = 0.5;
+        let mut trail_stats = trail_stats();
+        trail_stats.resize(MAX_TRAILS, trail_stats[0]);
+        let unknown = Agent {
+            agent_type: 1,
+            ..lone_agent(0.0)
+        };
+        let agents = vec![lone_agent(0.0), unknown];
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, agents, vec![agent_stats()[0]], interactions, trail_stats);
+        simulation.set_trail_format(TrailFormat::Fixed8);
+        let constants = simulation.shader_constants(0.0, 0.1);
+        simulation.update_agents(&constants);
+        assert!(simulation.agents[0].x > 21.0);
+        assert!(simulation.pixel(uvec2(22, 20)).get_frac(MAX_TRAILS - 1) > 0.4);
+        // Agents of types past the end of the agent stats are skipped
+        assert_eq!(simulation.agents[1].x, 20.5);
+    }
+
+    #[test]
+    fn test_starvation() {
+        let mut agent_stats = agent_stats();
+        agent_stats[0].starvation_enabled = 1;
+        agent_stats[0].energy_cost = 0.1;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![lone_agent(1.0)], agent_stats, interactions(), trail_stats());
+        for step in 0..14 {
+            let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+            simulation.step(&constants);
+        }
+        assert_eq!(simulation.agents[0].agent_type, 0);
+        for step in 14..16 {
+            let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+            simulation.step(&constants);
+        }
+        assert_eq!(simulation.agents[0].agent_type, INACTIVE_AGENT_TYPE);
+    }
+
+    #[test]
+    fn test_conversion_rate() {
+        let mut agent_stats = agent_stats();
+        agent_stats[1].timeout_variation = 0.5;
+        let run = |conversion_rate: f32| {
+            let mut interactions = interactions();
+            interactions[1].conversion_rate = conversion_rate;
+            let agents = agents(16).into_iter().map(|agent| Agent { agent_type: 0, ..agent }).collect();
+            let mut simulation = ReferenceSimulation::new(MAP_SIZE, agents, agent_stats.clone(), interactions, trail_stats());
+            for y in 0..MAP_SIZE.y {
+                for x in 0..MAP_SIZE.x {
+                    simulation.pixel(uvec2(x, y)).set_frac(1, 1.0);
+                }
+            }
+            let constants = simulation.shader_constants(0.0, TIME_STEP);
+            simulation.update_agents(&constants);
+            simulation.agents
+        };
+        // Above the threshold, but too unlikely to convert within one step
+        assert!(run(0.001).iter().all(|agent| agent.agent_type == 0));
+        // Certain to convert, with countdowns spread around the timeout
+        let converted = run(120.0);
+        assert!(converted.iter().all(|agent| agent.agent_type == 1));
+        assert!(converted.iter().all(|agent| agent.countdown >= 1.5 && agent.countdown <= 2.5));
+        assert!(converted.iter().any(|agent| agent.countdown != converted[0].countdown));
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut agents = agents(10);
+        agents[0].agent_type = INACTIVE_AGENT_TYPE;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, agents, agent_stats(), interactions(), trail_stats());
+        for x in 0..10 {
+            simulation.pixel(uvec2(x, 0)).set_frac(2, 1.0);
+            simulation.pixel(uvec2(x, 1)).set_frac(2, 0.5);
+        }
+        let constants = simulation.shader_constants(0.0, TIME_STEP);
+        let statistics = simulation.statistics(&constants);
+        // Inactive agents aren't counted
+        assert_eq!(&statistics[STATISTICS_AGENT_OFFSET..], &[4, 5]);
+        assert_eq!(statistics[0], 0);
+        let total = statistics[2] as f32 / TRAIL_TOTAL_SCALE;
+        assert!((total - 15.0).abs() < 0.1);
+    }
+
+    fn no_density() -> SpawnConstants {
+        SpawnConstants {
+            map_size: MAP_SIZE,
+            num_agents: 0,
+            num_batches: 0,
+            seed: 0,
+            density_size: UVec2::ZERO,
+            density_centroid: Vec2::ZERO,
+            _padding: 0,
+        }
+    }
+
+    /// Spawns 1000 agents from a density image with the given values
+    fn spawn_from_image(values: &[u32], density_size: UVec2, density_centroid: Vec2, heading: SpawnHeading) -> Vec<Agent> {
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats());
+        let density_buffer = values
+            .iter()
+            .scan(0, |total, value| {
+                *total += value;
+                Some(*total)
+            })
+            .collect::<Vec<_>>();
+        let spawn_batch = SpawnBatch {
+            spawn_mode: SpawnMode::FromImage { heading }.encode(),
+            first_agent: 0,
+            num_agents: 1000,
+            agent_type: 0,
+            _padding: 0,
+        };
+        let constants = SpawnConstants { num_agents: 1000, num_batches: 1, density_size, density_centroid, ..no_density() };
+        simulation.spawn(&constants, &[spawn_batch], &density_buffer);
+        simulation.agents
+    }
+
+    #[test]
+    fn test_spawn_from_image() {
+        // Pixels of 16 by 24 on the map, a quarter of the density in the top right one and the rest in the bottom left one
+        let values = [0, 0, 0, 1, 3, 0, 0, 0];
+        let centroid = (vec2(3.5, 0.5) + vec2(0.5, 1.5) * 3.0) / 4.0;
+        let agents = spawn_from_image(&values, uvec2(4, 2), centroid, SpawnHeading::Centroid);
+        let top_right = agents.iter().filter(|agent| agent.x >= 48.0 && agent.y < 24.0).count();
+        let bottom_left = agents.iter().filter(|agent| agent.x < 16.0 && agent.y >= 24.0).count();
+        assert_eq!(top_right + bottom_left, 1000);
+        assert!((top_right as f32 - 250.0).abs() < 50.0);
+        for agent in &agents {
+            let to_centroid = (centroid * vec2(16.0, 24.0) - vec2(agent.x, agent.y)).normalize();
+            assert!(vec2(agent.angle.cos(), agent.angle.sin()).dot(to_centroid) > 0.999);
+        }
+
+        // Getting denser to the right everywhere
+        let agents = spawn_from_image(&[1, 2, 4], uvec2(3, 1), Vec2::ZERO, SpawnHeading::Gradient);
+        assert!(agents.iter().all(|agent| agent.angle == 0.0));
+
+        // Without an image, spawns over the whole map
+        let agents = spawn_from_image(&[], UVec2::ZERO, Vec2::ZERO, SpawnHeading::Random);
+        assert!(agents.iter().any(|agent| agent.x < 32.0) && agents.iter().any(|agent| agent.x >= 32.0));
+    }
+
+    #[test]
+    fn test_spawn_agents() {
+        let mut agent_stats = agent_stats();
+        agent_stats[1].timeout_variation = 0.5;
+        agent_stats[1].initial_energy = 3.0;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats, interactions(), trail_stats());
+        let batch = |spawn_mode: SpawnMode, first_agent, agent_type| SpawnBatch {
+            spawn_mode: spawn_mode.encode(),
+            first_agent,
+            num_agents: 1000,
+            agent_type,
+            _padding: 0,
+        };
+        let spawn_box = SpawnBox { left: 10, top: 5, box_width: 20, box_height: 10 };
+        let spawn_batches = [
+            batch(SpawnMode::EvenlyDistributed, 0, 0),
+            batch(SpawnMode::CircumferenceFacingInward { distance: 10 }, 1000, 0),
+            batch(SpawnMode::BoxFacingRandom { spawn_box }, 2000, 1),
+        ];
+        // Followed by 10 spare slots
+        let constants = SpawnConstants { map_size: MAP_SIZE, num_agents: 3010, num_batches: 3, seed: 0, ..no_density() };
+        simulation.spawn(&constants, &spawn_batches, &[]);
+        let agents = &simulation.agents;
+        let center = MAP_SIZE.as_vec2() / 2.0;
+
+        // Spread over the whole map, in every direction
+        let evenly_distributed = &agents[..1000];
+        assert!(evenly_distributed.iter().all(|agent| agent.x >= 0.0 && agent.x <= MAP_SIZE.x as f32 && agent.y >= 0.0 && agent.y <= MAP_SIZE.y as f32));
+        let mean_position = evenly_distributed.iter().map(|agent| vec2(agent.x, agent.y)).sum::<Vec2>() / 1000.0;
+        assert!((mean_position - center).length() < 2.0);
+        let mean_heading = evenly_distributed.iter().map(|agent| vec2(agent.angle.cos(), agent.angle.sin())).sum::<Vec2>() / 1000.0;
+        assert!(mean_heading.length() < 0.1);
+
+        // On the circle, heading for its center
+        for agent in &agents[1000..2000] {
+            let offset = vec2(agent.x, agent.y) - center;
+            assert!((offset.length() - 10.0).abs() < 0.01);
+            assert!((vec2(agent.angle.cos(), agent.angle.sin()).dot(offset / 10.0) + 1.0).abs() < 0.01);
+        }
+
+        // In the box, with the stats of the second agent type
+        let in_box = &agents[2000..3000];
+        for agent in in_box {
+            assert!(agent.x >= 10.0 && agent.x <= 30.0 && agent.y >= 5.0 && agent.y <= 15.0);
+            assert_eq!(agent.agent_type, 1);
+            assert_eq!(agent.energy, 3.0);
+            assert!((agent.countdown - 2.0).abs() <= 0.5);
+        }
+        let mean_countdown = in_box.iter().map(|agent| agent.countdown).sum::<f32>() / 1000.0;
+        assert!((mean_countdown - 2.0).abs() < 0.05);
+
+        assert!(agents[3000..].iter().all(|agent| agent.agent_type == INACTIVE_AGENT_TYPE));
+    }
+
+    #[test]
+    fn test_adjust_agents() {
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats());
+        let spawn_batch = SpawnBatch {
+            spawn_mode: SpawnMode::EvenlyDistributed.encode(),
+            first_agent: 0,
+            num_agents: 100,
+            agent_type: 0,
+            _padding: 0,
+        };
+        // 100 agents of the first type and 10 spare slots
+        let spawn_constants = SpawnConstants { map_size: MAP_SIZE, num_agents: 110, num_batches: 1, seed: 0, ..no_density() };
+        simulation.spawn(&spawn_constants, &[spawn_batch], &[]);
+        let count = |simulation: &ReferenceSimulation, agent_type| simulation.agents.iter().filter(|agent| agent.agent_type == agent_type).count();
+
+        let retire = AdjustConstants {
+            spawn: SpawnConstants { num_agents: 0, num_batches: 0, ..spawn_constants },
+            retired_type: 0,
+            num_retired: 30,
+            num_counted_types: 0,
+            _padding: 0,
+        };
+        assert_eq!(simulation.adjust(&retire, &[spawn_batch], &[]), [10, 100]);
+        assert_eq!(count(&simulation, 0), 70);
+        let remaining = simulation.agents.clone();
+
+        // More than the free slots, the rest is left out
+        let add = AdjustConstants {
+            spawn: SpawnConstants { num_agents: 50, num_batches: 1, seed: 1, ..spawn_constants },
+            retired_type: INACTIVE_AGENT_TYPE,
+            num_retired: 0,
+            num_counted_types: 0,
+            _padding: 0,
+        };
+        let spawn_box = SpawnBox { left: 10, top: 5, box_width: 20, box_height: 10 };
+        let added_batch = SpawnBatch {
+            spawn_mode: SpawnMode::BoxFacingRandom { spawn_box }.encode(),
+            num_agents: 50,
+            agent_type: 1,
+            ..spawn_batch
+        };
+        assert_eq!(simulation.adjust(&add, &[added_batch], &[]), [40, 0]);
+        assert_eq!(count(&simulation, 0), 70);
+        assert_eq!(count(&simulation, 1), 40);
+        assert_eq!(simulation.agents.len(), 110);
+        for (agent, before) in simulation.agents.iter().zip(&remaining) {
+            if before.agent_type == 0 {
+                assert_eq!(bytemuck::bytes_of(agent), bytemuck::bytes_of(before));
+            } else {
+                assert!(agent.x >= 10.0 && agent.x <= 30.0 && agent.y >= 5.0 && agent.y <= 15.0);
+            }
+        }
+
+        // Spawning and retiring nothing only counts the inactive slots and the agents per type
+        simulation.agents[3].agent_type = INACTIVE_AGENT_TYPE;
+        let count_only = AdjustConstants {
+            spawn: SpawnConstants { num_agents: 0, num_batches: 0, ..spawn_constants },
+            num_counted_types: 2,
+            ..add
+        };
+        let agent_count = |agent_type| count(&simulation, agent_type) as u32;
+        let expected = [1, 0, agent_count(0), agent_count(1)];
+        assert_eq!(simulation.adjust(&count_only, &[], &[]), expected);
+        assert_eq!(count(&simulation, INACTIVE_AGENT_TYPE), 1);
+    }
+
+    #[test]
+    fn test_sort_agents() {
+        let mut simulation = run(20);
+        simulation.agents[3].agent_type = INACTIVE_AGENT_TYPE;
+        let mut before = simulation.agents.iter().map(|agent| (agent.x.to_bits(), agent.y.to_bits(), agent.agent_type)).collect::<Vec<_>>();
+        simulation.sort_agents();
+        let bins = simulation.agents.iter().map(|agent| sort_bin(agent, MAP_SIZE)).collect::<Vec<_>>();
+        assert!(bins.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(simulation.agents.last().unwrap().agent_type, INACTIVE_AGENT_TYPE);
+        // Same agents, different order
+        let mut after = simulation.agents.iter().map(|agent| (agent.x.to_bits(), agent.y.to_bits(), agent.agent_type)).collect::<Vec<_>>();
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+        // Tiles are numbered along a Morton curve
+        assert_eq!(sort_bin(&Agent { x: 16.0, y: 0.0, ..simulation.agents[0] }, MAP_SIZE), 1);
+        assert_eq!(sort_bin(&Agent { x: 0.0, y: 16.0, ..simulation.agents[0] }, MAP_SIZE), 2);
+        assert_eq!(sort_bin(&Agent { x: 16.0, y: 16.0, ..simulation.agents[0] }, MAP_SIZE), 3);
+        assert_eq!(sort_bin(&Agent { x: 32.0, y: 0.0, ..simulation.agents[0] }, MAP_SIZE), 4);
+    }
+
+    #[test]
+    fn test_energy_gain() {
+        let mut agent_stats = agent_stats();
+        agent_stats[0].starvation_enabled = 1;
+        let mut interactions = interactions();
+        interactions[0].energy_gain = 2.0;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![lone_agent(1.0)], agent_stats, interactions, trail_stats());
+        for y in 0..MAP_SIZE.y {
+            for x in 0..MAP_SIZE.x {
+                simulation.pixel(uvec2(x, y)).set_frac(0, 1.0);
+            }
+        }
+        let constants = simulation.shader_constants(0.0, TIME_STEP);
+        simulation.update_agents(&constants);
+        assert!((simulation.agents[0].energy - (1.0 + 2.0 * TIME_STEP)).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_chemokinesis() {
+        let mut interactions = interactions();
+        interactions[0].speed_coefficient = -0.5;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![lone_agent(1.0)], agent_stats(), interactions, trail_stats());
+        for y in 0..MAP_SIZE.y {
+            for x in 0..MAP_SIZE.x {
+                simulation.pixel(uvec2(x, y)).set_frac(0, 1.0);
+            }
+        }
+        // The trail is even, so the agent goes straight at half its velocity
+        let constants = simulation.shader_constants(0.0, TIME_STEP);
+        simulation.update_agents(&constants);
+        assert!((simulation.agents[0].x - (20.5 + 0.5 * 40.0 * TIME_STEP)).abs() < 0.0001);
+        assert_eq!(simulation.agents[0].y, 20.5);
+    }
+
+    #[test]
+    fn test_reproduction() {
+        let mut agent_stats = agent_stats();
+        agent_stats[0].reproduction_enabled = 1;
+        agent_stats[0].reproduction_energy = 6.0;
+        let inactive = Agent {
+            agent_type: INACTIVE_AGENT_TYPE,
+            ..lone_agent(0.0)
+        };
+        let agents = vec![lone_agent(10.0), inactive, inactive, inactive];
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, agents, agent_stats, interactions(), trail_stats());
+        let constants = simulation.shader_constants(0.0, TIME_STEP);
+        simulation.update_agents(&constants);
+        let alive = simulation.agents.iter().filter(|agent| agent.agent_type == 0).collect::<Vec<_>>();
+        assert_eq!(alive.len(), 2);
+        for agent in alive {
+            assert_eq!(agent.energy, 5.0);
+        }
+
+        // Without a free slot nothing happens
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![lone_agent(10.0)], simulation.agent_stats, interactions(), trail_stats());
+        simulation.update_agents(&constants);
+        assert_eq!(simulation.agents[0].energy, 10.0);
+    }
+
+    #[test]
+    fn test_exclusive_reproduction() {
+        let mut agent_stats = agent_stats();
+        agent_stats[0].reproduction_enabled = 1;
+        agent_stats[0].reproduction_energy = 6.0;
+        agent_stats[0].exclusive_occupancy = 1;
+        let inactive = Agent {
+            agent_type: INACTIVE_AGENT_TYPE,
+            ..lone_agent(0.0)
+        };
+        let agents = vec![lone_agent(10.0), inactive, inactive, inactive];
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, agents.clone(), agent_stats.clone(), interactions(), trail_stats());
+        let constants = simulation.shader_constants(0.0, TIME_STEP);
+        simulation.update_agents(&constants);
+        let parent = simulation.agents[0];
+        let (child_index, child) = simulation.agents.iter().enumerate().skip(1).find(|(_, agent)| agent.agent_type == 0).unwrap();
+        let parent_pixel = vec2(parent.x, parent.y).as_ivec2();
+        let child_pixel = vec2(child.x, child.y).as_ivec2();
+        assert_ne!(parent_pixel, child_pixel);
+        assert_eq!((parent_pixel - child_pixel).abs().max_element(), 1);
+        assert_eq!(simulation.occupancy_buffer[(child_pixel.y as u32 * MAP_SIZE.x + child_pixel.x as u32) as usize], child_index as u32 + 1);
+
+        // Without a free neighbouring pixel no child is born
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, agents, agent_stats, interactions(), trail_stats());
+        simulation.occupancy_buffer.fill(99);
+        simulation.occupancy_buffer[(20 * MAP_SIZE.x + 20) as usize] = 1;
+        simulation.update_agents(&constants);
+        assert_eq!(simulation.agents[0].energy, 10.0);
+        assert!(simulation.agents[1..].iter().all(|agent| agent.agent_type == INACTIVE_AGENT_TYPE));
+    }
+
+    fn move_past_occupied_pixel(exclusive_occupancy: u32) -> ReferenceSimulation {
+        let agent = Agent {
+            x: 18.5,
+            ..lone_agent(0.0)
+        };
+        let mut agent_stats = agent_stats();
+        agent_stats[0].exclusive_occupancy = exclusive_occupancy;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![agent], agent_stats, interactions(), trail_stats());
+        simulation.occupancy_buffer[(20 * MAP_SIZE.x + 18) as usize] = 1;
+        simulation.occupancy_buffer[(20 * MAP_SIZE.x + 20) as usize] = 99;
+        // Moves two pixels
+        let constants = simulation.shader_constants(0.0, 0.05);
+        simulation.update_agents(&constants);
+        simulation
+    }
+
+    #[test]
+    fn test_exclusive_occupancy() {
+        let mut simulation = move_past_occupied_pixel(1);
+        let agent = simulation.agents[0];
+        assert!((agent.x - 19.5).abs() < 0.001);
+        assert_ne!(agent.angle, 0.0);
+        assert_eq!(simulation.occupancy_buffer[(20 * MAP_SIZE.x + 18) as usize], 0);
+        assert_eq!(simulation.occupancy_buffer[(20 * MAP_SIZE.x + 19) as usize], 1);
+        assert_eq!(simulation.occupancy_buffer[(20 * MAP_SIZE.x + 20) as usize], 99);
+        assert_eq!(simulation.pixel(uvec2(20, 20)).get_frac(0), 0.0);
+
+        let simulation = move_past_occupied_pixel(0);
+        let agent = simulation.agents[0];
+        assert!((agent.x - 20.5).abs() < 0.001);
+        assert_eq!(agent.angle, 0.0);
+        assert_eq!(simulation.occupancy_buffer[(20 * MAP_SIZE.x + 18) as usize], 1);
+    }
+
+    fn add_wall(simulation: &mut ReferenceSimulation, x: u32) {
+        for y in 0..MAP_SIZE.y {
+            simulation.obstacle_buffer[(y * MAP_SIZE.x + x) as usize] = 1;
+        }
+    }
+
+    fn trail_map(simulation: &mut ReferenceSimulation) -> TrailMap<'_> {
+        let constants = simulation.shader_constants(0.0, 1.0);
+        TrailMap::new(&constants, &mut simulation.trail_buffer, &simulation.obstacle_buffer, &mut simulation.occupancy_buffer)
+    }
+
+    #[test]
+    fn test_obstacle_blocks_agent() {
+        let agent = Agent {
+            x: 37.5,
+            ..lone_agent(0.0)
+        };
+        let mut agent_stats = agent_stats();
+        agent_stats[0].edge_behavior = EdgeBehavior::Reflect.encode();
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![agent], agent_stats, interactions(), trail_stats());
+        add_wall(&mut simulation, 40);
+        let constants = simulation.shader_constants(0.0, 0.1);
+        simulation.update_agents(&constants);
+        let agent = simulation.agents[0];
+        assert!((agent.x - 39.5).abs() < 0.001);
+        assert_eq!(agent.angle, PI);
+        assert!(simulation.pixel(uvec2(39, 20)).get_frac(0) > 0.1);
+        assert_eq!(simulation.pixel(uvec2(40, 20)).get_frac(0), 0.0);
+    }
+
+    #[test]
+    fn test_obstacle_sensing() {
+        let agent = lone_agent(0.0);
+        let mut agent_stats = agent_stats()[0];
+        agent_stats.obstacle_attraction = -0.5;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), Vec::new(), Vec::new(), trail_stats());
+        add_wall(&mut simulation, 29);
+        let sensed = sense(&mut trail_map(&mut simulation), &agent, &agent_stats, InteractionRow::new(&interactions(), 0, NUM_TRAILS as u32), 0.0, agent_stats.sensor_offset);
+        assert_eq!(sensed, Some(-1.5));
+    }
+
+    #[test]
+    fn test_sensor_kernel_radius() {
+        let agent = lone_agent(0.0);
+        let mut agent_stats = agent_stats()[0];
+        agent_stats.avoidance_threshold = 100.0;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), Vec::new(), Vec::new(), trail_stats());
+        for y in 0..MAP_SIZE.y {
+            for x in 0..MAP_SIZE.x {
+                simulation.pixel(uvec2(x, y)).set_frac(0, 1.0);
+            }
+        }
+        for (radius, expected) in [(0, 1.0), (1, 9.0), (2, 25.0)] {
+            agent_stats.sensor_kernel_radius = radius;
+            let sensed = sense(&mut trail_map(&mut simulation), &agent, &agent_stats, InteractionRow::new(&interactions(), 0, NUM_TRAILS as u32), 0.0, 5.0);
+            assert_eq!(sensed, Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_bilinear_sensing() {
+        let agent = lone_agent(0.0);
+        let mut agent_stats = agent_stats()[0];
+        agent_stats.sensor_kernel_radius = 0;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), Vec::new(), Vec::new(), trail_stats());
+        simulation.pixel(uvec2(26, 20)).set_frac(0, 1.0);
+        // Sensors at the center of pixel 25, the edge between 25 and 26, and the center of 26
+        for (sensor_offset, truncated, bilinear) in [(5.0, 0.0, 0.0), (5.5, 1.0, 0.5), (6.0, 1.0, 1.0)] {
+            for (bilinear_sensing, expected) in [(0, truncated), (1, bilinear)] {
+                agent_stats.bilinear_sensing = bilinear_sensing;
+                let sensed = sense(&mut trail_map(&mut simulation), &agent, &agent_stats, InteractionRow::new(&interactions(), 0, NUM_TRAILS as u32), 0.0, sensor_offset);
+                assert!((sensed.unwrap() - expected).abs() < 0.001);
+            }
+        }
+    }
+
+    fn trail_total(simulation: &mut ReferenceSimulation) -> f32 {
+        let mut total = 0.0;
+        for y in 0..MAP_SIZE.y {
+            for x in 0..MAP_SIZE.x {
+                total += simulation.pixel(uvec2(x, y)).get_frac(0);
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn test_splat_deposition() {
+        // A quarter of a pixel per step, not enough to leave the starting pixel
+        let mut agent_stats = agent_stats();
+        agent_stats[0].velocity = 0.25 / TIME_STEP;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![lone_agent(0.0)], agent_stats.clone(), interactions(), trail_stats());
+        let constants = simulation.shader_constants(0.0, TIME_STEP);
+        simulation.update_agents(&constants);
+        assert_eq!(trail_total(&mut simulation), 0.0);
+
+        agent_stats[0].splat_deposition = 1;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![lone_agent(0.0)], agent_stats, interactions(), trail_stats());
+        simulation.update_agents(&constants);
+        // The addition of 0.2 for a quarter step, spread over the pixels on either side of x = 20.75
+        assert!((trail_total(&mut simulation) - 0.05).abs() < 0.001);
+        let left = simulation.pixel(uvec2(20, 20)).get_frac(0);
+        let right = simulation.pixel(uvec2(21, 20)).get_frac(0);
+        assert!((left - 0.0375).abs() < 0.001);
+        assert!((right - 0.0125).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_sensor_placement() {
+        let mut agent_stats = agent_stats()[0];
+        agent_stats.sensor_count = 5;
+        let angles = (0..5)
+            .map(|index| sensor_placement(&agent_stats, index, 5).0 * 180.0 / PI)
+            .collect::<Vec<_>>();
+        for (angle, expected) in angles.iter().zip([120.0, 60.0, 0.0, -60.0, -120.0]) {
+            assert!((angle - expected).abs() < 0.001);
+        }
+
+        agent_stats.custom_sensors = 1;
+        agent_stats.sensor_angles[1] = 45.0;
+        agent_stats.sensor_offsets[1] = 4.0;
+        let (angle, offset) = sensor_placement(&agent_stats, 1, 5);
+        assert!((angle - PI / 4.0).abs() < 0.001);
+        assert_eq!(offset, 4.0);
+    }
+
+    /// A single agent heading along +x with one custom sensor on each side, and trail only on its left
+    fn steer_toward_trail(sensor_angle: f32) -> f32 {
+        let agent = lone_agent(0.0);
+        let mut agent_stats = agent_stats();
+        agent_stats[0].sensor_count = 2;
+        agent_stats[0].custom_sensors = 1;
+        agent_stats[0].sensor_angles[0] = sensor_angle;
+        agent_stats[0].sensor_angles[1] = -sensor_angle;
+        agent_stats[0].sensor_offsets[0] = 6.0;
+        agent_stats[0].sensor_offsets[1] = 6.0;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![agent], agent_stats, interactions(), trail_stats());
+        let sensor = vec2(20.5, 20.5) + vec2(sensor_angle.to_radians().cos(), sensor_angle.to_radians().sin()) * 6.0;
+        simulation.pixel(sensor.as_uvec2()).set_frac(0, 1.0);
+        let constants = simulation.shader_constants(0.0, TIME_STEP);
+        simulation.update_agents(&constants);
+        simulation.agents[0].angle
+    }
+
+    #[test]
+    fn test_custom_sensors_steer() {
+        assert!(steer_toward_trail(90.0) > 0.0);
+        assert!(steer_toward_trail(30.0) > 0.0);
+    }
+
+    #[test]
+    fn test_steering_models() {
+        let mut agent_stats = agent_stats()[0];
+        let toward_left = SensorWeights { left: 9.0, forward: 0.0, right: 0.0, has_forward: true };
+        let toward_right = SensorWeights { left: 0.0, forward: 0.0, right: 9.0, has_forward: true };
+        let forward = SensorWeights { left: 0.0, forward: 9.0, right: 0.0, has_forward: true };
+        for model in [SteeringModel::Classic, SteeringModel::Jones, SteeringModel::Softmax, SteeringModel::Multiplicative] {
+            agent_stats.steering_model = model.encode();
+            assert!(steer(&agent_stats, toward_left, 0.5, 2.0) > 0.0);
+            assert!(steer(&agent_stats, toward_right, 0.5, 2.0) < 0.0);
+            assert_eq!(steer(&agent_stats, forward, 0.5, 2.0), 0.0);
+        }
+        // Jones always turns at the full turn speed
+        agent_stats.steering_model = SteeringModel::Jones.encode();
+        assert_eq!(steer(&agent_stats, toward_left, 0.1, 2.0), 2.0);
+        // Softmax gets sharper with the gain, and ignores the sensors without it
+        agent_stats.steering_model = SteeringModel::Softmax.encode();
+        agent_stats.steering_gain = 10.0;
+        assert!((steer(&agent_stats, toward_left, 0.5, 2.0) - 2.0).abs() < 0.001);
+        agent_stats.steering_gain = 0.0;
+        assert_eq!(steer(&agent_stats, toward_left, 0.5, 2.0), 0.0);
+    }
+
+    #[test]
+    fn test_obstacle_blocks_diffusion() {
+        let mut trail_stats = trail_stats();
+        trail_stats[0].evaporation_speed = 0.0;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats);
+        add_wall(&mut simulation, 30);
+        simulation.pixel(uvec2(29, 10)).set_frac(0, 1.0);
+        for step in 0..50 {
+            let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+            simulation.diffuse(&constants);
+        }
+        assert!(simulation.pixel(uvec2(28, 10)).get_frac(0) > 0.0);
+        assert_eq!(simulation.pixel(uvec2(30, 10)).get_frac(0), 0.0);
+        assert_eq!(simulation.pixel(uvec2(31, 10)).get_frac(0), 0.0);
+    }
+
+    #[test]
+    fn test_emitters() {
+        let mut trail_stats = trail_stats();
+        trail_stats[0].evaporation_speed = 0.0;
+        trail_stats[0].diffusion_speed = 0.0;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats);
+        simulation.emitter_stats = vec![
+            EmitterStats { trail_index: 0, rate: 1.0 },
+            EmitterStats { trail_index: 0, rate: -1.0 },
+        ];
+        simulation.emitter_map[(10 * MAP_SIZE.x + 10) as usize] = 1;
+        simulation.emitter_map[(10 * MAP_SIZE.x + 30) as usize] = 2;
+        simulation.pixel(uvec2(30, 10)).set_frac(0, 1.0);
+        for step in 0..30 {
+            let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+            simulation.diffuse(&constants);
+        }
+        let source = simulation.pixel(uvec2(10, 10)).get_frac(0);
+        let sink = simulation.pixel(uvec2(30, 10)).get_frac(0);
+        assert!((source - 0.5).abs() < 0.01);
+        assert!((sink - 0.5).abs() < 0.01);
+        assert_eq!(simulation.pixel(uvec2(20, 10)).get_frac(0), 0.0);
+    }
+
+    fn uniform_flow(strength: f32, agent_drift: f32) -> FlowConstants {
+        FlowConstants {
+            mode: FlowMode::Uniform.encode(),
+            strength,
+            agent_drift,
+            ..Default::default()
+        }
+    }
+
+    /// Spreads a single saturated pixel with one blur at full diffusion speed
+    fn spread_once(kernel: DiffusionKernel) -> ReferenceSimulation {
+        let mut trail_stats = trail_stats();
+        for stats in trail_stats.iter_mut() {
+            stats.evaporation_speed = 0.0;
+            stats.diffusion_speed = 100.0;
+            stats.kernel = kernel;
+        }
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats);
+        simulation.pixel(uvec2(30, 20)).set_frac(0, 1.0);
+        let constants = simulation.shader_constants(0.0, 1.0);
+        simulation.diffuse(&constants);
+        simulation
+    }
+
+    #[test]
+    fn test_diffusion_kernels() {
+        let value_at = |simulation: &mut ReferenceSimulation, x, y| simulation.pixel(uvec2(x, y)).get_frac(0);
+        let total = |simulation: &mut ReferenceSimulation| {
+            (0..MAP_SIZE.y).flat_map(|y| (0..MAP_SIZE.x).map(move |x| (x, y))).map(|(x, y)| value_at(simulation, x, y)).sum::<f32>()
+        };
+
+        let mut none = spread_once(DiffusionKernel { shape: KernelShape::None.encode(), ..DiffusionKernel::BOX_3X3 });
+        assert!(value_at(&mut none, 30, 20) > 0.999);
+        assert_eq!(value_at(&mut none, 31, 20), 0.0);
+
+        // The box spreads evenly up to its radius, across the tiles of neighbouring workgroups
+        let mut wide_box = spread_once(DiffusionKernel { radius: 3, ..DiffusionKernel::BOX_3X3 });
+        assert!((value_at(&mut wide_box, 33, 23) - value_at(&mut wide_box, 30, 20)).abs() < 0.001);
+        assert!((value_at(&mut wide_box, 27, 17) - 1.0 / 49.0).abs() < 0.001);
+        assert_eq!(value_at(&mut wide_box, 34, 20), 0.0);
+        assert!((total(&mut wide_box) - 1.0).abs() < 0.01);
+
+        let mut gaussian = spread_once(DiffusionKernel { shape: KernelShape::Gaussian.encode(), sigma: 1.5, ..DiffusionKernel::BOX_3X3 });
+        assert!(value_at(&mut gaussian, 30, 20) > value_at(&mut gaussian, 31, 20));
+        assert!(value_at(&mut gaussian, 31, 20) > value_at(&mut gaussian, 32, 20));
+        assert!((value_at(&mut gaussian, 32, 20) - value_at(&mut gaussian, 30, 22)).abs() < 0.001);
+        assert!((total(&mut gaussian) - 1.0).abs() < 0.01);
+
+        // Stretched along the x axis
+        let mut anisotropic = spread_once(DiffusionKernel { shape: KernelShape::Anisotropic.encode(), sigma: 1.5, ratio: 0.3, ..DiffusionKernel::BOX_3X3 });
+        assert!(value_at(&mut anisotropic, 32, 20) > 10.0 * value_at(&mut anisotropic, 30, 22));
+        assert!((total(&mut anisotropic) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_trail_formats() {
+        // Evaporates less per step than the fixed point formats can represent
+        let evaporate = |trail_format| {
+            let mut trail_stats = trail_stats();
+            for stats in trail_stats.iter_mut() {
+                stats.evaporation_speed = 0.05;
+                stats.kernel.shape = KernelShape::None.encode();
+            }
+            let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats);
+            simulation.set_trail_format(trail_format);
+            simulation.pixel(uvec2(10, 10)).set_frac(0, 0.5);
+            for step in 0..60 {
+                let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+                simulation.diffuse(&constants);
+            }
+            simulation.pixel(uvec2(10, 10)).get_frac(0)
+        };
+        let expected = 0.5 - 60.0 * 0.0005 * TIME_STEP;
+        assert!((evaporate(TrailFormat::Float32 { channels: NUM_TRAILS as u32 }) - expected).abs() < 0.00001);
+        // Each step rounds down to the next representable value
+        assert!(evaporate(TrailFormat::Fixed16) < expected);
+        assert!(evaporate(TrailFormat::Fixed8) < evaporate(TrailFormat::Fixed16));
+    }
+
+    #[test]
+    fn test_flow_advects_trails() {
+        let mut trail_stats = trail_stats();
+        for stats in trail_stats.iter_mut() {
+            stats.evaporation_speed = 0.0;
+            stats.diffusion_speed = 0.0;
+        }
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats);
+        // Half a pixel per step to the right
+        simulation.flow = uniform_flow(0.5 / TIME_STEP, 0.0);
+        simulation.pixel(uvec2(20, 10)).set_frac(0, 1.0);
+        let value_at = |simulation: &mut ReferenceSimulation, x| simulation.pixel(uvec2(x, 10)).get_frac(0);
+        for step in 0..2 {
+            let constants = simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP);
+            simulation.diffuse(&constants);
+        }
+        // Two half steps split the trail over three pixels, mostly on the middle one
+        assert_eq!(value_at(&mut simulation, 19), 0.0);
+        assert!(value_at(&mut simulation, 21) > value_at(&mut simulation, 20));
+        assert!(value_at(&mut simulation, 21) > value_at(&mut simulation, 22));
+        assert!(value_at(&mut simulation, 22) > 0.0);
+        let total = (20..=22).map(|x| value_at(&mut simulation, x)).sum::<f32>();
+        assert!((total - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_flow_pushes_agents() {
+        let mut agent_stats = agent_stats();
+        agent_stats[0].velocity = 0.0;
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, vec![lone_agent(1.0)], agent_stats, interactions(), trail_stats());
+        // Agents without drift stay put
+        simulation.flow = uniform_flow(1.0 / TIME_STEP, 0.0);
+        simulation.step(&simulation.shader_constants(0.0, TIME_STEP));
+        assert_eq!(simulation.agents[0].x, 20.5);
+        simulation.flow = uniform_flow(1.0 / TIME_STEP, 1.0);
+        for step in 1..6 {
+            simulation.step(&simulation.shader_constants(step as f32 * TIME_STEP, TIME_STEP));
+        }
+        assert!((simulation.agents[0].x - 25.5).abs() < 0.001);
+        assert_eq!(simulation.agents[0].y, 20.5);
+    }
+
+    fn react_once(reaction: Reaction, values: [f32; 2]) -> [f32; 2] {
+        let mut trail_stats = trail_stats();
+        for stats in trail_stats.iter_mut() {
+            stats.evaporation_speed = 0.0;
+            stats.diffusion_speed = 0.0;
+        }
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats);
+        simulation.reactions = vec![reaction];
+        let mut pixel = simulation.pixel(uvec2(10, 10));
+        pixel.set_frac(0, values[0]);
+        pixel.set_frac(1, values[1]);
+        let constants = simulation.shader_constants(0.0, 0.5);
+        simulation.diffuse(&constants);
+        let pixel = simulation.pixel(uvec2(10, 10));
+        [pixel.get_frac(0), pixel.get_frac(1)]
+    }
+
+    #[test]
+    fn test_reactions() {
+        let close = |first: [f32; 2], second: [f32; 2]| {
+            (first[0] - second[0]).abs() < 0.001 && (first[1] - second[1]).abs() < 0.001
+        };
+        // Decay of channel 0 into channel 1
+        let decay = Reaction {
+            source: 0,
+            target: 1,
+            rate: 1.0,
+            ..Default::default()
+        };
+        assert!(close(react_once(decay, [0.8, 0.1]), [0.4, 0.5]));
+        // Without a target the source just disappears
+        let decay = Reaction {
+            target: NO_CHANNEL,
+            ..decay
+        };
+        assert!(close(react_once(decay, [0.8, 0.1]), [0.4, 0.1]));
+        // U + 2V -> 3V
+        let gray_scott = Reaction {
+            source: 0,
+            catalyst: 1,
+            catalyst_exponent: 2.0,
+            target: 1,
+            rate: 1.0,
+        };
+        assert!(close(react_once(gray_scott, [0.8, 0.5]), [0.7, 0.6]));
+        assert!(close(react_once(gray_scott, [0.8, 0.0]), [0.8, 0.0]));
+    }
+
+    fn diffused_across_edge(boundary_mode: BoundaryMode) -> f32 {
+        let mut simulation = ReferenceSimulation::new(MAP_SIZE, Vec::new(), agent_stats(), interactions(), trail_stats());
+        simulation.boundary_mode = boundary_mode;
+        simulation.pixel(uvec2(0, 10)).set_frac(0, 1.0);
+        let constants = simulation.shader_constants(0.0, TIME_STEP);
+        simulation.diffuse(&constants);
+        simulation.pixel(uvec2(MAP_SIZE.x - 1, 10)).get_frac(0)
+    }
+
+    #[test]
+    fn test_wrap_diffuses_across_edge() {
+        assert_eq!(diffused_across_edge(BoundaryMode::Clamp), 0.0);
+        assert!(diffused_across_edge(BoundaryMode::Wrap) > 0.0);
+    }
+
+    const GOLDEN_300_STEPS: u64 = 1363086161296470918;
+    const GOLDEN_DIFFUSE_200_STEPS: u64 = 7548049075649717029;
+}
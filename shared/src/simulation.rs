@@ -0,0 +1,1402 @@
+//! The simulation kernels behind the compute shaders.
+//! The shader entry points in `shader-slime` are thin wrappers around these functions,
+//! and [`crate::reference`] runs the same functions on the CPU.
+use crate::pixel_view::*;
+use crate::*;
+use core::f32::consts::PI;
+use glam::{IVec2, UVec2, Vec2, ivec2, uvec2, vec2};
+use spirv_std::glam;
+// Note: This cfg is incorrect on its surface, it really should be "are we compiling with std", but
+// we tie #[no_std] above to the same condition, so it's fine.
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+use spirv_std::num_traits::Pow;
+
+pub enum Bounds {
+    InsideBounds,
+    OutsideBounds,
+    Obstacle,
+    Occupied,
+}
+
+// Number of random slots an agent tries before giving up on reproducing
+const REPRODUCTION_PROBES: u32 = 8;
+
+// The statistics buffer holds MAX_TRAILS trail totals followed by one agent count per agent type
+pub const STATISTICS_AGENT_OFFSET: usize = MAX_TRAILS;
+// The counters of adjust_agent hold the inactive slots and retired agents seen, followed by one agent count per counted type
+pub const ADJUST_AGENT_OFFSET: usize = 2;
+// Agents are sorted by the SORT_TILE_SIZE by SORT_TILE_SIZE tile they are in
+pub const SORT_TILE_SIZE: u32 = 16;
+// Trail totals are summed in fixed point with this many steps per saturated pixel, so they can be added atomically.
+// A map of fewer than 2^26 pixels fits in a u32.
+pub const TRAIL_TOTAL_SCALE: f32 = 64.0;
+
+/// One step of a single agent, as run by `main_cs`.
+/// `invocation_id` is the x component of the global invocation id, which seeds the random steering
+/// and identifies the agent in the occupancy buffer.
+/// Returns true if the agent has enough energy to reproduce, see [`reproduce`].
+pub fn update_agent(
+    invocation_id: u32,
+    constants: &ShaderConstants,
+    agent: &mut Agent,
+    agent_stats_buffer: &[AgentStats],
+    interaction_buffer: &[TrailInteraction],
+    map: &mut TrailMap<'_>,
+    flow_field: &[Vec2],
+) -> bool {
+    if agent.agent_type >= constants.num_agent_types {
+        return false;
+    }
+    let agent_type = agent.agent_type;
+    let agent_stats = &agent_stats_buffer[agent_type as usize];
+    let interactions = InteractionRow::new(interaction_buffer, agent_type, constants.num_trails);
+
+    let map_size = map.map_size;
+    let boundary_mode = map.boundary_mode;
+    let trail_format = map.trail_format;
+    let frame_seed = hash(hash(constants.seed).wrapping_add(constants.frame));
+    let random = hash(((agent.y * map_size.x as f32 + agent.x) as u32).wrapping_add(hash(invocation_id ^ frame_seed)));
+    // Zero for agents that ignore the occupancy buffer
+    let occupant = if agent_stats.exclusive_occupancy != 0 { invocation_id + 1 } else { 0 };
+    let splat = agent_stats.splat_deposition != 0;
+    let step = AgentStep {
+        interactions,
+        agent_stats_buffer,
+        num_agent_types: constants.num_agent_types,
+        occupant,
+        splat,
+        random: hash(random),
+    };
+
+    // Sensor based on sensory data
+    // Sensors are grouped by which side of the heading they are on,
+    // each side is as strong as its strongest sensor and blocked if any of its sensors is saturated
+    let sensor_count = u32::min(agent_stats.sensor_count, MAX_SENSORS as u32);
+    let mut weight_left = f32::MIN;
+    let mut weight_forward = f32::MIN;
+    let mut weight_right = f32::MIN;
+    let mut blocked_left = false;
+    let mut blocked_forward = false;
+    let mut blocked_right = false;
+    let mut has_forward = false;
+    for sensor_index in 0..sensor_count {
+        let (angle_offset, sensor_offset) = sensor_placement(agent_stats, sensor_index, sensor_count);
+        let weight = sense(map, agent, agent_stats, interactions, angle_offset, sensor_offset);
+        if angle_offset > 0.0 {
+            match weight {
+                Some(weight) => weight_left = f32::max(weight_left, weight),
+                None => blocked_left = true,
+            }
+        } else if angle_offset < 0.0 {
+            match weight {
+                Some(weight) => weight_right = f32::max(weight_right, weight),
+                None => blocked_right = true,
+            }
+        } else {
+            has_forward = true;
+            match weight {
+                Some(weight) => weight_forward = f32::max(weight_forward, weight),
+                None => blocked_forward = true,
+            }
+        }
+    }
+
+    let (speed_scale, turn_scale) = kinesis(map, interactions, vec2(agent.x, agent.y));
+    let random_steer_strength = random as f32 / u32::MAX as f32;
+    let turn_speed = agent_stats.turn_speed * turn_scale * PI;
+    let turn_speed_avoidance = agent_stats.turn_speed_avoidance * PI;
+
+    if blocked_left && blocked_right {
+        agent.angle += 0.0;
+    } else if blocked_left {
+        agent.angle -= random_steer_strength * turn_speed_avoidance * constants.time_step;
+    } else if blocked_right {
+        agent.angle += random_steer_strength * turn_speed_avoidance * constants.time_step;
+    } else if blocked_forward {
+        agent.angle += (random_steer_strength - 0.5) * 2.0 * turn_speed_avoidance * constants.time_step;
+    } else {
+        let weights = SensorWeights {
+            left: weight_left,
+            forward: weight_forward,
+            right: weight_right,
+            has_forward,
+        };
+        agent.angle += steer(agent_stats, weights, random_steer_strength, turn_speed) * constants.time_step;
+    }
+
+    // Render each pixel inbetween here and the end of the streak we move this frame
+    let velocity = agent_stats.velocity * speed_scale;
+    let mut num_steps = velocity * constants.time_step;
+    let step_size = 1.0;
+    let start_pos = vec2(agent.x, agent.y);
+    let mut step_pos = start_pos;
+    // Where the agent is put back if it runs into an obstacle
+    let mut free_pos;
+    let bounds: Bounds = 'clamp_block: {
+        while num_steps > step_size {
+            free_pos = step_pos;
+            step_pos = wrap_position(step_pos + vec2(agent.angle.cos(), agent.angle.sin()) * step_size, map_size, boundary_mode);
+            let bounds = process_pixel(map, &step, agent, step_pos, 1.0);
+            match bounds {
+                Bounds::InsideBounds => {}
+                _ => break 'clamp_block bounds,
+            }
+            num_steps -= step_size;
+        }
+        // If we didn't go outside the window, which is the normal case,
+        // num_steps is now smaller than 1.0
+        // Do the last little leap
+        let previous = step_pos;
+        free_pos = previous;
+        step_pos = wrap_position(step_pos + vec2(agent.angle.cos(), agent.angle.sin()) * num_steps, map_size, boundary_mode);
+        // Splatting agents deposit for the part of a step they moved, even without leaving their pixel
+        if previous.as_ivec2() != step_pos.as_ivec2() || splat {
+            let amount = if splat { num_steps } else { 1.0 };
+            let bounds = process_pixel(map, &step, agent, step_pos, amount);
+            break 'clamp_block bounds;
+        }
+        if map_position(step_pos.as_ivec2(), map_size, boundary_mode).is_some() {
+            Bounds::InsideBounds
+        } else {
+            Bounds::OutsideBounds
+        }
+    };
+    match bounds {
+        Bounds::InsideBounds => {}
+        Bounds::OutsideBounds => {
+            let outside_pos = step_pos.as_ivec2();
+            step_pos.x = f32::min(constants.map_size.x as f32 - 1.01, f32::max(0.01, step_pos.x));
+            step_pos.y = f32::min(constants.map_size.y as f32 - 1.01, f32::max(0.01, step_pos.y));
+            let hit_x = outside_pos.x < 0 || outside_pos.x >= map_size.x as i32;
+            let hit_y = outside_pos.y < 0 || outside_pos.y >= map_size.y as i32;
+            if !collide(agent, agent_stats, random, hit_x, hit_y) {
+                release_cell(map.occupancy_buffer, map_size, boundary_mode, start_pos, occupant);
+                agent.x = step_pos.x;
+                agent.y = step_pos.y;
+                return false;
+            }
+        }
+        Bounds::Obstacle => {
+            let blocked_pos = step_pos.as_ivec2();
+            step_pos = free_pos;
+            let free = free_pos.as_ivec2();
+            // Work out which side of the obstacle we ran into by checking the neighbours we passed between
+            let mut hit_x = blocked_pos.x != free.x && is_blocked(map.obstacle_buffer, map_size, boundary_mode, ivec2(blocked_pos.x, free.y));
+            let mut hit_y = blocked_pos.y != free.y && is_blocked(map.obstacle_buffer, map_size, boundary_mode, ivec2(free.x, blocked_pos.y));
+            if !hit_x && !hit_y {
+                // Ran straight into a corner
+                hit_x = true;
+                hit_y = true;
+            }
+            if !collide(agent, agent_stats, random, hit_x, hit_y) {
+                release_cell(map.occupancy_buffer, map_size, boundary_mode, start_pos, occupant);
+                agent.x = step_pos.x;
+                agent.y = step_pos.y;
+                return false;
+            }
+        }
+        Bounds::Occupied => {
+            // Like the Jones model, stay put and pick a new random heading
+            step_pos = free_pos;
+            agent.angle = (random as f32 / u32::MAX as f32) * 2.0 * PI;
+        }
+    }
+    if constants.flow.agent_drift != 0.0 {
+        // Drift with the flow, without depositing along the way or drifting into obstacles
+        let drift = flow_at(step_pos, constants, flow_field) * constants.flow.agent_drift * constants.time_step;
+        let drifted = wrap_position(step_pos + drift, map_size, boundary_mode);
+        if !is_blocked(map.obstacle_buffer, map_size, boundary_mode, drifted.as_ivec2()) {
+            step_pos = drifted;
+        }
+    }
+    if occupant != 0 && !move_occupancy(map.occupancy_buffer, map_size, boundary_mode, start_pos, step_pos, occupant) {
+        // Another agent claimed the pixel first
+        step_pos = start_pos;
+        agent.angle = (random as f32 / u32::MAX as f32) * 2.0 * PI;
+    }
+
+    agent.x = step_pos.x;
+    agent.y = step_pos.y;
+
+    if agent_stats.timeout > 0.01 {
+        agent.countdown -= constants.time_step;
+        if agent.countdown <= 0.0 && agent_stats.timeout_conversion < constants.num_agent_types {
+            agent.agent_type = agent_stats.timeout_conversion;
+            agent.countdown = initial_countdown(&agent_stats_buffer[agent_stats.timeout_conversion as usize], hash(random));
+        }
+    }
+
+    match map_position(step_pos.as_ivec2(), map_size, boundary_mode) {
+        // An agent that already converted this step keeps its new type
+        Some(position) if agent.agent_type == agent_type => {
+            let pixel = get_pixel(map.trail_buffer, map_size, trail_format, position);
+            for trail_index in 0..interactions.num_trails {
+                let interaction = interactions.get(trail_index);
+                if interaction.conversion_enabled == 0 || interaction.conversion_rate == 0.0 || interaction.conversion >= constants.num_agent_types {
+                    continue;
+                }
+                let concentration = pixel.get_frac(trail_index);
+                let chance = interaction.conversion_rate * concentration * constants.time_step;
+                let roll = hash(random.wrapping_add(trail_index as u32 + 1));
+                if concentration > interaction.conversion_threshold && (roll as f32 / u32::MAX as f32) < chance {
+                    agent.agent_type = interaction.conversion;
+                    agent.countdown = initial_countdown(&agent_stats_buffer[interaction.conversion as usize], hash(roll));
+                    break;
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let mut wants_to_reproduce = false;
+    if agent_stats.starvation_enabled != 0 || agent_stats.reproduction_enabled != 0 {
+        agent.energy -= agent_stats.energy_cost * velocity * constants.time_step;
+        if let Some(position) = map_position(step_pos.as_ivec2(), map_size, boundary_mode) {
+            let pixel = get_pixel(map.trail_buffer, map_size, trail_format, position);
+            for trail_index in 0..interactions.num_trails {
+                agent.energy += pixel.get_frac(trail_index) * interactions.get(trail_index).energy_gain * constants.time_step;
+            }
+        }
+        if agent_stats.starvation_enabled != 0 && agent.energy <= 0.0 {
+            agent.agent_type = INACTIVE_AGENT_TYPE;
+        } else {
+            wants_to_reproduce = agent_stats.reproduction_enabled != 0 && agent.energy >= agent_stats.reproduction_energy;
+        }
+    }
+
+    // Free the pixel if the agent died or was converted to a type that ignores occupancy
+    let still_exclusive = agent.agent_type < constants.num_agent_types
+        && agent_stats_buffer[agent.agent_type as usize].exclusive_occupancy != 0;
+    if !still_exclusive {
+        release_cell(map.occupancy_buffer, map_size, boundary_mode, step_pos, occupant);
+    }
+    wants_to_reproduce
+}
+
+/// Countdown of a new agent of this type, `timeout` varied by up to `timeout_variation` either way
+pub fn initial_countdown(agent_stats: &AgentStats, random: u32) -> f32 {
+    let variation = (random as f32 / u32::MAX as f32 - 0.5) * 2.0 * agent_stats.timeout_variation;
+    f32::max(agent_stats.timeout + variation, 0.0)
+}
+
+/// Agent `agent_index` of a freshly spawned agents buffer, from the batch covering it or an inactive spare slot.
+/// Follows the same distributions as the cpu spawning in the program, but not the same random numbers.
+pub fn spawn_agent(agent_index: u32, constants: &SpawnConstants, spawn_batches: &[SpawnBatch], agent_stats_buffer: &[AgentStats], density_buffer: &[u32]) -> Agent {
+    for &batch in spawn_batches.iter().take(constants.num_batches as usize) {
+        if agent_index < batch.first_agent || agent_index - batch.first_agent >= batch.num_agents {
+            continue;
+        }
+        let agent_stats = &agent_stats_buffer[batch.agent_type as usize];
+        let mut random = hash(hash(constants.seed) ^ agent_index);
+        let mut next_random = || {
+            random = hash(random);
+            random as f32 / u32::MAX as f32
+        };
+        let map_size = constants.map_size.as_vec2();
+        let center = map_size / 2.0;
+        let random_angle = next_random() * 2.0 * PI;
+        let direction = vec2(random_angle.cos(), random_angle.sin());
+        let (position, angle) = match batch.spawn_mode.decode() {
+            SpawnMode::EvenlyDistributed => (vec2(next_random(), next_random()) * map_size, random_angle),
+            SpawnMode::CenterFacingOutward => (center, random_angle),
+            SpawnMode::PointFacingOutward { x, y } => (vec2(x as f32, y as f32), random_angle),
+            SpawnMode::PointFacingClockwise { x, y, distance } => {
+                (vec2(x as f32, y as f32) + direction * distance as f32, PI / 2.0 + random_angle)
+            }
+            SpawnMode::CircleFacingInward { max_distance } => {
+                (center + direction * next_random() * max_distance as f32, PI + random_angle)
+            }
+            SpawnMode::CircumferenceFacingInward { distance } => (center + direction * distance as f32, PI + random_angle),
+            SpawnMode::CircumferenceFacingOutward { distance } => (center + direction * distance as f32, random_angle),
+            SpawnMode::CircumferenceFacingRandom { distance } => {
+                (center + direction * distance as f32, next_random() * 2.0 * PI)
+            }
+            SpawnMode::CircumferenceFacingClockwise { distance } => {
+                (center + direction * distance as f32, PI / 2.0 + random_angle)
+            }
+            SpawnMode::BoxFacingRandom { spawn_box } => {
+                let corner = vec2(spawn_box.left as f32, spawn_box.top as f32);
+                let size = vec2(spawn_box.box_width as f32, spawn_box.box_height as f32);
+                (corner + vec2(next_random(), next_random()) * size, random_angle)
+            }
+            SpawnMode::FromImage { heading } => {
+                let density = SpawnDensity {
+                    cumulative: density_buffer,
+                    size: constants.density_size,
+                    centroid: constants.density_centroid,
+                };
+                density.sample(constants.map_size, heading, next_random(), vec2(next_random(), next_random()), random_angle)
+            }
+        };
+        return Agent {
+            x: position.x,
+            y: position.y,
+            angle,
+            agent_type: batch.agent_type,
+            countdown: initial_countdown(agent_stats, hash(random)),
+            energy: agent_stats.initial_energy,
+        };
+    }
+    Agent {
+        x: 0.0,
+        y: 0.0,
+        angle: 0.0,
+        agent_type: INACTIVE_AGENT_TYPE,
+        countdown: 0.0,
+        energy: 0.0,
+    }
+}
+
+/// The density image of SpawnMode::FromImage, as the running total of its values in row major order
+#[derive(Copy, Clone)]
+pub struct SpawnDensity<'buffer> {
+    pub cumulative: &'buffer [u32],
+    pub size: UVec2,
+    // Center of mass, in pixels of the image
+    pub centroid: Vec2,
+}
+
+impl<'buffer> SpawnDensity<'buffer> {
+    // Value of the pixel, clamped to the image
+    fn value(&self, position: IVec2) -> f32 {
+        let position = position.clamp(IVec2::ZERO, self.size.as_ivec2() - 1);
+        let index = (position.y * self.size.x as i32 + position.x) as usize;
+        let previous = if index > 0 { self.cumulative[index - 1] } else { 0 };
+        (self.cumulative[index] - previous) as f32
+    }
+
+    /// Position on the map and heading of a new agent. `fraction` picks the pixel, denser pixels more often,
+    /// and `jitter` where in the pixel the agent is placed. Without a density image agents spawn evenly over the map.
+    pub fn sample(&self, map_size: UVec2, heading: SpawnHeading, fraction: f32, jitter: Vec2, random_angle: f32) -> (Vec2, f32) {
+        let num_pixels = self.size.x * self.size.y;
+        if num_pixels == 0 || self.cumulative.len() < num_pixels as usize || self.cumulative[num_pixels as usize - 1] == 0 {
+            return (jitter * map_size.as_vec2(), random_angle);
+        }
+        let total = self.cumulative[num_pixels as usize - 1];
+        let target = u32::min((fraction * total as f32) as u32, total - 1);
+        // Binary search for the first pixel whose running total is past the target
+        let mut low = 0;
+        let mut high = num_pixels - 1;
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.cumulative[middle as usize] > target {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        let pixel = ivec2((low % self.size.x) as i32, (low / self.size.x) as i32);
+        let scale = map_size.as_vec2() / self.size.as_vec2();
+        let position = (pixel.as_vec2() + jitter) * scale;
+        let direction = match heading {
+            SpawnHeading::Random => Vec2::ZERO,
+            SpawnHeading::Gradient => vec2(
+                self.value(pixel + ivec2(1, 0)) - self.value(pixel - ivec2(1, 0)),
+                self.value(pixel + ivec2(0, 1)) - self.value(pixel - ivec2(0, 1)),
+            ) / scale,
+            SpawnHeading::Centroid => self.centroid * scale - position,
+        };
+        // Flat parts of the image and agents right on the centroid get a random heading
+        if direction.x == 0.0 && direction.y == 0.0 {
+            (position, random_angle)
+        } else {
+            (position, direction.y.atan2(direction.x))
+        }
+    }
+}
+
+/// Spawns the agents of the batches into inactive slots and retires agents of `retired_type`,
+/// leaving the other agents alone. `counters` holds the number of inactive slots and agents of `retired_type` seen so far,
+/// then the agents of every counted type as they were before the dispatch, see [`ADJUST_AGENT_OFFSET`].
+/// So a dispatch that spawns and retires nothing counts the inactive slots and the agents per type.
+/// Which slots and agents are picked depends on the order the invocations run in.
+pub fn adjust_agent(agent_index: usize, constants: &AdjustConstants, spawn_batches: &[SpawnBatch], agent_stats_buffer: &[AgentStats], density_buffer: &[u32], agents_buffer: &mut [Agent], counters: &mut [u32]) {
+    let agent_type = agents_buffer[agent_index].agent_type;
+    if agent_type < constants.num_counted_types {
+        atomic_add(&mut counters[ADJUST_AGENT_OFFSET + agent_type as usize], 1);
+    }
+    if agent_type == INACTIVE_AGENT_TYPE {
+        let spawn_index = atomic_add(&mut counters[0], 1);
+        if spawn_index < constants.spawn.num_agents {
+            agents_buffer[agent_index] = spawn_agent(spawn_index, &constants.spawn, spawn_batches, agent_stats_buffer, density_buffer);
+        }
+    } else if agent_type == constants.retired_type && constants.num_retired > 0 && atomic_add(&mut counters[1], 1) < constants.num_retired {
+        agents_buffer[agent_index].agent_type = INACTIVE_AGENT_TYPE;
+    }
+}
+
+/// Splits the agent at `parent_index` into a free slot of the agents buffer, sharing its energy with the child.
+/// Gives up if no free slot is found after a few random probes.
+/// The slot is claimed with [`CLAIMED_AGENT_TYPE`] and only gets the child's type once the child is written,
+/// so an invocation updating that slot in the same dispatch never sees a half written agent.
+/// Children of exclusive types take a free neighbouring pixel, and aren't born if there is none.
+pub fn reproduce(parent_index: usize, constants: &ShaderConstants, agent_stats_buffer: &[AgentStats], obstacle_buffer: &[u32], occupancy_buffer: &mut [u32], agents_buffer: &mut [Agent]) {
+    let parent = agents_buffer[parent_index];
+    let mut random = hash(parent_index as u32 ^ hash(hash(constants.seed).wrapping_add(constants.frame)));
+    for _ in 0..REPRODUCTION_PROBES {
+        random = hash(random);
+        let slot = random as usize % agents_buffer.len();
+        if compare_exchange(&mut agents_buffer[slot].agent_type, INACTIVE_AGENT_TYPE, CLAIMED_AGENT_TYPE) {
+            let mut position = vec2(parent.x, parent.y);
+            if agent_stats_buffer[parent.agent_type as usize].exclusive_occupancy != 0 {
+                match claim_neighbour(constants, obstacle_buffer, occupancy_buffer, position, slot as u32 + 1, hash(random)) {
+                    Some(neighbour) => position = neighbour,
+                    None => {
+                        atomic_store(&mut agents_buffer[slot].agent_type, INACTIVE_AGENT_TYPE);
+                        return;
+                    }
+                }
+            }
+            let energy = parent.energy / 2.0;
+            agents_buffer[slot] = Agent {
+                x: position.x,
+                y: position.y,
+                angle: (random as f32 / u32::MAX as f32) * 2.0 * PI,
+                energy,
+                agent_type: CLAIMED_AGENT_TYPE,
+                ..parent
+            };
+            atomic_store(&mut agents_buffer[slot].agent_type, parent.agent_type);
+            agents_buffer[parent_index].energy = energy;
+            return;
+        }
+    }
+}
+
+/// Claims one of the eight pixels around `position` for `occupant`, starting from a random one,
+/// and returns the center of the pixel it got
+fn claim_neighbour(constants: &ShaderConstants, obstacle_buffer: &[u32], occupancy_buffer: &mut [u32], position: Vec2, occupant: u32, random: u32) -> Option<Vec2> {
+    let map_size = constants.map_size;
+    let boundary_mode = constants.boundary_mode.decode();
+    let pixel = position.as_ivec2();
+    for step in 0..8 {
+        let offset = match random.wrapping_add(step) % 8 {
+            0 => ivec2(1, 0),
+            1 => ivec2(1, 1),
+            2 => ivec2(0, 1),
+            3 => ivec2(-1, 1),
+            4 => ivec2(-1, 0),
+            5 => ivec2(-1, -1),
+            6 => ivec2(0, -1),
+            _ => ivec2(1, -1),
+        };
+        let Some(neighbour) = map_position(pixel + offset, map_size, boundary_mode) else {
+            continue;
+        };
+        if !is_obstacle(obstacle_buffer, map_size, neighbour)
+            && compare_exchange(&mut occupancy_buffer[(neighbour.y * map_size.x + neighbour.x) as usize], 0, occupant) {
+            return Some(neighbour.as_vec2() + 0.5);
+        }
+    }
+    None
+}
+
+/// Sets `target` to `value` if it equals `comparator`, and returns whether it did.
+/// Invocations on the gpu race for the same values, so there this has to be atomic.
+fn compare_exchange(target: &mut u32, comparator: u32, value: u32) -> bool {
+    atomic_compare_exchange(target, comparator, value) == comparator
+}
+
+/// Sets `target` to `value`, after all earlier writes of this invocation are visible to the others
+fn atomic_store(target: &mut u32, value: u32) {
+    #[cfg(target_arch = "spirv")]
+    {
+        use spirv_std::memory::{Scope, Semantics};
+        unsafe {
+            spirv_std::arch::atomic_store::<u32, { Scope::Device as u32 }, { (Semantics::RELEASE.bits() | Semantics::UNIFORM_MEMORY.bits()) }>(target, value)
+        }
+    }
+    #[cfg(not(target_arch = "spirv"))]
+    {
+        *target = value;
+    }
+}
+
+/// Like [`compare_exchange`], but returns the value `target` had before
+fn atomic_compare_exchange(target: &mut u32, comparator: u32, value: u32) -> u32 {
+    #[cfg(target_arch = "spirv")]
+    {
+        use spirv_std::memory::{Scope, Semantics};
+        unsafe {
+            spirv_std::arch::atomic_compare_exchange::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }, { Semantics::NONE.bits() }>(
+                target,
+                value,
+                comparator,
+            )
+        }
+    }
+    #[cfg(not(target_arch = "spirv"))]
+    {
+        let previous = *target;
+        if previous == comparator {
+            *target = value;
+        }
+        previous
+    }
+}
+
+/// Moves the claim of `occupant` in the occupancy buffer from the pixel at `from` to the pixel at `to`.
+/// Returns false if `to` is held by another agent.
+fn move_occupancy(occupancy_buffer: &mut [u32], map_size: UVec2, boundary_mode: BoundaryMode, from: Vec2, to: Vec2, occupant: u32) -> bool {
+    if let Some(to) = map_position(to.as_ivec2(), map_size, boundary_mode) {
+        let to_index = (to.y * map_size.x + to.x) as usize;
+        let from_index = match map_position(from.as_ivec2(), map_size, boundary_mode) {
+            Some(from) => (from.y * map_size.x + from.x) as usize,
+            None => to_index,
+        };
+        if from_index == to_index {
+            // Newly spawned agents claim the pixel they stand on
+            compare_exchange(&mut occupancy_buffer[to_index], 0, occupant);
+            return true;
+        }
+        if !compare_exchange(&mut occupancy_buffer[to_index], 0, occupant) {
+            return false;
+        }
+        compare_exchange(&mut occupancy_buffer[from_index], occupant, 0);
+    }
+    true
+}
+
+fn release_cell(occupancy_buffer: &mut [u32], map_size: UVec2, boundary_mode: BoundaryMode, position: Vec2, occupant: u32) {
+    if occupant == 0 {
+        return;
+    }
+    if let Some(position) = map_position(position.as_ivec2(), map_size, boundary_mode) {
+        compare_exchange(&mut occupancy_buffer[(position.y * map_size.x + position.x) as usize], occupant, 0);
+    }
+}
+
+/// Angle relative to the heading in radians and distance from the agent of sensor `sensor_index`
+pub fn sensor_placement(agent_stats: &AgentStats, sensor_index: u32, sensor_count: u32) -> (f32, f32) {
+    if agent_stats.custom_sensors != 0 {
+        (
+            agent_stats.sensor_angles[sensor_index as usize] * PI / 180.0,
+            agent_stats.sensor_offsets[sensor_index as usize],
+        )
+    } else {
+        // Spread evenly around the heading, first sensor on the left
+        let spread = (sensor_count - 1) as f32 / 2.0 - sensor_index as f32;
+        (spread * agent_stats.sensor_angle_spacing * PI / 180.0, agent_stats.sensor_offset)
+    }
+}
+
+pub fn sense(map: &mut TrailMap<'_>, agent: &Agent, agent_stats: &AgentStats, interactions: InteractionRow, angle_offset: f32, sensor_offset: f32) -> Option<f32> {
+    let sensor_angle = agent.angle + angle_offset;
+    let sensor_position = vec2(
+        agent.x + sensor_angle.cos() * sensor_offset,
+        agent.y + sensor_angle.sin() * sensor_offset,
+    );
+    let radius = agent_stats.sensor_kernel_radius as i32;
+    let bilinear = agent_stats.bilinear_sensing != 0;
+    // With bilinear sensing the kernel is shifted by the fraction of a pixel the sensor is off the pixel centers,
+    // so it covers one more row and column, the first and last of them only partially
+    let (sensor_center, fraction, extent) = if bilinear {
+        let corner = sensor_position - 0.5;
+        let base = corner.floor();
+        (base.as_ivec2(), corner - base, 1)
+    } else {
+        (sensor_position.as_ivec2(), Vec2::ZERO, 0)
+    };
+    let mut sum = 0.0;
+
+    for offset_x in -radius..=radius + extent {
+        for offset_y in -radius..=radius + extent {
+            let pos = sensor_center + ivec2(offset_x, offset_y);
+            let weight = if bilinear {
+                kernel_edge_weight(offset_x, radius, fraction.x) * kernel_edge_weight(offset_y, radius, fraction.y)
+            } else {
+                1.0
+            };
+
+            if let Some(pos) = map_position(pos, map.map_size, map.boundary_mode) {
+                if is_obstacle(map.obstacle_buffer, map.map_size, pos) {
+                    sum += agent_stats.obstacle_attraction * weight;
+                } else {
+                    let pixel = map.pixel(pos);
+                    for i in 0..interactions.num_trails {
+                        sum += pixel.get_frac(i) * interactions.get(i).attraction * weight;
+                    }
+                }
+            }
+        }
+    }
+    if sum > agent_stats.avoidance_threshold {
+        None
+    } else {
+        Some(sum)
+    }
+}
+
+// How much of the pixel at `offset` a bilinear sensor kernel of `radius` covers, along one axis
+fn kernel_edge_weight(offset: i32, radius: i32, fraction: f32) -> f32 {
+    if offset == -radius {
+        1.0 - fraction
+    } else if offset == radius + 1 {
+        fraction
+    } else {
+        1.0
+    }
+}
+
+// The strongest sensor on each side, f32::MIN for sides without sensors
+#[derive(Copy, Clone)]
+pub struct SensorWeights {
+    pub left: f32,
+    pub forward: f32,
+    pub right: f32,
+    pub has_forward: bool,
+}
+
+/// Turn rate in radians per second, positive is to the left, for agents without saturated sensors
+pub fn steer(agent_stats: &AgentStats, weights: SensorWeights, random_steer_strength: f32, turn_speed: f32) -> f32 {
+    let SensorWeights { left, forward, right, has_forward } = weights;
+    match agent_stats.steering_model.decode() {
+        SteeringModel::Classic => {
+            // If center is stronger than edges, continue forward
+            if has_forward && forward > left && forward > right {
+                0.0
+            }
+            // If edges are stronger than center, pick a direction randomly
+            else if has_forward && left > forward && right > forward {
+                (random_steer_strength - 0.5) * 2.0 * turn_speed
+            }
+            // If there's a gradient in one direction, turn that way
+            else if right > left {
+                -(random_steer_strength * turn_speed)
+            } else if left > right {
+                random_steer_strength * turn_speed
+            } else {
+                0.0
+            }
+        }
+        SteeringModel::Jones => {
+            if has_forward && forward > left && forward > right {
+                0.0
+            } else if has_forward && left > forward && right > forward {
+                if random_steer_strength < 0.5 { -turn_speed } else { turn_speed }
+            } else if right > left {
+                -turn_speed
+            } else if left > right {
+                turn_speed
+            } else {
+                0.0
+            }
+        }
+        SteeringModel::Softmax => {
+            let max = f32::max(left, f32::max(forward, right));
+            let softmax_term = |weight: f32| if weight == f32::MIN { 0.0 } else { ((weight - max) * agent_stats.steering_gain).exp() };
+            let left_term = softmax_term(left);
+            let right_term = softmax_term(right);
+            let total = left_term + softmax_term(forward) + right_term;
+            if total > 0.0 {
+                (left_term - right_term) / total * turn_speed
+            } else {
+                0.0
+            }
+        }
+        SteeringModel::Multiplicative => {
+            let side_value = |weight: f32| if weight == f32::MIN { 0.0 } else { weight };
+            let difference = (side_value(left) - side_value(right)) * agent_stats.steering_gain;
+            random_steer_strength * difference.clamp(-1.0, 1.0) * turn_speed
+        }
+    }
+}
+
+// How much faster the agent moves and turns on the trails at its position
+fn kinesis(map: &mut TrailMap<'_>, interactions: InteractionRow, position: Vec2) -> (f32, f32) {
+    let mut speed_scale = 1.0;
+    let mut turn_scale = 1.0;
+    if let Some(position) = map_position(position.as_ivec2(), map.map_size, map.boundary_mode) {
+        let pixel = map.pixel(position);
+        for i in 0..interactions.num_trails {
+            let interaction = interactions.get(i);
+            if interaction.speed_coefficient != 0.0 || interaction.turn_coefficient != 0.0 {
+                let concentration = pixel.get_frac(i);
+                speed_scale += interaction.speed_coefficient * concentration;
+                turn_scale += interaction.turn_coefficient * concentration;
+            }
+        }
+    }
+    (f32::max(speed_scale, 0.0), f32::max(turn_scale, 0.0))
+}
+
+/// Deposits `amount` times the additions, into the pixel at `position` or, if the step splats,
+/// into the four pixels around it weighted by how close their centers are.
+pub fn process_pixel(map: &mut TrailMap<'_>, step: &AgentStep<'_>, agent: &mut Agent, position: Vec2, amount: f32) -> Bounds {
+    let sub_pixel = position;
+    let map_size = map.map_size;
+    if let Some(position) = map_position(sub_pixel.as_ivec2(), map_size, map.boundary_mode) {
+        if is_obstacle(map.obstacle_buffer, map_size, position) {
+            return Bounds::Obstacle;
+        }
+        if step.occupant != 0 {
+            let held_by = map.occupancy_buffer[(position.y * map_size.x + position.x) as usize];
+            if held_by != 0 && held_by != step.occupant {
+                return Bounds::Occupied;
+            }
+        }
+        let interactions = step.interactions;
+        for trail_index in 0..interactions.num_trails {
+            let interaction = interactions.get(trail_index);
+            let value_frac = map.pixel(position).get_frac(trail_index);
+            // Rate based conversions are rolled once per step in update_agent
+            if interaction.conversion_enabled != 0 && interaction.conversion_rate == 0.0 && value_frac > interaction.conversion_threshold && interaction.conversion < step.num_agent_types {
+                agent.agent_type = interaction.conversion;
+                agent.countdown = initial_countdown(&step.agent_stats_buffer[interaction.conversion as usize], step.random);
+            }
+            let addition = interaction.addition * amount;
+            if !step.splat {
+                deposit(map, position, trail_index, addition);
+            } else if addition != 0.0 {
+                let corner = sub_pixel - 0.5;
+                let base = corner.floor();
+                let fraction = corner - base;
+                let base = base.as_ivec2();
+                for offset_y in 0..=1 {
+                    for offset_x in 0..=1 {
+                        let weight_x = if offset_x == 0 { 1.0 - fraction.x } else { fraction.x };
+                        let weight_y = if offset_y == 0 { 1.0 - fraction.y } else { fraction.y };
+                        let weight = weight_x * weight_y;
+                        if weight == 0.0 {
+                            continue;
+                        }
+                        // The share of pixels outside the map or under obstacles is lost
+                        let splat_pos = map_position(base + ivec2(offset_x, offset_y), map_size, map.boundary_mode)
+                            .filter(|&splat_pos| !is_obstacle(map.obstacle_buffer, map_size, splat_pos));
+                        if let Some(splat_pos) = splat_pos {
+                            deposit(map, splat_pos, trail_index, addition * weight);
+                        }
+                    }
+                }
+            }
+        }
+        Bounds::InsideBounds
+    } else {
+        Bounds::OutsideBounds
+    }
+}
+
+/// Adds `addition` to a trail of the pixel at `position`, saturating at 1.0
+fn deposit(map: &mut TrailMap<'_>, position: UVec2, trail_index: usize, addition: f32) {
+    let trail_format = map.trail_format;
+    if matches!(map.deposition_mode, DepositionMode::Atomic) {
+        if addition != 0.0 {
+            let (int_index, shift) = trail_format.channel_location(trail_index);
+            let pixel_index = ((position.y * map.map_size.x + position.x) * trail_format.ints_per_pixel()) as usize + int_index;
+            deposit_atomic(&mut map.trail_buffer[pixel_index], trail_format, shift, addition);
+        }
+    } else {
+        let value_frac = map.pixel(position).get_frac(trail_index) + addition;
+        map.pixel(position).set_frac(trail_index, f32::min(value_frac, 1.0));
+    }
+}
+
+/// Adds `addition` to the channel at `shift` in `int`, saturating like the fast path in [`deposit`].
+/// Retries until no other invocation changed the int in between, so no deposit is lost.
+fn deposit_atomic(int: &mut u32, trail_format: TrailFormat, shift: u32, addition: f32) {
+    let mask = trail_format.mask();
+    let mut previous = *int;
+    loop {
+        let value_frac = trail_format.frac_from_int((previous >> shift) & mask) + addition;
+        let value = trail_format.int_from_frac(f32::min(value_frac, 1.0));
+        let updated = (previous & !(mask << shift)) | ((value & mask) << shift);
+        let current = atomic_compare_exchange(int, previous, updated);
+        if current == previous {
+            break;
+        }
+        previous = current;
+    }
+}
+
+/// The interactions of one agent type with every trail, a row of the interaction buffer
+#[derive(Copy, Clone)]
+pub struct InteractionRow<'buffer> {
+    interaction_buffer: &'buffer [TrailInteraction],
+    offset: usize,
+    pub num_trails: usize,
+}
+
+impl<'buffer> InteractionRow<'buffer> {
+    /// It is the callers responsibility to make sure agent_type is below the number of agent types
+    pub fn new(interaction_buffer: &'buffer [TrailInteraction], agent_type: u32, num_trails: u32) -> Self {
+        Self {
+            interaction_buffer,
+            offset: agent_type as usize * num_trails as usize,
+            num_trails: num_trails as usize,
+        }
+    }
+
+    pub fn get(&self, trail_index: usize) -> TrailInteraction {
+        self.interaction_buffer[self.offset + trail_index]
+    }
+}
+
+/// The buffers agents sense, deposit into and move over, with the settings of the map they are laid out by
+pub struct TrailMap<'buffer> {
+    pub trail_buffer: &'buffer mut [u32],
+    pub obstacle_buffer: &'buffer [u32],
+    pub occupancy_buffer: &'buffer mut [u32],
+    pub map_size: UVec2,
+    pub boundary_mode: BoundaryMode,
+    pub trail_format: TrailFormat,
+    pub deposition_mode: DepositionMode,
+}
+
+impl<'buffer> TrailMap<'buffer> {
+    pub fn new(constants: &ShaderConstants, trail_buffer: &'buffer mut [u32], obstacle_buffer: &'buffer [u32], occupancy_buffer: &'buffer mut [u32]) -> Self {
+        Self {
+            trail_buffer,
+            obstacle_buffer,
+            occupancy_buffer,
+            map_size: constants.map_size,
+            boundary_mode: constants.boundary_mode.decode(),
+            trail_format: constants.trail_format.decode(),
+            deposition_mode: constants.deposition_mode.decode(),
+        }
+    }
+
+    /// It is the callers responsibility to make sure position is within bounds
+    pub fn pixel(&mut self, position: UVec2) -> PixelView<'_> {
+        get_pixel(self.trail_buffer, self.map_size, self.trail_format, position)
+    }
+}
+
+/// What stays the same for every pixel an agent moves over in one step, see [`process_pixel`]
+#[derive(Copy, Clone)]
+pub struct AgentStep<'buffer> {
+    pub interactions: InteractionRow<'buffer>,
+    pub agent_stats_buffer: &'buffer [AgentStats],
+    pub num_agent_types: u32,
+    /// The agent's id in the occupancy buffer, or zero if it ignores occupancy
+    pub occupant: u32,
+    pub splat: bool,
+    pub random: u32,
+}
+
+/// Applies the agent's edge behavior after it ran into the edge of the map or an obstacle.
+/// `hit_x` and `hit_y` tell which axes were blocked.
+/// Returns false if the agent was removed.
+fn collide(agent: &mut Agent, agent_stats: &AgentStats, random: u32, hit_x: bool, hit_y: bool) -> bool {
+    match agent_stats.edge_behavior.decode() {
+        EdgeBehavior::RandomAngle => {
+            agent.angle = (random as f32 / u32::MAX as f32) * 2.0 * PI;
+        }
+        EdgeBehavior::Reflect => {
+            if hit_x {
+                agent.angle = PI - agent.angle;
+            }
+            if hit_y {
+                agent.angle = -agent.angle;
+            }
+        }
+        EdgeBehavior::Absorb => {
+            agent.agent_type = INACTIVE_AGENT_TYPE;
+            return false;
+        }
+    }
+    true
+}
+
+/// It is the callers responsibility to make sure position is within bounds
+pub fn is_obstacle(obstacle_buffer: &[u32], map_size: UVec2, position: UVec2) -> bool {
+    obstacle_buffer[(position.y * map_size.x + position.x) as usize] != 0
+}
+
+/// True if the position is outside the map or covered by an obstacle
+pub fn is_blocked(obstacle_buffer: &[u32], map_size: UVec2, boundary_mode: BoundaryMode, position: IVec2) -> bool {
+    match map_position(position, map_size, boundary_mode) {
+        Some(position) => is_obstacle(obstacle_buffer, map_size, position),
+        None => true,
+    }
+}
+
+/// It is the callers responsibility to make sure position is within bounds
+pub fn get_pixel(trail_buffer: &mut [u32], map_size: UVec2, trail_format: TrailFormat, position: UVec2) -> PixelView<'_> {
+    let pixel_index = (position.y as usize * map_size.x as usize + position.x as usize) * trail_format.ints_per_pixel() as usize;
+    PixelView::new(trail_buffer, pixel_index, trail_format)
+}
+
+pub fn is_inside_bounds(position: IVec2, map_size: UVec2) -> bool {
+    position.x >= 0 && position.x < map_size.x as i32 && position.y >= 0 && position.y < map_size.y as i32
+}
+
+pub fn is_inside_bounds_u(position: UVec2, map_size: UVec2) -> bool {
+    position.x < map_size.x && position.y < map_size.y
+}
+
+/// Resolves a pixel position according to the boundary mode.
+/// Returns None for positions outside the map, unless the map wraps around.
+pub fn map_position(position: IVec2, map_size: UVec2, boundary_mode: BoundaryMode) -> Option<UVec2> {
+    match boundary_mode {
+        BoundaryMode::Clamp => {
+            if is_inside_bounds(position, map_size) {
+                Some(position.as_uvec2())
+            } else {
+                None
+            }
+        }
+        BoundaryMode::Wrap => {
+            let size = map_size.as_ivec2();
+            let mut wrapped = ivec2(position.x % size.x, position.y % size.y);
+            if wrapped.x < 0 {
+                wrapped.x += size.x;
+            }
+            if wrapped.y < 0 {
+                wrapped.y += size.y;
+            }
+            Some(wrapped.as_uvec2())
+        }
+    }
+}
+
+/// Wraps a sub-pixel position onto the map if the map wraps around, otherwise returns it unchanged
+pub fn wrap_position(position: Vec2, map_size: UVec2, boundary_mode: BoundaryMode) -> Vec2 {
+    match boundary_mode {
+        BoundaryMode::Clamp => position,
+        BoundaryMode::Wrap => {
+            let size = map_size.as_vec2();
+            let mut wrapped = vec2(position.x % size.x, position.y % size.y);
+            if wrapped.x < 0.0 {
+                wrapped.x += size.x;
+            }
+            if wrapped.y < 0.0 {
+                wrapped.y += size.y;
+            }
+            wrapped
+        }
+    }
+}
+
+// diffuse_cs runs in square workgroups of this size, keep in sync with its threads attribute.
+// Small enough that the tile of the widest trail format fits into 16KB of workgroup memory.
+pub const DIFFUSE_GROUP_SIZE: u32 = 8;
+// The tile covers the workgroup plus the largest kernel and one pixel for bilinear sampling on every side
+pub const DIFFUSE_TILE_APRON: u32 = MAX_KERNEL_RADIUS + 1;
+pub const DIFFUSE_TILE_SIZE: u32 = DIFFUSE_GROUP_SIZE + 2 * DIFFUSE_TILE_APRON;
+pub const DIFFUSE_TILE_LEN: usize = (DIFFUSE_TILE_SIZE * DIFFUSE_TILE_SIZE * MAX_INTS_PER_PIXEL) as usize;
+
+/// The part of the trail map around a workgroup of `diffuse_cs`, copied into workgroup memory once,
+/// so the invocations don't each read their whole kernel from the storage buffer.
+/// Reads outside the tile, which advection and wrapping around the map can cause, fall back to the buffer.
+pub struct TrailTile<'tile> {
+    tile: &'tile mut [u32],
+    origin: IVec2,
+    trail_buffer: &'tile mut [u32],
+    obstacle_buffer: &'tile [u32],
+    map_size: UVec2,
+    boundary_mode: BoundaryMode,
+    trail_format: TrailFormat,
+}
+
+impl<'tile> TrailTile<'tile> {
+    pub fn new(tile: &'tile mut [u32], group_id: UVec2, trail_buffer: &'tile mut [u32], obstacle_buffer: &'tile [u32], constants: &ShaderConstants) -> Self {
+        Self {
+            tile,
+            origin: diffuse_tile_origin(group_id),
+            trail_buffer,
+            obstacle_buffer,
+            map_size: constants.map_size,
+            boundary_mode: constants.boundary_mode.decode(),
+            trail_format: constants.trail_format.decode(),
+        }
+    }
+
+    pub fn get_frac(&mut self, position: UVec2, channel_index: usize) -> f32 {
+        let local = position.as_ivec2() - self.origin;
+        let tile_size = DIFFUSE_TILE_SIZE as i32;
+        if local.x >= 0 && local.y >= 0 && local.x < tile_size && local.y < tile_size {
+            get_pixel(self.tile, uvec2(DIFFUSE_TILE_SIZE, DIFFUSE_TILE_SIZE), self.trail_format, local.as_uvec2()).get_frac(channel_index)
+        } else {
+            get_pixel(self.trail_buffer, self.map_size, self.trail_format, position).get_frac(channel_index)
+        }
+    }
+}
+
+fn diffuse_tile_origin(group_id: UVec2) -> IVec2 {
+    (group_id * DIFFUSE_GROUP_SIZE).as_ivec2() - DIFFUSE_TILE_APRON as i32
+}
+
+/// The share of the tile one invocation of `diffuse_cs` copies before the workgroup barrier.
+/// `local_index` is the flattened local invocation id.
+/// The tile is laid out like the trail buffer, so only the first part of it is used by the narrower formats.
+pub fn load_diffuse_tile(local_index: u32, group_id: UVec2, map_size: UVec2, trail_format: TrailFormat, trail_buffer: &[u32], tile: &mut [u32]) {
+    let origin = diffuse_tile_origin(group_id);
+    let ints_per_pixel = trail_format.ints_per_pixel();
+    let mut tile_index = local_index;
+    while tile_index < DIFFUSE_TILE_SIZE * DIFFUSE_TILE_SIZE {
+        let position = origin + ivec2((tile_index % DIFFUSE_TILE_SIZE) as i32, (tile_index / DIFFUSE_TILE_SIZE) as i32);
+        // Pixels outside the map are never read from the tile
+        if is_inside_bounds(position, map_size) {
+            let source = ((position.y as u32 * map_size.x + position.x as u32) * ints_per_pixel) as usize;
+            let target = (tile_index * ints_per_pixel) as usize;
+            let len = ints_per_pixel as usize;
+            tile[target..target + len].copy_from_slice(&trail_buffer[source..source + len]);
+        }
+        tile_index += DIFFUSE_GROUP_SIZE * DIFFUSE_GROUP_SIZE;
+    }
+}
+
+/// The buffers `diffuse_cs` only reads from, besides the trail map
+#[derive(Copy, Clone)]
+pub struct DiffusionBuffers<'buffer> {
+    pub trail_stats: &'buffer [TrailStats],
+    pub emitter_map: &'buffer [u32],
+    pub emitter_stats: &'buffer [EmitterStats],
+    pub reactions: &'buffer [Reaction],
+    pub flow_field: &'buffer [Vec2],
+}
+
+/// Diffusion and evaporation of a single pixel, as run by `diffuse_cs`.
+/// Reads from `input`, the tile of the intermediate buffer, and writes the result into `output_buffer`.
+pub fn diffuse_pixel(pos: UVec2, constants: &ShaderConstants, buffers: &DiffusionBuffers<'_>, input: &mut TrailTile<'_>, output_buffer: &mut [u32]) {
+    let DiffusionBuffers { trail_stats, emitter_map, emitter_stats, reactions, flow_field } = *buffers;
+    let map_size = constants.map_size;
+    let trail_format = constants.trail_format.decode();
+    if !is_inside_bounds_u(pos, map_size) {
+        return;
+    }
+    if is_obstacle(input.obstacle_buffer, map_size, pos) {
+        let mut output_pixel = get_pixel(output_buffer, map_size, trail_format, pos);
+        for channel_index in 0..constants.num_trails as usize {
+            output_pixel.set(channel_index, 0);
+        }
+        return;
+    }
+    // Semi-lagrangian advection, everything is read from where the flow carries it from
+    let source_pos = pos.as_vec2() - flow_at(pos.as_vec2(), constants, flow_field) * constants.time_step;
+    for (channel_index, stats) in trail_stats.iter().enumerate().take(constants.num_trails as usize) {
+        let evaporation_speed = stats.evaporation_speed;
+        let center_value = input.get_frac(pos, channel_index);
+        let mut value = sample_trail(input, channel_index, source_pos, center_value);
+
+        let kernel = &stats.kernel;
+        if !matches!(kernel.shape.decode(), KernelShape::None) {
+            let diffusion_speed = stats.diffusion_speed;
+            let blur_result = blur(input, channel_index, kernel, source_pos, center_value);
+            value = lerp(
+                value,
+                blur_result,
+                (diffusion_speed / 100.0) * constants.time_step,
+            );
+        }
+
+        let evaporation_this_tick = (evaporation_speed / 100.0) * constants.time_step;
+        let mut new_value = f32::max(0.0, value - evaporation_this_tick);
+        new_value += stats.feed_rate * (1.0 - new_value) * constants.time_step;
+        let mut output_pixel = get_pixel(output_buffer, map_size, trail_format, pos);
+        output_pixel.set_frac(channel_index, new_value);
+    }
+
+    for reaction in reactions.iter().take(constants.num_reactions as usize) {
+        react(get_pixel(output_buffer, map_size, trail_format, pos), reaction, constants.num_trails, constants.time_step);
+    }
+
+    let emitter = emitter_map[(pos.y * map_size.x + pos.x) as usize];
+    if emitter != 0 && emitter <= constants.num_emitters {
+        let emitter_stats = emitter_stats[emitter as usize - 1];
+        if emitter_stats.trail_index < constants.num_trails {
+            let mut output_pixel = get_pixel(output_buffer, map_size, trail_format, pos);
+            let value = output_pixel.get_frac(emitter_stats.trail_index as usize) + emitter_stats.rate * constants.time_step;
+            output_pixel.set_frac(emitter_stats.trail_index as usize, saturate(value));
+        }
+    }
+}
+
+/// Weighted average of the kernel around `position`.
+/// Samples outside the map count as zero, and obstacles mirror `center_value`, so nothing diffuses into them.
+fn blur(input: &mut TrailTile<'_>, channel_index: usize, kernel: &DiffusionKernel, position: Vec2, center_value: f32) -> f32 {
+    let shape = kernel.shape.decode();
+    let radius = kernel_radius(kernel) as i32;
+    let sigma = f32::max(kernel.sigma, 0.01);
+    // The gaussian is separable, so the exponentials are only needed along one axis
+    let mut gaussian = [0.0; 2 * MAX_KERNEL_RADIUS as usize + 1];
+    if matches!(shape, KernelShape::Gaussian) {
+        for offset in -radius..=radius {
+            gaussian[(offset + radius) as usize] = (-(offset * offset) as f32 / (2.0 * sigma * sigma)).exp();
+        }
+    }
+    let direction = kernel.direction.to_radians();
+    let axis = vec2(direction.cos(), direction.sin());
+    let sigma_across = f32::max(sigma * kernel.ratio, 0.01);
+
+    let mut sum = 0.0;
+    let mut total_weight = 0.0;
+    for offset_x in -radius..=radius {
+        for offset_y in -radius..=radius {
+            let offset = vec2(offset_x as f32, offset_y as f32);
+            let weight = match shape {
+                KernelShape::Gaussian => gaussian[(offset_x + radius) as usize] * gaussian[(offset_y + radius) as usize],
+                KernelShape::Anisotropic => {
+                    let along = offset.dot(axis);
+                    let across = axis.perp_dot(offset);
+                    (-(along * along) / (2.0 * sigma * sigma) - (across * across) / (2.0 * sigma_across * sigma_across)).exp()
+                }
+                _ => 1.0,
+            };
+            total_weight += weight;
+            sum += weight * sample_trail(input, channel_index, position + offset, center_value);
+        }
+    }
+    sum / total_weight
+}
+
+/// Radius of the kernel in pixels, at most MAX_KERNEL_RADIUS
+pub fn kernel_radius(kernel: &DiffusionKernel) -> u32 {
+    let radius = match kernel.shape.decode() {
+        KernelShape::None => 0,
+        KernelShape::Box => kernel.radius,
+        KernelShape::Gaussian => (3.0 * kernel.sigma).ceil() as u32,
+        KernelShape::Anisotropic => (3.0 * kernel.sigma * f32::max(1.0, kernel.ratio)).ceil() as u32,
+    };
+    u32::min(radius, MAX_KERNEL_RADIUS)
+}
+
+/// Bilinear sample of a trail channel, with pixel centers at whole numbers.
+/// Obstacles read as `obstacle_value` and pixels outside the map as zero.
+fn sample_trail(input: &mut TrailTile<'_>, channel_index: usize, position: Vec2, obstacle_value: f32) -> f32 {
+    let base = position.floor();
+    let fraction = position - base;
+    let base = base.as_ivec2();
+    let mut result = 0.0;
+    for offset_y in 0..=1 {
+        for offset_x in 0..=1 {
+            let weight_x = if offset_x == 0 { 1.0 - fraction.x } else { fraction.x };
+            let weight_y = if offset_y == 0 { 1.0 - fraction.y } else { fraction.y };
+            let weight = weight_x * weight_y;
+            // Skipping empty weights keeps whole pixel positions exact and cheap
+            if weight == 0.0 {
+                continue;
+            }
+            if let Some(sample_pos) = map_position(base + ivec2(offset_x, offset_y), input.map_size, input.boundary_mode) {
+                let value = if is_obstacle(input.obstacle_buffer, input.map_size, sample_pos) {
+                    obstacle_value
+                } else {
+                    input.get_frac(sample_pos, channel_index)
+                };
+                result += value * weight;
+            }
+        }
+    }
+    result
+}
+
+/// Velocity of the flow field at a position, in pixels per second
+pub fn flow_at(position: Vec2, constants: &ShaderConstants, flow_field: &[Vec2]) -> Vec2 {
+    let flow = &constants.flow;
+    let map_size = constants.map_size;
+    match flow.mode.decode() {
+        FlowMode::None => Vec2::ZERO,
+        FlowMode::Uniform => vec2(flow.direction.cos(), flow.direction.sin()) * flow.strength,
+        FlowMode::Vortex => {
+            let offset = position - flow.center * map_size.as_vec2();
+            vec2(-offset.y, offset.x).normalize_or_zero() * flow.strength
+        }
+        FlowMode::CurlNoise => {
+            // The curl of a scalar field has no divergence, so the trails swirl without bunching up
+            let noise_pos = position * flow.noise_scale;
+            let epsilon = 0.01;
+            let seed = hash(constants.seed);
+            let derivative_x = value_noise(noise_pos + vec2(epsilon, 0.0), seed) - value_noise(noise_pos - vec2(epsilon, 0.0), seed);
+            let derivative_y = value_noise(noise_pos + vec2(0.0, epsilon), seed) - value_noise(noise_pos - vec2(0.0, epsilon), seed);
+            vec2(derivative_y, -derivative_x) / (2.0 * epsilon) * flow.strength
+        }
+        FlowMode::Image => match map_position(position.as_ivec2(), map_size, constants.boundary_mode.decode()) {
+            Some(pixel) => flow_field[(pixel.y * map_size.x + pixel.x) as usize] * flow.strength,
+            None => Vec2::ZERO,
+        },
+    }
+}
+
+/// Smoothly interpolated random values between 0 and 1 at whole number positions
+fn value_noise(position: Vec2, seed: u32) -> f32 {
+    let base = position.floor();
+    let fraction = position - base;
+    let smooth = fraction * fraction * (3.0 - 2.0 * fraction);
+    let base = base.as_ivec2();
+    lerp(
+        lerp(noise_corner(base, seed), noise_corner(base + ivec2(1, 0), seed), smooth.x),
+        lerp(noise_corner(base + ivec2(0, 1), seed), noise_corner(base + ivec2(1, 1), seed), smooth.x),
+        smooth.y,
+    )
+}
+
+fn noise_corner(corner: IVec2, seed: u32) -> f32 {
+    hash(hash(seed ^ corner.x as u32).wrapping_add(corner.y as u32)) as f32 / u32::MAX as f32
+}
+
+fn react(mut pixel: PixelView, reaction: &Reaction, num_trails: u32, time_step: f32) {
+    if reaction.source >= num_trails {
+        return;
+    }
+    let source = pixel.get_frac(reaction.source as usize);
+    let mut amount = reaction.rate * source * time_step;
+    if reaction.catalyst < num_trails {
+        amount *= pixel.get_frac(reaction.catalyst as usize).pow(reaction.catalyst_exponent);
+    }
+    // Can't take more than there is
+    let amount = f32::min(amount, source);
+    pixel.set_frac(reaction.source as usize, source - amount);
+    if reaction.target < num_trails {
+        let target = pixel.get_frac(reaction.target as usize);
+        pixel.set_frac(reaction.target as usize, saturate(target + amount));
+    }
+}
+
+/// Applies the brush to a single pixel, as run by `mouse_cs`.
+/// Every invocation only writes its own pixel, so unlike the deposition of agents this can't race.
+pub fn brush_pixel(map_pos: UVec2, mouse_constants: &MouseConstants, trail_buffer: &mut [u32], obstacle_buffer: &mut [u32]) {
+    if mouse_constants.mouse_down != 0 {
+        if !is_inside_bounds_u(map_pos, mouse_constants.map_size) {
+            return;
+        }
+        let screen_pos = screen_from_map_pos(map_pos, mouse_constants.map_size, mouse_constants.screen_size);
+        let brush_size = (mouse_constants.brush_size / mouse_constants.map_size.x as f32) * mouse_constants.screen_size.x as f32;
+        if within_range(screen_pos.as_vec2(), mouse_constants.mouse_position, brush_size) {
+            let mut pixel = get_pixel(trail_buffer, mouse_constants.map_size, mouse_constants.trail_format.decode(), map_pos);
+            match mouse_constants.click_mode.decode() {
+                ClickMode::Disabled => {}
+                ClickMode::ShowMenu => {}
+                ClickMode::PaintTrail(trail_index) => {
+                    if trail_index < mouse_constants.num_trails {
+                        pixel.set_frac(trail_index as usize, 1.0);
+                    }
+                }
+                ClickMode::ResetTrail(trail_index) => {
+                    if trail_index < mouse_constants.num_trails {
+                        pixel.set_frac(trail_index as usize, 1.0);
+                    }
+                }
+                ClickMode::ResetAllTrails => {
+                    for i in 0..mouse_constants.num_trails as usize {
+                        pixel.set(i, 0x00);
+                    }
+                }
+                ClickMode::PaintObstacle => {
+                    obstacle_buffer[(map_pos.y * mouse_constants.map_size.x + map_pos.x) as usize] = 1;
+                }
+                ClickMode::EraseObstacle => {
+                    obstacle_buffer[(map_pos.y * mouse_constants.map_size.x + map_pos.x) as usize] = 0;
+                }
+                // Emitters are placed on the cpu
+                ClickMode::PlaceEmitter(_) => {}
+            }
+        }
+    }
+}
+
+/// Adds the agent to the count of its type, as run by `count_agents_cs`
+pub fn count_agent(agent: &Agent, num_agent_types: u32, statistics_buffer: &mut [u32]) {
+    if agent.agent_type < num_agent_types {
+        atomic_add(&mut statistics_buffer[STATISTICS_AGENT_OFFSET + agent.agent_type as usize], 1);
+    }
+}
+
+/// Adds the pixel to the trail totals, as run by `sum_trails_cs`
+pub fn sum_trail_pixel(map_pos: UVec2, constants: &ShaderConstants, trail_buffer: &mut [u32], statistics_buffer: &mut [u32]) {
+    if !is_inside_bounds_u(map_pos, constants.map_size) {
+        return;
+    }
+    let pixel = get_pixel(trail_buffer, constants.map_size, constants.trail_format.decode(), map_pos);
+    for (i, total) in statistics_buffer.iter_mut().enumerate().take(constants.num_trails as usize) {
+        let value = (pixel.get_frac(i) * TRAIL_TOTAL_SCALE + 0.5) as u32;
+        // Most pixels are empty, skip them to keep the contention down
+        if value != 0 {
+            atomic_add(total, value);
+        }
+    }
+}
+
+/// Number of bins of the agent sort for this map, the tiles are numbered along a Morton curve
+/// over a square power of two grid, plus a last bin for inactive agents
+pub fn num_sort_bins(map_size: UVec2) -> u32 {
+    let tiles = (map_size + SORT_TILE_SIZE - 1) / SORT_TILE_SIZE;
+    let side = tiles.x.max(tiles.y).next_power_of_two();
+    side * side + 1
+}
+
+/// Bin of the agent, neighbouring tiles mostly get nearby bins so agents close on the map end up close in the buffer
+pub fn sort_bin(agent: &Agent, map_size: UVec2) -> u32 {
+    if agent.agent_type == INACTIVE_AGENT_TYPE {
+        return num_sort_bins(map_size) - 1;
+    }
+    let tile = vec2(agent.x, agent.y).max(Vec2::ZERO).as_uvec2().min(map_size - 1) / SORT_TILE_SIZE;
+    spread_bits(tile.x) | (spread_bits(tile.y) << 1)
+}
+
+// Puts a zero bit in between every bit of the lower 16 bits
+fn spread_bits(mut value: u32) -> u32 {
+    value &= 0x0000ffff;
+    value = (value | (value << 8)) & 0x00ff00ff;
+    value = (value | (value << 4)) & 0x0f0f0f0f;
+    value = (value | (value << 2)) & 0x33333333;
+    value = (value | (value << 1)) & 0x55555555;
+    value
+}
+
+/// First pass of the agent sort, as run by `sort_count_cs`: counts the agents per bin
+/// and remembers the agent's place within its bin
+pub fn count_sort_bin(agent_index: usize, agents_buffer: &[Agent], map_size: UVec2, bins: &mut [u32], ranks: &mut [u32]) {
+    let bin = sort_bin(&agents_buffer[agent_index], map_size);
+    ranks[agent_index] = atomic_add(&mut bins[bin as usize], 1);
+}
+
+/// Second pass of the agent sort, as run by `sort_scan_cs` on a single invocation: turns the counts into offsets
+pub fn scan_sort_bins(bins: &mut [u32], num_bins: u32) {
+    let mut offset = 0;
+    for bin in bins.iter_mut().take(num_bins as usize) {
+        let count = *bin;
+        *bin = offset;
+        offset += count;
+    }
+}
+
+/// Last pass of the agent sort, as run by `sort_scatter_cs`: copies the agent to its sorted place
+pub fn scatter_agent(agent_index: usize, agents_buffer: &[Agent], map_size: UVec2, bins: &[u32], ranks: &[u32], sorted_buffer: &mut [Agent]) {
+    let agent = agents_buffer[agent_index];
+    let bin = sort_bin(&agent, map_size);
+    sorted_buffer[(bins[bin as usize] + ranks[agent_index]) as usize] = agent;
+}
+
+/// Returns the value `target` had before
+fn atomic_add(target: &mut u32, value: u32) -> u32 {
+    #[cfg(target_arch = "spirv")]
+    {
+        use spirv_std::memory::{Scope, Semantics};
+        unsafe {
+            spirv_std::arch::atomic_i_add::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }>(target, value)
+        }
+    }
+    #[cfg(not(target_arch = "spirv"))]
+    {
+        let previous = *target;
+        *target = previous.wrapping_add(value);
+        previous
+    }
+}
+
+pub fn screen_from_map_pos(map_pos: UVec2, map_size: UVec2, screen_size: UVec2) -> UVec2 {
+    ((map_pos.as_vec2() / map_size.as_vec2()) * screen_size.as_vec2()).as_uvec2()
+}
+pub fn map_from_screen_pos(screen_pos: IVec2, screen_size: UVec2, map_size: UVec2) -> IVec2 {
+    ((screen_pos.as_vec2() / screen_size.as_vec2()) * map_size.as_vec2()).as_ivec2()
+}
+
+pub fn within_range(first: Vec2, second: Vec2, distance: f32) -> bool {
+    let square_distance = (first.x - second.x).pow(2) + (first.y - second.y).pow(2);
+    square_distance < distance.pow(2)
+}
+
+pub fn lerp(from: f32, to: f32, interpolation: f32) -> f32 {
+    from + (to - from) * interpolation
+}
+
+pub fn hash(mut state: u32) -> u32 {
+    state ^= 2747636419u32;
+    state = state.wrapping_mul(2654435769u32);
+    state ^= state >> 16;
+    state = state.wrapping_mul(2654435769u32);
+    state ^= state >> 16;
+    state = state.wrapping_mul(2654435769u32);
+    state
+}